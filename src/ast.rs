@@ -1,42 +1,138 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use crate::{lox_type::LoxType, token::Token};
 
+/// Identifies an expression that refers to a variable, so the resolver's
+/// results can't be confused with another use of the same name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ExprId(usize);
+
+impl ExprId {
+    /// A new ID, unique across every program parsed by this process.
+    pub fn next() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Stmt {
     Block(Vec<Stmt>),
 
     Class {
         name: Token,
+        /// `var` declarations in the class body, evaluated for each new
+        /// instance before `init` runs.
+        fields: Vec<(Token, Expr)>,
         methods: Vec<Stmt>,
         opt_superclass: Option<Expr>,
     },
 
+    /// `var (a, b) = value;`, which unpacks a tuple into new variables, or
+    /// `var {a, b} = value;`, which reads the properties of an instance
+    /// into variables of the same names. `opening` tells them apart.
+    Destructure {
+        opening: Token,
+        names: Vec<Token>,
+        initializer: Expr,
+    },
+
+    /// `(a, b) = value;` or `{a, b} = value;`, which unpack like
+    /// `Destructure` into variables that already exist.
+    DestructureAssign {
+        opening: Token,
+        targets: Vec<(ExprId, Token)>,
+        value: Expr,
+    },
+
+    DoWhile {
+        keyword: Token,
+        body: Box<Stmt>,
+        condition: Expr,
+    },
+
     Expression(Expr),
 
+    /// A C-style `for` loop. The variables its initializers declare are
+    /// scoped to the loop, and the increment runs after each pass of `body`.
+    For {
+        keyword: Token,
+        initializers: Vec<Stmt>,
+        condition: Expr,
+        opt_increment: Option<Box<Expr>>,
+        body: Box<Stmt>,
+    },
+
+    /// `for (var name in value) body`, or with `value..end` a loop over the
+    /// integers from `value` up to but excluding `end`.
+    ForIn {
+        keyword: Token,
+        name: Token,
+        value: Expr,
+        opt_end: Option<Box<Expr>>,
+        body: Box<Stmt>,
+    },
+
     Function {
         name: Token,
         params: Vec<Token>,
+        /// A final `...name` parameter, which collects the arguments past
+        /// `params` into a tuple.
+        opt_rest: Option<Token>,
         body: Vec<Stmt>,
     },
 
     If {
+        keyword: Token,
         condition: Expr,
         then_branch: Box<Stmt>,
         opt_else_branch: Option<Box<Stmt>>,
     },
 
-    Print(Expr),
+    Import {
+        keyword: Token,
+        path: String,
+    },
+
+    Print {
+        keyword: Token,
+        value: Expr,
+    },
 
     Return {
         keyword: Token,
         value: Expr,
     },
 
+    Switch {
+        keyword: Token,
+        value: Expr,
+        cases: Vec<(Expr, Vec<Stmt>)>,
+        opt_default: Option<Vec<Stmt>>,
+    },
+
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+
+    Try {
+        keyword: Token,
+        body: Vec<Stmt>,
+        opt_catch: Option<(Token, Vec<Stmt>)>,
+        opt_finally: Option<Vec<Stmt>>,
+    },
+
     Var {
         name: Token,
         initializer: Expr,
+        /// Declared with `const`, so it can't be assigned to.
+        is_const: bool,
     },
 
     While {
+        keyword: Token,
         condition: Expr,
         body: Box<Stmt>,
     },
@@ -45,6 +141,7 @@ pub enum Stmt {
 #[derive(Clone, Debug)]
 pub enum Expr {
     Assign {
+        id: ExprId,
         name: Token,
         value: Box<Expr>,
     },
@@ -64,6 +161,8 @@ pub enum Expr {
     Get {
         object: Box<Expr>,
         name: Token,
+        /// Written `?.`: a nil object ends the optional chain around it.
+        is_optional: bool,
     },
 
     Grouping(Box<Expr>),
@@ -76,34 +175,360 @@ pub enum Expr {
         right: Box<Expr>,
     },
 
+    /// A chain of gets and calls containing a `?.`. When a `?.` finds nil,
+    /// the rest of the chain is skipped and the whole chain is nil.
+    OptionalChain(Box<Expr>),
+
     Set {
         object: Box<Expr>,
         name: Token,
         value: Box<Expr>,
     },
 
+    /// `...value` in an argument list, which passes the values of a tuple as
+    /// separate arguments.
+    Spread {
+        ellipsis: Token,
+        value: Box<Expr>,
+    },
+
     Super {
+        id: ExprId,
         keyword: Token,
         method: Token,
     },
 
-    This(Token),
+    This {
+        id: ExprId,
+        keyword: Token,
+    },
+
+    /// Values separated by commas after `return`.
+    Tuple(Vec<Expr>),
 
     Unary {
         operator: Token,
         right: Box<Expr>,
     },
 
-    Variable(Token),
+    Variable {
+        id: ExprId,
+        name: Token,
+    },
+}
+
+/// Walks statements. Implement it for an analysis pass and call
+/// `Stmt::accept` to dispatch on each node.
+pub trait StmtVisitor<R> {
+    fn visit_block(&mut self, stmts: &[Stmt]) -> R;
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        fields: &[(Token, Expr)],
+        methods: &[Stmt],
+        opt_superclass: Option<&Expr>,
+    ) -> R;
+
+    fn visit_destructure(&mut self, opening: &Token, names: &[Token], initializer: &Expr) -> R;
+
+    fn visit_destructure_assign(
+        &mut self,
+        opening: &Token,
+        targets: &[(ExprId, Token)],
+        value: &Expr,
+    ) -> R;
+
+    fn visit_do_while(&mut self, keyword: &Token, body: &Stmt, condition: &Expr) -> R;
+
+    fn visit_expression(&mut self, expr: &Expr) -> R;
+
+    fn visit_for(
+        &mut self,
+        keyword: &Token,
+        initializers: &[Stmt],
+        condition: &Expr,
+        opt_increment: Option<&Expr>,
+        body: &Stmt,
+    ) -> R;
+
+    fn visit_for_in(
+        &mut self,
+        keyword: &Token,
+        name: &Token,
+        value: &Expr,
+        opt_end: Option<&Expr>,
+        body: &Stmt,
+    ) -> R;
+
+    fn visit_function(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        opt_rest: Option<&Token>,
+        body: &[Stmt],
+    ) -> R;
+
+    fn visit_if(
+        &mut self,
+        keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        opt_else_branch: Option<&Stmt>,
+    ) -> R;
+
+    fn visit_import(&mut self, keyword: &Token, path: &str) -> R;
+
+    fn visit_print(&mut self, keyword: &Token, value: &Expr) -> R;
+
+    fn visit_return(&mut self, keyword: &Token, value: &Expr) -> R;
+
+    fn visit_switch(
+        &mut self,
+        keyword: &Token,
+        value: &Expr,
+        cases: &[(Expr, Vec<Stmt>)],
+        opt_default: Option<&[Stmt]>,
+    ) -> R;
+
+    fn visit_throw(&mut self, keyword: &Token, value: &Expr) -> R;
+
+    fn visit_try(
+        &mut self,
+        keyword: &Token,
+        body: &[Stmt],
+        opt_catch: Option<(&Token, &[Stmt])>,
+        opt_finally: Option<&[Stmt]>,
+    ) -> R;
+
+    fn visit_var(&mut self, name: &Token, initializer: &Expr, is_const: bool) -> R;
+
+    fn visit_while(&mut self, keyword: &Token, condition: &Expr, body: &Stmt) -> R;
+}
+
+/// Walks expressions. Implement it for an analysis pass and call
+/// `Expr::accept` to dispatch on each node.
+pub trait ExprVisitor<R> {
+    fn visit_assign(&mut self, id: ExprId, name: &Token, value: &Expr) -> R;
+
+    fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> R;
+
+    fn visit_call(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> R;
+
+    fn visit_get(&mut self, object: &Expr, name: &Token, is_optional: bool) -> R;
+
+    fn visit_grouping(&mut self, expr: &Expr) -> R;
+
+    fn visit_literal(&mut self, value: &LoxType) -> R;
+
+    fn visit_logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> R;
+
+    fn visit_optional_chain(&mut self, chain: &Expr) -> R;
+
+    fn visit_set(&mut self, object: &Expr, name: &Token, value: &Expr) -> R;
+
+    fn visit_spread(&mut self, ellipsis: &Token, value: &Expr) -> R;
+
+    fn visit_super(&mut self, id: ExprId, keyword: &Token, method: &Token) -> R;
+
+    fn visit_this(&mut self, id: ExprId, keyword: &Token) -> R;
+
+    fn visit_tuple(&mut self, elements: &[Expr]) -> R;
+
+    fn visit_unary(&mut self, operator: &Token, right: &Expr) -> R;
+
+    fn visit_variable(&mut self, id: ExprId, name: &Token) -> R;
+}
+
+impl Stmt {
+    pub fn accept<R>(&self, visitor: &mut dyn StmtVisitor<R>) -> R {
+        match self {
+            Stmt::Block(stmts) => visitor.visit_block(stmts),
+            Stmt::Class {
+                name,
+                fields,
+                methods,
+                opt_superclass,
+            } => visitor.visit_class(name, fields, methods, opt_superclass.as_ref()),
+            Stmt::Destructure {
+                opening,
+                names,
+                initializer,
+            } => visitor.visit_destructure(opening, names, initializer),
+            Stmt::DestructureAssign {
+                opening,
+                targets,
+                value,
+            } => visitor.visit_destructure_assign(opening, targets, value),
+            Stmt::DoWhile {
+                keyword,
+                body,
+                condition,
+            } => visitor.visit_do_while(keyword, body, condition),
+            Stmt::Expression(expr) => visitor.visit_expression(expr),
+            Stmt::For {
+                keyword,
+                initializers,
+                condition,
+                opt_increment,
+                body,
+            } => visitor.visit_for(
+                keyword,
+                initializers,
+                condition,
+                opt_increment.as_deref(),
+                body,
+            ),
+            Stmt::ForIn {
+                keyword,
+                name,
+                value,
+                opt_end,
+                body,
+            } => visitor.visit_for_in(keyword, name, value, opt_end.as_deref(), body),
+            Stmt::Function {
+                name,
+                params,
+                opt_rest,
+                body,
+            } => visitor.visit_function(name, params, opt_rest.as_ref(), body),
+            Stmt::If {
+                keyword,
+                condition,
+                then_branch,
+                opt_else_branch,
+            } => visitor.visit_if(keyword, condition, then_branch, opt_else_branch.as_deref()),
+            Stmt::Import { keyword, path } => visitor.visit_import(keyword, path),
+            Stmt::Print { keyword, value } => visitor.visit_print(keyword, value),
+            Stmt::Return { keyword, value } => visitor.visit_return(keyword, value),
+            Stmt::Switch {
+                keyword,
+                value,
+                cases,
+                opt_default,
+            } => visitor.visit_switch(keyword, value, cases, opt_default.as_deref()),
+            Stmt::Throw { keyword, value } => visitor.visit_throw(keyword, value),
+            Stmt::Try {
+                keyword,
+                body,
+                opt_catch,
+                opt_finally,
+            } => visitor.visit_try(
+                keyword,
+                body,
+                opt_catch
+                    .as_ref()
+                    .map(|(name, catch_body)| (name, catch_body.as_slice())),
+                opt_finally.as_deref(),
+            ),
+            Stmt::Var {
+                name,
+                initializer,
+                is_const,
+            } => visitor.visit_var(name, initializer, *is_const),
+            Stmt::While {
+                keyword,
+                condition,
+                body,
+            } => visitor.visit_while(keyword, condition, body),
+        }
+    }
+
+    /// The source line the statement starts on, if it carries one.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::Block(stmts) => stmts.iter().find_map(Stmt::line),
+            Stmt::Class { name, .. } => Some(name.line),
+            Stmt::Destructure { opening, .. } | Stmt::DestructureAssign { opening, .. } => {
+                Some(opening.line)
+            }
+            Stmt::DoWhile { keyword, .. } => Some(keyword.line),
+            Stmt::Expression(expr) => expr.line(),
+            Stmt::For { keyword, .. } => Some(keyword.line),
+            Stmt::ForIn { keyword, .. } => Some(keyword.line),
+            Stmt::Function { name, .. } => Some(name.line),
+            Stmt::If { keyword, .. } => Some(keyword.line),
+            Stmt::Import { keyword, .. } => Some(keyword.line),
+            Stmt::Print { keyword, .. } => Some(keyword.line),
+            Stmt::Return { keyword, .. } => Some(keyword.line),
+            Stmt::Switch { keyword, .. } => Some(keyword.line),
+            Stmt::Throw { keyword, .. } => Some(keyword.line),
+            Stmt::Try { keyword, .. } => Some(keyword.line),
+            Stmt::Var { name, .. } => Some(name.line),
+            Stmt::While { keyword, .. } => Some(keyword.line),
+        }
+    }
 }
 
 impl Expr {
-    pub fn is_nil(&self) -> bool {
-        use Expr::*;
+    pub fn accept<R>(&self, visitor: &mut dyn ExprVisitor<R>) -> R {
+        match self {
+            Expr::Assign { id, name, value } => visitor.visit_assign(*id, name, value),
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => visitor.visit_binary(left, operator, right),
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => visitor.visit_call(callee, paren, arguments),
+            Expr::Get {
+                object,
+                name,
+                is_optional,
+            } => visitor.visit_get(object, name, *is_optional),
+            Expr::Grouping(expr) => visitor.visit_grouping(expr),
+            Expr::Literal(value) => visitor.visit_literal(value),
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => visitor.visit_logical(left, operator, right),
+            Expr::OptionalChain(chain) => visitor.visit_optional_chain(chain),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => visitor.visit_set(object, name, value),
+            Expr::Spread { ellipsis, value } => visitor.visit_spread(ellipsis, value),
+            Expr::Super {
+                id,
+                keyword,
+                method,
+            } => visitor.visit_super(*id, keyword, method),
+            Expr::This { id, keyword } => visitor.visit_this(*id, keyword),
+            Expr::Tuple(elements) => visitor.visit_tuple(elements),
+            Expr::Unary { operator, right } => visitor.visit_unary(operator, right),
+            Expr::Variable { id, name } => visitor.visit_variable(*id, name),
+        }
+    }
 
+    /// The source line of the first token in the expression. Literals don't
+    /// keep their token, so a bare literal has no line.
+    pub fn line(&self) -> Option<usize> {
         match self {
-            Literal(LoxType::Nil) => true,
-            _ => false,
+            Expr::Assign { name, .. } => Some(name.line),
+            Expr::Binary { left, operator, .. } | Expr::Logical { left, operator, .. } => {
+                left.line().or(Some(operator.line))
+            }
+            Expr::Call { callee, paren, .. } => callee.line().or(Some(paren.line)),
+            Expr::Get { object, name, .. } | Expr::Set { object, name, .. } => {
+                object.line().or(Some(name.line))
+            }
+            Expr::Grouping(expr) | Expr::OptionalChain(expr) => expr.line(),
+            Expr::Literal(_) => None,
+            Expr::Spread { ellipsis, .. } => Some(ellipsis.line),
+            Expr::Super { keyword, .. } => Some(keyword.line),
+            Expr::This { keyword, .. } => Some(keyword.line),
+            Expr::Tuple(elements) => elements.iter().find_map(Expr::line),
+            Expr::Unary { operator, .. } => Some(operator.line),
+            Expr::Variable { name, .. } => Some(name.line),
         }
     }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Expr::Literal(LoxType::Nil))
+    }
 }
@@ -0,0 +1,862 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    convert::{TryFrom, TryInto},
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ast::{Expr, ExprId, Stmt},
+    interner::Interner,
+    lox_type::LoxType,
+    token::Token,
+    token_type::TokenType,
+};
+
+const MAGIC: &[u8] = b"RLOXAST";
+
+/// Written after `MAGIC`. Bump it whenever the encoding, `Stmt`, `Expr` or
+/// `TokenType` changes, so caches from other builds are misses rather than
+/// misread.
+const FORMAT_VERSION: u8 = 3;
+
+/// Loads the statements cached for the script at `path`, if the cache was
+/// written for this exact source by this version of rlox. Anything else,
+/// including a missing or damaged cache, is a miss.
+pub fn load(path: &Path, src: &str, compat: bool) -> Option<Vec<Stmt>> {
+    let bytes = fs::read(cache_path(path)).ok()?;
+
+    let (&version, bytes) = bytes.strip_prefix(MAGIC)?.split_first()?;
+
+    if version != FORMAT_VERSION {
+        return None;
+    }
+
+    let mut decoder = Decoder {
+        bytes,
+        interner: Interner::default(),
+    };
+
+    if decoder.u64()? != source_hash(src, compat) {
+        return None;
+    }
+
+    let stmts = decoder.stmts()?;
+
+    if decoder.bytes.is_empty() {
+        Some(stmts)
+    } else {
+        None
+    }
+}
+
+/// Caches the statements parsed from the script at `path`. Failing to write
+/// the cache isn't an error; the next run just parses the script again.
+pub fn store(path: &Path, src: &str, compat: bool, stmts: &[Stmt]) {
+    let mut encoder = Encoder {
+        bytes: [MAGIC, &[FORMAT_VERSION]].concat(),
+    };
+
+    encoder.u64(source_hash(src, compat));
+
+    if encoder.stmts(stmts).is_some() {
+        let _ = fs::write(cache_path(path), encoder.bytes);
+    }
+}
+
+/// `script.lox` caches to `script.lox.astcache` next to it.
+fn cache_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+
+    file_name.push(".astcache");
+
+    path.with_file_name(file_name)
+}
+
+fn source_hash(src: &str, compat: bool) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    compat.hash(&mut hasher);
+    src.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+/// Writes nodes as tagged little-endian fields. Returns `None` for anything
+/// that can't appear in a parsed program, such as a literal function.
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, n: u8) {
+        self.bytes.push(n);
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn usize(&mut self, n: usize) {
+        self.u64(n as u64);
+    }
+
+    fn bool(&mut self, b: bool) {
+        self.u8(b as u8);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> Option<()> {
+        self.usize(stmts.len());
+
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+
+        Some(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Option<()> {
+        match stmt {
+            Stmt::Block(stmts) => {
+                self.u8(0);
+                self.stmts(stmts)?;
+            }
+            Stmt::Class {
+                name,
+                fields,
+                methods,
+                opt_superclass,
+            } => {
+                self.u8(1);
+                self.token(name)?;
+                self.usize(fields.len());
+
+                for (field_name, value) in fields {
+                    self.token(field_name)?;
+                    self.expr(value)?;
+                }
+
+                self.stmts(methods)?;
+                self.opt_expr(opt_superclass.as_ref())?;
+            }
+            Stmt::DoWhile {
+                keyword,
+                body,
+                condition,
+            } => {
+                self.u8(2);
+                self.token(keyword)?;
+                self.stmt(body)?;
+                self.expr(condition)?;
+            }
+            Stmt::Expression(expr) => {
+                self.u8(3);
+                self.expr(expr)?;
+            }
+            Stmt::For {
+                keyword,
+                initializers,
+                condition,
+                opt_increment,
+                body,
+            } => {
+                self.u8(4);
+                self.token(keyword)?;
+                self.stmts(initializers)?;
+                self.expr(condition)?;
+                self.opt_expr(opt_increment.as_deref())?;
+                self.stmt(body)?;
+            }
+            Stmt::ForIn {
+                keyword,
+                name,
+                value,
+                opt_end,
+                body,
+            } => {
+                self.u8(5);
+                self.token(keyword)?;
+                self.token(name)?;
+                self.expr(value)?;
+                self.opt_expr(opt_end.as_deref())?;
+                self.stmt(body)?;
+            }
+            Stmt::Function {
+                name,
+                params,
+                opt_rest,
+                body,
+            } => {
+                self.u8(6);
+                self.token(name)?;
+                self.tokens(params)?;
+                self.opt_token(opt_rest.as_ref())?;
+                self.stmts(body)?;
+            }
+            Stmt::If {
+                keyword,
+                condition,
+                then_branch,
+                opt_else_branch,
+            } => {
+                self.u8(7);
+                self.token(keyword)?;
+                self.expr(condition)?;
+                self.stmt(then_branch)?;
+
+                match opt_else_branch {
+                    Some(else_branch) => {
+                        self.bool(true);
+                        self.stmt(else_branch)?;
+                    }
+                    None => self.bool(false),
+                }
+            }
+            Stmt::Import { keyword, path } => {
+                self.u8(8);
+                self.token(keyword)?;
+                self.str(path);
+            }
+            Stmt::Print { keyword, value } => {
+                self.u8(9);
+                self.token(keyword)?;
+                self.expr(value)?;
+            }
+            Stmt::Return { keyword, value } => {
+                self.u8(10);
+                self.token(keyword)?;
+                self.expr(value)?;
+            }
+            Stmt::Switch {
+                keyword,
+                value,
+                cases,
+                opt_default,
+            } => {
+                self.u8(11);
+                self.token(keyword)?;
+                self.expr(value)?;
+                self.usize(cases.len());
+
+                for (case_value, body) in cases {
+                    self.expr(case_value)?;
+                    self.stmts(body)?;
+                }
+
+                self.opt_stmts(opt_default.as_deref())?;
+            }
+            Stmt::Throw { keyword, value } => {
+                self.u8(12);
+                self.token(keyword)?;
+                self.expr(value)?;
+            }
+            Stmt::Try {
+                keyword,
+                body,
+                opt_catch,
+                opt_finally,
+            } => {
+                self.u8(13);
+                self.token(keyword)?;
+                self.stmts(body)?;
+
+                match opt_catch {
+                    Some((name, catch_body)) => {
+                        self.bool(true);
+                        self.token(name)?;
+                        self.stmts(catch_body)?;
+                    }
+                    None => self.bool(false),
+                }
+
+                self.opt_stmts(opt_finally.as_deref())?;
+            }
+            Stmt::Var {
+                name,
+                initializer,
+                is_const,
+            } => {
+                self.u8(14);
+                self.token(name)?;
+                self.expr(initializer)?;
+                self.bool(*is_const);
+            }
+            Stmt::While {
+                keyword,
+                condition,
+                body,
+            } => {
+                self.u8(15);
+                self.token(keyword)?;
+                self.expr(condition)?;
+                self.stmt(body)?;
+            }
+            Stmt::Destructure {
+                opening,
+                names,
+                initializer,
+            } => {
+                self.u8(16);
+                self.token(opening)?;
+                self.tokens(names)?;
+                self.expr(initializer)?;
+            }
+            Stmt::DestructureAssign {
+                opening,
+                targets,
+                value,
+            } => {
+                self.u8(17);
+                self.token(opening)?;
+                self.usize(targets.len());
+
+                for (_, name) in targets {
+                    self.token(name)?;
+                }
+
+                self.expr(value)?;
+            }
+        }
+
+        Some(())
+    }
+
+    fn opt_stmts(&mut self, opt_stmts: Option<&[Stmt]>) -> Option<()> {
+        match opt_stmts {
+            Some(stmts) => {
+                self.bool(true);
+                self.stmts(stmts)
+            }
+            None => {
+                self.bool(false);
+                Some(())
+            }
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> Option<()> {
+        self.usize(exprs.len());
+
+        for expr in exprs {
+            self.expr(expr)?;
+        }
+
+        Some(())
+    }
+
+    /// Expression IDs aren't written; loading hands out new ones so they
+    /// can't clash with expressions parsed by this process.
+    fn expr(&mut self, expr: &Expr) -> Option<()> {
+        match expr {
+            Expr::Assign { name, value, .. } => {
+                self.u8(0);
+                self.token(name)?;
+                self.expr(value)?;
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.u8(1);
+                self.expr(left)?;
+                self.token(operator)?;
+                self.expr(right)?;
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                self.u8(2);
+                self.expr(callee)?;
+                self.token(paren)?;
+                self.exprs(arguments)?;
+            }
+            Expr::Get {
+                object,
+                name,
+                is_optional,
+            } => {
+                self.u8(3);
+                self.expr(object)?;
+                self.token(name)?;
+                self.bool(*is_optional);
+            }
+            Expr::Grouping(expr) => {
+                self.u8(4);
+                self.expr(expr)?;
+            }
+            Expr::Literal(value) => {
+                self.u8(5);
+                self.literal(value)?;
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.u8(6);
+                self.expr(left)?;
+                self.token(operator)?;
+                self.expr(right)?;
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.u8(7);
+                self.expr(object)?;
+                self.token(name)?;
+                self.expr(value)?;
+            }
+            Expr::Super {
+                keyword, method, ..
+            } => {
+                self.u8(8);
+                self.token(keyword)?;
+                self.token(method)?;
+            }
+            Expr::This { keyword, .. } => {
+                self.u8(9);
+                self.token(keyword)?;
+            }
+            Expr::Unary { operator, right } => {
+                self.u8(10);
+                self.token(operator)?;
+                self.expr(right)?;
+            }
+            Expr::Variable { name, .. } => {
+                self.u8(11);
+                self.token(name)?;
+            }
+            Expr::Tuple(elements) => {
+                self.u8(12);
+                self.exprs(elements)?;
+            }
+            Expr::Spread { ellipsis, value } => {
+                self.u8(13);
+                self.token(ellipsis)?;
+                self.expr(value)?;
+            }
+            Expr::OptionalChain(chain) => {
+                self.u8(14);
+                self.expr(chain)?;
+            }
+        }
+
+        Some(())
+    }
+
+    fn opt_token(&mut self, opt_token: Option<&Token>) -> Option<()> {
+        match opt_token {
+            Some(token) => {
+                self.bool(true);
+                self.token(token)
+            }
+            None => {
+                self.bool(false);
+                Some(())
+            }
+        }
+    }
+
+    fn opt_expr(&mut self, opt_expr: Option<&Expr>) -> Option<()> {
+        match opt_expr {
+            Some(expr) => {
+                self.bool(true);
+                self.expr(expr)
+            }
+            None => {
+                self.bool(false);
+                Some(())
+            }
+        }
+    }
+
+    fn literal(&mut self, value: &LoxType) -> Option<()> {
+        match value {
+            LoxType::Boolean(b) => {
+                self.u8(0);
+                self.bool(*b);
+            }
+            LoxType::Integer(n) => {
+                self.u8(1);
+                self.u64(*n as u64);
+            }
+            LoxType::Nil => self.u8(2),
+            LoxType::Number(n) => {
+                self.u8(3);
+                self.u64(n.to_bits());
+            }
+            LoxType::String(s) => {
+                self.u8(4);
+                self.str(s);
+            }
+            LoxType::Uninitialized => self.u8(5),
+            LoxType::Callable(_) | LoxType::Class(_) | LoxType::Instance(_) | LoxType::Tuple(_) => {
+                return None
+            }
+        }
+
+        Some(())
+    }
+
+    fn tokens(&mut self, tokens: &[Token]) -> Option<()> {
+        self.usize(tokens.len());
+
+        for token in tokens {
+            self.token(token)?;
+        }
+
+        Some(())
+    }
+
+    /// Comments are left out; nothing downstream of the parser reads them.
+    fn token(&mut self, token: &Token) -> Option<()> {
+        let token_type = TokenType::ALL
+            .iter()
+            .position(|token_type| *token_type == token.token_type)?;
+
+        self.usize(token_type);
+        self.str(&token.lexeme);
+
+        match token.literal {
+            Some(ref literal) => {
+                self.bool(true);
+                self.literal(literal)?;
+            }
+            None => self.bool(false),
+        }
+
+        self.usize(token.line);
+        self.usize(token.column);
+
+        Some(())
+    }
+}
+
+/// Reads back what `Encoder` wrote, returning `None` at the first thing
+/// that doesn't fit.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    interner: Interner,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.bytes.len() {
+            return None;
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+
+        self.bytes = rest;
+
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        usize::try_from(self.u64()?).ok()
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    fn str(&mut self) -> Option<&'a str> {
+        let len = self.usize()?;
+
+        std::str::from_utf8(self.take(len)?).ok()
+    }
+
+    fn stmts(&mut self) -> Option<Vec<Stmt>> {
+        let len = self.usize()?;
+
+        (0..len).map(|_| self.stmt()).collect()
+    }
+
+    fn opt_stmts(&mut self) -> Option<Option<Vec<Stmt>>> {
+        if self.bool()? {
+            Some(Some(self.stmts()?))
+        } else {
+            Some(None)
+        }
+    }
+
+    fn boxed_stmt(&mut self) -> Option<Box<Stmt>> {
+        Some(Box::new(self.stmt()?))
+    }
+
+    fn stmt(&mut self) -> Option<Stmt> {
+        let stmt = match self.u8()? {
+            0 => Stmt::Block(self.stmts()?),
+            1 => {
+                let name = self.token()?;
+                let len = self.usize()?;
+                let fields = (0..len)
+                    .map(|_| Some((self.token()?, self.expr()?)))
+                    .collect::<Option<_>>()?;
+
+                Stmt::Class {
+                    name,
+                    fields,
+                    methods: self.stmts()?,
+                    opt_superclass: self.opt_expr()?,
+                }
+            }
+            2 => Stmt::DoWhile {
+                keyword: self.token()?,
+                body: self.boxed_stmt()?,
+                condition: self.expr()?,
+            },
+            3 => Stmt::Expression(self.expr()?),
+            4 => Stmt::For {
+                keyword: self.token()?,
+                initializers: self.stmts()?,
+                condition: self.expr()?,
+                opt_increment: self.opt_expr()?.map(Box::new),
+                body: self.boxed_stmt()?,
+            },
+            5 => Stmt::ForIn {
+                keyword: self.token()?,
+                name: self.token()?,
+                value: self.expr()?,
+                opt_end: self.opt_expr()?.map(Box::new),
+                body: self.boxed_stmt()?,
+            },
+            6 => Stmt::Function {
+                name: self.token()?,
+                params: self.tokens()?,
+                opt_rest: self.opt_token()?,
+                body: self.stmts()?,
+            },
+            7 => Stmt::If {
+                keyword: self.token()?,
+                condition: self.expr()?,
+                then_branch: self.boxed_stmt()?,
+                opt_else_branch: if self.bool()? {
+                    Some(self.boxed_stmt()?)
+                } else {
+                    None
+                },
+            },
+            8 => Stmt::Import {
+                keyword: self.token()?,
+                path: self.str()?.to_string(),
+            },
+            9 => Stmt::Print {
+                keyword: self.token()?,
+                value: self.expr()?,
+            },
+            10 => Stmt::Return {
+                keyword: self.token()?,
+                value: self.expr()?,
+            },
+            11 => {
+                let keyword = self.token()?;
+                let value = self.expr()?;
+                let len = self.usize()?;
+                let cases = (0..len)
+                    .map(|_| Some((self.expr()?, self.stmts()?)))
+                    .collect::<Option<_>>()?;
+
+                Stmt::Switch {
+                    keyword,
+                    value,
+                    cases,
+                    opt_default: self.opt_stmts()?,
+                }
+            }
+            12 => Stmt::Throw {
+                keyword: self.token()?,
+                value: self.expr()?,
+            },
+            13 => Stmt::Try {
+                keyword: self.token()?,
+                body: self.stmts()?,
+                opt_catch: if self.bool()? {
+                    Some((self.token()?, self.stmts()?))
+                } else {
+                    None
+                },
+                opt_finally: self.opt_stmts()?,
+            },
+            14 => Stmt::Var {
+                name: self.token()?,
+                initializer: self.expr()?,
+                is_const: self.bool()?,
+            },
+            15 => Stmt::While {
+                keyword: self.token()?,
+                condition: self.expr()?,
+                body: self.boxed_stmt()?,
+            },
+            16 => Stmt::Destructure {
+                opening: self.token()?,
+                names: self.tokens()?,
+                initializer: self.expr()?,
+            },
+            17 => Stmt::DestructureAssign {
+                opening: self.token()?,
+                targets: self
+                    .tokens()?
+                    .into_iter()
+                    .map(|name| (ExprId::next(), name))
+                    .collect(),
+                value: self.expr()?,
+            },
+            _ => return None,
+        };
+
+        Some(stmt)
+    }
+
+    fn exprs(&mut self) -> Option<Vec<Expr>> {
+        let len = self.usize()?;
+
+        (0..len).map(|_| self.expr()).collect()
+    }
+
+    fn boxed_expr(&mut self) -> Option<Box<Expr>> {
+        Some(Box::new(self.expr()?))
+    }
+
+    fn expr(&mut self) -> Option<Expr> {
+        let expr = match self.u8()? {
+            0 => Expr::Assign {
+                id: ExprId::next(),
+                name: self.token()?,
+                value: self.boxed_expr()?,
+            },
+            1 => Expr::Binary {
+                left: self.boxed_expr()?,
+                operator: self.token()?,
+                right: self.boxed_expr()?,
+            },
+            2 => Expr::Call {
+                callee: self.boxed_expr()?,
+                paren: self.token()?,
+                arguments: self.exprs()?,
+            },
+            3 => Expr::Get {
+                object: self.boxed_expr()?,
+                name: self.token()?,
+                is_optional: self.bool()?,
+            },
+            4 => Expr::Grouping(self.boxed_expr()?),
+            5 => Expr::Literal(self.literal()?),
+            6 => Expr::Logical {
+                left: self.boxed_expr()?,
+                operator: self.token()?,
+                right: self.boxed_expr()?,
+            },
+            7 => Expr::Set {
+                object: self.boxed_expr()?,
+                name: self.token()?,
+                value: self.boxed_expr()?,
+            },
+            8 => Expr::Super {
+                id: ExprId::next(),
+                keyword: self.token()?,
+                method: self.token()?,
+            },
+            9 => Expr::This {
+                id: ExprId::next(),
+                keyword: self.token()?,
+            },
+            10 => Expr::Unary {
+                operator: self.token()?,
+                right: self.boxed_expr()?,
+            },
+            11 => Expr::Variable {
+                id: ExprId::next(),
+                name: self.token()?,
+            },
+            12 => Expr::Tuple(self.exprs()?),
+            13 => Expr::Spread {
+                ellipsis: self.token()?,
+                value: self.boxed_expr()?,
+            },
+            14 => Expr::OptionalChain(self.boxed_expr()?),
+            _ => return None,
+        };
+
+        Some(expr)
+    }
+
+    fn opt_token(&mut self) -> Option<Option<Token>> {
+        if self.bool()? {
+            Some(Some(self.token()?))
+        } else {
+            Some(None)
+        }
+    }
+
+    fn opt_expr(&mut self) -> Option<Option<Expr>> {
+        if self.bool()? {
+            Some(Some(self.expr()?))
+        } else {
+            Some(None)
+        }
+    }
+
+    fn literal(&mut self) -> Option<LoxType> {
+        let value = match self.u8()? {
+            0 => LoxType::Boolean(self.bool()?),
+            1 => LoxType::Integer(self.u64()? as i64),
+            2 => LoxType::Nil,
+            3 => LoxType::Number(f64::from_bits(self.u64()?)),
+            4 => LoxType::String(self.str()?.into()),
+            5 => LoxType::Uninitialized,
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    fn tokens(&mut self) -> Option<Vec<Token>> {
+        let len = self.usize()?;
+
+        (0..len).map(|_| self.token()).collect()
+    }
+
+    fn token(&mut self) -> Option<Token> {
+        let token_type = *TokenType::ALL.get(self.usize()?)?;
+        let lexeme = self.str()?;
+        let lexeme = self.interner.intern(lexeme);
+        let literal = if self.bool()? {
+            Some(self.literal()?)
+        } else {
+            None
+        };
+
+        Some(Token::new(
+            token_type,
+            lexeme,
+            literal,
+            self.usize()?,
+            self.usize()?,
+        ))
+    }
+}
@@ -0,0 +1,325 @@
+use crate::{
+    ast::{Expr, Stmt},
+    lox_type::LoxType,
+    token::Token,
+};
+
+/// Renders the AST as s-expressions, one line per top-level statement.
+pub struct AstPrinter;
+
+impl AstPrinter {
+    pub fn print(&self, stmts: &[Stmt]) -> String {
+        stmts
+            .iter()
+            .map(|stmt| self.print_statement(stmt))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Prints `stmt` on one line, cut short with "..." past `width`
+    /// characters.
+    pub fn summarize_statement(&self, stmt: &Stmt, width: usize) -> String {
+        let source = self.print_statement(stmt);
+
+        if source.chars().count() <= width {
+            return source;
+        }
+
+        let mut summary: String = source.chars().take(width.saturating_sub(3)).collect();
+
+        summary.push_str("...");
+
+        summary
+    }
+
+    pub fn print_statement(&self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Block(stmts) => self.parenthesize_statements("block", stmts),
+            Stmt::Class {
+                name,
+                fields,
+                methods,
+                opt_superclass,
+            } => {
+                let mut head = format!("class {}", name.lexeme);
+
+                if let Some(superclass) = opt_superclass {
+                    head.push_str(&format!(" < {}", self.print_expression(superclass)));
+                }
+
+                for (field_name, value) in fields {
+                    head.push(' ');
+                    head.push_str(
+                        &self.parenthesize(&format!("var {}", field_name.lexeme), &[value]),
+                    );
+                }
+
+                self.parenthesize_statements(&head, methods)
+            }
+            Stmt::Expression(expr) => self.parenthesize(";", &[expr]),
+            Stmt::Function {
+                name,
+                params,
+                opt_rest,
+                body,
+            } => {
+                let mut params = join_lexemes(params);
+
+                if let Some(rest) = opt_rest {
+                    if !params.is_empty() {
+                        params.push(' ');
+                    }
+
+                    params.push_str(&format!("...{}", rest.lexeme));
+                }
+
+                let head = format!("fun {} ({})", name.lexeme, params);
+
+                self.parenthesize_statements(&head, body)
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                opt_else_branch,
+                ..
+            } => {
+                let mut out = format!(
+                    "(if {} {}",
+                    self.print_expression(condition),
+                    self.print_statement(then_branch)
+                );
+
+                if let Some(else_branch) = opt_else_branch {
+                    out.push_str(&format!(" {}", self.print_statement(else_branch)));
+                }
+
+                out.push(')');
+
+                out
+            }
+            Stmt::Import { path, .. } => format!("(import \"{}\")", path),
+            Stmt::Print { value: expr, .. } => self.parenthesize("print", &[expr]),
+            Stmt::Return { value, .. } => self.parenthesize("return", &[value]),
+            Stmt::Switch {
+                value,
+                cases,
+                opt_default,
+                ..
+            } => {
+                let mut out = format!("(switch {}", self.print_expression(value));
+
+                for (case_value, body) in cases {
+                    let head = format!("case {}", self.print_expression(case_value));
+
+                    out.push(' ');
+                    out.push_str(&self.parenthesize_statements(&head, body));
+                }
+
+                if let Some(body) = opt_default {
+                    out.push(' ');
+                    out.push_str(&self.parenthesize_statements("default", body));
+                }
+
+                out.push(')');
+
+                out
+            }
+            Stmt::Throw { value, .. } => self.parenthesize("throw", &[value]),
+            Stmt::Try {
+                body,
+                opt_catch,
+                opt_finally,
+                ..
+            } => {
+                let mut out = format!("(try {}", self.parenthesize_statements("block", body));
+
+                if let Some((name, catch_body)) = opt_catch {
+                    out.push(' ');
+                    out.push_str(
+                        &self
+                            .parenthesize_statements(&format!("catch {}", name.lexeme), catch_body),
+                    );
+                }
+
+                if let Some(finally_body) = opt_finally {
+                    out.push(' ');
+                    out.push_str(&self.parenthesize_statements("finally", finally_body));
+                }
+
+                out.push(')');
+
+                out
+            }
+            Stmt::Var {
+                name,
+                initializer,
+                is_const,
+            } => {
+                let keyword = if *is_const { "const" } else { "var" };
+
+                if let Expr::Literal(LoxType::Uninitialized) = initializer {
+                    format!("({} {})", keyword, name.lexeme)
+                } else {
+                    self.parenthesize(&format!("{} {}", keyword, name.lexeme), &[initializer])
+                }
+            }
+            Stmt::Destructure {
+                names, initializer, ..
+            } => self.parenthesize(&format!("var ({})", join_lexemes(names)), &[initializer]),
+            Stmt::DestructureAssign { targets, value, .. } => {
+                let names: Vec<Token> = targets.iter().map(|(_, name)| name.clone()).collect();
+
+                self.parenthesize(&format!("= ({})", join_lexemes(&names)), &[value])
+            }
+            Stmt::DoWhile {
+                body, condition, ..
+            } => format!(
+                "(do {} while {})",
+                self.print_statement(body),
+                self.print_expression(condition)
+            ),
+            Stmt::For {
+                initializers,
+                condition,
+                opt_increment,
+                body,
+                ..
+            } => {
+                let initializers: Vec<String> = initializers
+                    .iter()
+                    .map(|initializer| self.print_statement(initializer))
+                    .collect();
+
+                let increment = match opt_increment {
+                    Some(increment) => self.print_expression(increment),
+                    None => "nil".to_string(),
+                };
+
+                format!(
+                    "(for ({}) {} {} {})",
+                    initializers.join(" "),
+                    self.print_expression(condition),
+                    increment,
+                    self.print_statement(body)
+                )
+            }
+            Stmt::ForIn {
+                name,
+                value,
+                opt_end,
+                body,
+                ..
+            } => {
+                let iterable = match opt_end {
+                    Some(end) => format!(
+                        "(.. {} {})",
+                        self.print_expression(value),
+                        self.print_expression(end)
+                    ),
+                    None => self.print_expression(value),
+                };
+
+                format!(
+                    "(for-in {} {} {})",
+                    name.lexeme,
+                    iterable,
+                    self.print_statement(body)
+                )
+            }
+            Stmt::While {
+                condition, body, ..
+            } => format!(
+                "(while {} {})",
+                self.print_expression(condition),
+                self.print_statement(body)
+            ),
+        }
+    }
+
+    pub fn print_expression(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Assign { name, value, .. } => {
+                self.parenthesize(&format!("= {}", name.lexeme), &[value])
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => self.parenthesize(&operator.lexeme, &[left, right]),
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                let mut exprs = vec![callee.as_ref()];
+
+                exprs.extend(arguments.iter());
+
+                self.parenthesize("call", &exprs)
+            }
+            Expr::Get {
+                object,
+                name,
+                is_optional,
+            } => {
+                let operator = if *is_optional { "?." } else { "." };
+
+                self.parenthesize(&format!("{} {}", operator, name.lexeme), &[object])
+            }
+            Expr::Grouping(expr) => self.parenthesize("group", &[expr]),
+            Expr::OptionalChain(chain) => self.parenthesize("optional", &[chain]),
+            Expr::Literal(LoxType::String(s)) => format!("\"{}\"", s),
+            Expr::Literal(value) => value.to_string(),
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => self.parenthesize(&operator.lexeme, &[left, right]),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => self.parenthesize(&format!("= . {}", name.lexeme), &[object, value]),
+            Expr::Spread { value, .. } => self.parenthesize("...", &[value]),
+            Expr::Super { method, .. } => format!("(super {})", method.lexeme),
+            Expr::This { .. } => "this".to_string(),
+            Expr::Tuple(elements) => {
+                self.parenthesize("tuple", &elements.iter().collect::<Vec<_>>())
+            }
+            Expr::Unary { operator, right } => self.parenthesize(&operator.lexeme, &[right]),
+            Expr::Variable { name, .. } => name.lexeme.to_string(),
+        }
+    }
+
+    fn parenthesize(&self, name: &str, exprs: &[&Expr]) -> String {
+        let mut out = format!("({}", name);
+
+        for expr in exprs {
+            out.push(' ');
+            out.push_str(&self.print_expression(expr));
+        }
+
+        out.push(')');
+
+        out
+    }
+
+    fn parenthesize_statements(&self, name: &str, stmts: &[Stmt]) -> String {
+        let mut out = format!("({}", name);
+
+        for stmt in stmts {
+            out.push(' ');
+            out.push_str(&self.print_statement(stmt));
+        }
+
+        out.push(')');
+
+        out
+    }
+}
+
+fn join_lexemes(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|token| &*token.lexeme)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
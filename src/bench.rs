@@ -0,0 +1,66 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    lox::{self, Options},
+    stats::{format_duration, Stats},
+};
+
+pub const DEFAULT_RUNS: usize = 5;
+
+/// Runs each script in `files` `runs` times with its output discarded, then
+/// prints its timings and the counters from the last run.
+pub fn run_benchmarks(files: &[PathBuf], runs: usize, options: &Options) -> bool {
+    let mut ok = true;
+
+    for file in files {
+        match bench(file, runs, options) {
+            Ok((times, stats)) => {
+                let total: Duration = times.iter().sum();
+                let min = times.iter().min().copied().unwrap_or_default();
+
+                println!(
+                    "{}: mean {}, min {} over {} runs; {} statements, {} calls, {} environments",
+                    file.display(),
+                    format_duration(total / runs as u32),
+                    format_duration(min),
+                    runs,
+                    stats.statements,
+                    stats.calls,
+                    stats.environments
+                );
+            }
+            Err(message) => {
+                println!("{}: {}", file.display(), message);
+
+                ok = false;
+            }
+        }
+    }
+
+    ok
+}
+
+fn bench(file: &Path, runs: usize, options: &Options) -> Result<(Vec<Duration>, Stats), String> {
+    let mut times = Vec::new();
+    let mut last = Stats::default();
+
+    for _ in 0..runs {
+        let (diagnostics, stats) =
+            lox::run_script_with_stats(file, &[], options, Box::new(io::sink()))
+                .map_err(|err| err.to_string())?;
+
+        if let Some(err) = diagnostics.errors().first() {
+            return Err(err.to_string().replace('\n', " "));
+        }
+
+        times.push(stats.elapsed);
+
+        last = stats;
+    }
+
+    Ok((times, last))
+}
@@ -1,27 +1,105 @@
-use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+use std::{fmt, ptr};
 
-use crate::{function::Function, interpreter::InterpreterError, lox_type::LoxType, token::Token};
+use indexmap::IndexMap;
+
+use crate::{
+    ast::Expr,
+    environment::Environment,
+    function::Function,
+    interpreter::InterpreterError,
+    lox_type::LoxType,
+    shared::{Locked, Shared},
+    token::Token,
+};
+
+/// Whether a property is private: only code in a class related to the
+/// instance's class can get or set it.
+pub fn is_private(name: &str) -> bool {
+    name.starts_with('_')
+}
 
 #[derive(Debug, Clone)]
 pub struct LoxClass {
-    name: String,
-    methods: HashMap<String, Function>,
-    superclass: Option<Rc<RefCell<LoxClass>>>,
+    name: Shared<str>,
+    fields: Shared<[(Token, Expr)]>,
+    methods: IndexMap<Shared<str>, Function>,
+    superclass: Option<Shared<Locked<LoxClass>>>,
+    /// The scope the class was declared in, where field initializers run.
+    closure: Shared<Locked<Environment>>,
 }
 
 impl LoxClass {
     pub fn new(
-        name: &str,
-        methods: HashMap<String, Function>,
-        superclass: Option<Rc<RefCell<LoxClass>>>,
+        name: Shared<str>,
+        fields: Shared<[(Token, Expr)]>,
+        methods: IndexMap<Shared<str>, Function>,
+        superclass: Option<Shared<Locked<LoxClass>>>,
+        closure: Shared<Locked<Environment>>,
     ) -> Self {
         Self {
-            name: name.to_string(),
+            name,
+            fields,
             methods,
             superclass,
+            closure,
         }
     }
 
+    pub fn name(&self) -> &Shared<str> {
+        &self.name
+    }
+
+    /// The fields declared in the class body with their initializers, not
+    /// counting inherited ones.
+    pub fn fields(&self) -> Shared<[(Token, Expr)]> {
+        Shared::clone(&self.fields)
+    }
+
+    pub fn closure(&self) -> &Shared<Locked<Environment>> {
+        &self.closure
+    }
+
+    pub fn methods(&self) -> impl Iterator<Item = &Function> {
+        self.methods.values()
+    }
+
+    pub fn superclass(&self) -> Option<&Shared<Locked<LoxClass>>> {
+        self.superclass.as_ref()
+    }
+
+    /// Whether this class is `class` or inherits from it.
+    pub fn is_subclass_of(&self, class: &Shared<Locked<LoxClass>>) -> bool {
+        if ptr::eq(self, &*class.borrow()) {
+            return true;
+        }
+
+        self.superclass
+            .as_ref()
+            .is_some_and(|superclass| superclass.borrow().is_subclass_of(class))
+    }
+
+    /// The names of the class's methods, its own in declaration order and
+    /// then inherited ones it doesn't override.
+    pub fn method_names(&self) -> Vec<Shared<str>> {
+        let mut names: Vec<Shared<str>> = self.methods.keys().cloned().collect();
+
+        if let Some(ref superclass) = self.superclass {
+            for name in superclass.borrow().method_names() {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+
+        names
+    }
+
+    /// Adds a method, or replaces one the class declared or gained before.
+    /// Methods already bound to instances keep the old definition.
+    pub fn define_method(&mut self, name: Shared<str>, method: Function) {
+        self.methods.insert(name, method);
+    }
+
     pub fn find_method(&self, name: &str) -> Option<Function> {
         if self.methods.contains_key(name) {
             self.methods.get(name).cloned()
@@ -43,33 +121,87 @@ impl fmt::Display for LoxClass {
 
 #[derive(Debug, Clone)]
 pub struct LoxInstance {
-    class: Rc<RefCell<LoxClass>>,
-    fields: HashMap<String, LoxType>,
+    class: Shared<Locked<LoxClass>>,
+    fields: IndexMap<Shared<str>, LoxType>,
 }
 
 impl LoxInstance {
-    pub fn new(class: &Rc<RefCell<LoxClass>>) -> Self {
+    pub fn new(class: &Shared<Locked<LoxClass>>) -> Self {
         Self {
-            class: Rc::clone(class),
-            fields: HashMap::new(),
+            class: Shared::clone(class),
+            fields: IndexMap::new(),
         }
     }
 
     pub fn get(&self, name: &Token, instance: &LoxType) -> Result<LoxType, InterpreterError> {
-        if let Some(field) = self.fields.get(&name.lexeme) {
-            Ok(field.clone())
-        } else if let Some(method) = self.class.borrow().find_method(&name.lexeme) {
-            Ok(LoxType::Callable(method.bind(instance.clone())))
-        } else {
-            Err(InterpreterError::runtime_error(
+        self.find_property(&name.lexeme, instance).ok_or_else(|| {
+            InterpreterError::runtime_error(
                 Some(name.clone()),
                 &format!("Undefined property '{}'.", name.lexeme),
-            ))
+            )
+        })
+    }
+
+    /// The field `name`, or else the method `name` bound to `instance`.
+    pub fn find_property(&self, name: &str, instance: &LoxType) -> Option<LoxType> {
+        if let Some(field) = self.fields.get(name) {
+            Some(field.clone())
+        } else {
+            self.class
+                .borrow()
+                .find_method(name)
+                .map(|method| LoxType::Callable(method.bind(instance.clone())))
         }
     }
 
+    /// Whether `name` is a field or a method of the instance.
+    pub fn has_property(&self, name: &str) -> bool {
+        self.fields.contains_key(name) || self.class.borrow().find_method(name).is_some()
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Function> {
+        self.class.borrow().find_method(name)
+    }
+
     pub fn set(&mut self, name: &Token, value: LoxType) {
-        self.fields.insert(name.lexeme.to_string(), value);
+        self.set_field(Shared::clone(&name.lexeme), value);
+    }
+
+    pub fn set_field(&mut self, name: Shared<str>, value: LoxType) {
+        self.fields.insert(name, value);
+    }
+
+    pub fn class(&self) -> &Shared<Locked<LoxClass>> {
+        &self.class
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = &LoxType> {
+        self.fields.values()
+    }
+
+    /// The instance's fields and their names, in the order they were first
+    /// set.
+    pub fn named_fields(&self) -> impl Iterator<Item = (&Shared<str>, &LoxType)> {
+        self.fields.iter()
+    }
+
+    /// The names of the instance's fields in the order they were first set,
+    /// then its methods.
+    pub fn property_names(&self) -> Vec<Shared<str>> {
+        let mut names: Vec<Shared<str>> = self.fields.keys().cloned().collect();
+
+        for name in self.class.borrow().method_names() {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        names
+    }
+
+    /// Drops every field, breaking any cycles that run through them.
+    pub fn clear(&mut self) {
+        self.fields.clear();
     }
 }
 
@@ -0,0 +1,195 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    slice,
+};
+
+use crate::ast::Stmt;
+
+/// How many times each statement line of the scripts and modules a run
+/// loaded was executed, collected by `--coverage` and `--lcov`. Statements
+/// that run one after another on the same line count as one pass over it;
+/// a new pass starts when one of them runs again, as in a one-line loop.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    files: BTreeMap<PathBuf, BTreeMap<usize, usize>>,
+    /// The file and line of the last statement recorded.
+    last_path: PathBuf,
+    last_line: usize,
+    /// Addresses of the statements run in the current pass over `last_line`.
+    pass: HashSet<usize>,
+}
+
+impl Coverage {
+    /// Starts tracking the lines of `statements`, parsed from `path`, as not
+    /// run yet.
+    pub(crate) fn add_file(&mut self, path: &Path, statements: &[Stmt]) {
+        let lines = self.files.entry(path.to_path_buf()).or_default();
+
+        add_lines(lines, statements);
+    }
+
+    pub(crate) fn record(&mut self, path: &Path, line: usize, stmt: &Stmt) {
+        let address = stmt as *const Stmt as usize;
+
+        if line == self.last_line && path == self.last_path {
+            if self.pass.insert(address) {
+                return;
+            }
+        } else {
+            self.last_line = line;
+
+            if path != self.last_path {
+                self.last_path = path.to_path_buf();
+            }
+        }
+
+        self.pass.clear();
+        self.pass.insert(address);
+
+        if let Some(count) = self
+            .files
+            .get_mut(path)
+            .and_then(|lines| lines.get_mut(&line))
+        {
+            *count += 1;
+        }
+    }
+
+    /// Renders the counts as an lcov tracefile, which coverage viewers and
+    /// CI services understand.
+    pub fn lcov(&self) -> String {
+        let mut out = String::new();
+
+        for (path, lines) in &self.files {
+            out.push_str("TN:\n");
+            out.push_str(&format!("SF:{}\n", path.display()));
+
+            for (line, count) in lines {
+                out.push_str(&format!("DA:{},{}\n", line, count));
+            }
+
+            out.push_str(&format!("LF:{}\n", lines.len()));
+            out.push_str(&format!("LH:{}\n", hit_count(lines)));
+            out.push_str("end_of_record\n");
+        }
+
+        out
+    }
+}
+
+/// One line per file with the share of lines run, followed by the lines
+/// that never ran.
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (path, lines)) in self.files.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+
+            let hit = hit_count(lines);
+
+            let percent = if lines.is_empty() {
+                100.0
+            } else {
+                hit as f64 * 100.0 / lines.len() as f64
+            };
+
+            write!(
+                f,
+                "{}: {}/{} lines ({:.1}%)",
+                path.display(),
+                hit,
+                lines.len(),
+                percent
+            )?;
+
+            let missed: Vec<_> = lines
+                .iter()
+                .filter(|(_, count)| **count == 0)
+                .map(|(line, _)| line.to_string())
+                .collect();
+
+            if !missed.is_empty() {
+                write!(f, "\n  not run: {}", missed.join(", "))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn hit_count(lines: &BTreeMap<usize, usize>) -> usize {
+    lines.values().filter(|count| **count > 0).count()
+}
+
+/// Adds the line of every statement in `statements`, including nested
+/// ones. Blocks have no line of their own.
+fn add_lines(lines: &mut BTreeMap<usize, usize>, statements: &[Stmt]) {
+    for stmt in statements {
+        if let Some(line) = stmt.line().filter(|_| !matches!(stmt, Stmt::Block(_))) {
+            lines.entry(line).or_insert(0);
+        }
+
+        match stmt {
+            Stmt::Block(body) | Stmt::Function { body, .. } => add_lines(lines, body),
+            Stmt::Class { methods, .. } => add_lines(lines, methods),
+            Stmt::DoWhile { body, .. } | Stmt::ForIn { body, .. } | Stmt::While { body, .. } => {
+                add_lines(lines, slice::from_ref(body))
+            }
+            Stmt::For {
+                initializers, body, ..
+            } => {
+                add_lines(lines, initializers);
+                add_lines(lines, slice::from_ref(body));
+            }
+            Stmt::If {
+                then_branch,
+                opt_else_branch,
+                ..
+            } => {
+                add_lines(lines, slice::from_ref(then_branch));
+
+                if let Some(else_branch) = opt_else_branch {
+                    add_lines(lines, slice::from_ref(else_branch));
+                }
+            }
+            Stmt::Switch {
+                cases, opt_default, ..
+            } => {
+                for (_, body) in cases {
+                    add_lines(lines, body);
+                }
+
+                if let Some(body) = opt_default {
+                    add_lines(lines, body);
+                }
+            }
+            Stmt::Try {
+                body,
+                opt_catch,
+                opt_finally,
+                ..
+            } => {
+                add_lines(lines, body);
+
+                if let Some((_, body)) = opt_catch {
+                    add_lines(lines, body);
+                }
+
+                if let Some(body) = opt_finally {
+                    add_lines(lines, body);
+                }
+            }
+            Stmt::Destructure { .. }
+            | Stmt::DestructureAssign { .. }
+            | Stmt::Expression(_)
+            | Stmt::Import { .. }
+            | Stmt::Print { .. }
+            | Stmt::Return { .. }
+            | Stmt::Throw { .. }
+            | Stmt::Var { .. } => (),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::BTreeSet,
+    io::{stdin, stdout, Write},
+};
+
+use crate::{
+    ast::Stmt,
+    ast_printer::AstPrinter,
+    interpreter::{Hook, Interpreter, InterpreterError},
+};
+
+const HELP: &str = "commands:
+  s, step          run until the next statement
+  n, next          run until the next statement in this function or its caller
+  c, continue      run until the next breakpoint
+  b, break <line>  set a breakpoint
+  d, delete <line> remove a breakpoint
+  e, env           print the environment chain
+  q, quit          stop the program
+  h, help          show this message";
+
+enum Mode {
+    Step,
+    Next(usize),
+    Continue,
+}
+
+/// A line-based debugger driven from stdin, installed as an interpreter hook
+/// by `rlox --debug`.
+pub struct Debugger {
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    last_line: Option<usize>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            mode: Mode::Step,
+            last_line: None,
+        }
+    }
+
+    fn should_stop(&self, interpreter: &Interpreter, line: usize) -> bool {
+        match self.mode {
+            Mode::Step => true,
+            Mode::Next(depth) => interpreter.call_depth() <= depth,
+            Mode::Continue => self.breakpoints.contains(&line) && self.last_line != Some(line),
+        }
+    }
+
+    fn prompt(&mut self, interpreter: &Interpreter) -> Result<(), InterpreterError> {
+        let mut input = String::new();
+
+        loop {
+            print!("(debug) ");
+
+            let _ = stdout().flush();
+
+            input.clear();
+
+            if stdin().read_line(&mut input).unwrap_or(0) == 0 {
+                self.mode = Mode::Continue;
+
+                return Ok(());
+            }
+
+            let mut words = input.split_whitespace();
+
+            match (words.next(), words.next().map(str::parse::<usize>)) {
+                (Some("s"), None) | (Some("step"), None) => {
+                    self.mode = Mode::Step;
+
+                    return Ok(());
+                }
+                (Some("n"), None) | (Some("next"), None) => {
+                    self.mode = Mode::Next(interpreter.call_depth());
+
+                    return Ok(());
+                }
+                (Some("c"), None) | (Some("continue"), None) => {
+                    self.mode = Mode::Continue;
+
+                    return Ok(());
+                }
+                (Some("b"), Some(Ok(line))) | (Some("break"), Some(Ok(line))) => {
+                    self.breakpoints.insert(line);
+
+                    println!("Breakpoint set at line {}.", line);
+                }
+                (Some("d"), Some(Ok(line))) | (Some("delete"), Some(Ok(line))) => {
+                    if self.breakpoints.remove(&line) {
+                        println!("Breakpoint removed from line {}.", line);
+                    } else {
+                        println!("No breakpoint at line {}.", line);
+                    }
+                }
+                (Some("e"), None) | (Some("env"), None) => print_environment(interpreter),
+                (Some("q"), None) | (Some("quit"), None) => {
+                    return Err(InterpreterError::runtime_error(
+                        None,
+                        "Program stopped by the debugger.",
+                    ))
+                }
+                (None, _) => (),
+                _ => println!("{}", HELP),
+            }
+        }
+    }
+}
+
+impl Hook for Debugger {
+    fn before_statement(
+        &mut self,
+        interpreter: &Interpreter,
+        stmt: &Stmt,
+    ) -> Result<(), InterpreterError> {
+        if let Stmt::Block(_) = stmt {
+            return Ok(());
+        }
+
+        let line = match stmt.line() {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+
+        if self.should_stop(interpreter, line) {
+            println!(
+                "[line {}] {}",
+                line,
+                AstPrinter.summarize_statement(stmt, 72)
+            );
+
+            self.prompt(interpreter)?;
+        }
+
+        self.last_line = Some(line);
+
+        Ok(())
+    }
+}
+
+fn print_environment(interpreter: &Interpreter) {
+    println!("{}", interpreter.environment().borrow().describe());
+}
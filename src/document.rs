@@ -0,0 +1,187 @@
+use crate::{
+    ast::Stmt, error::Diagnostics, parser::Parser, scanner::Scanner, token_type::TokenType,
+};
+
+/// A zero-based line and character, as editors count them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+/// What's kept of one top-level declaration so an edit after it can skip
+/// reparsing it.
+struct Declaration {
+    /// How many statements it parsed to.
+    statements: usize,
+    /// Byte offset just past its last token, where scanning picks up again.
+    end: usize,
+    /// Line of its last token.
+    line: usize,
+    seen_line: usize,
+    is_reusable: bool,
+}
+
+/// A source text together with its parsed statements. Edits only reparse
+/// from the first top-level declaration they could change, so typing near
+/// the end of a long file doesn't parse the whole file again.
+pub struct Document {
+    text: String,
+    compat: bool,
+    statements: Vec<Stmt>,
+    declarations: Vec<Declaration>,
+    diagnostics: Diagnostics,
+}
+
+impl Document {
+    pub fn new(text: String, compat: bool) -> Self {
+        let mut document = Self {
+            text,
+            compat,
+            statements: Vec::new(),
+            declarations: Vec::new(),
+            diagnostics: Diagnostics::new(),
+        };
+
+        document.reparse(0);
+
+        document
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn statements(&self) -> &[Stmt] {
+        &self.statements
+    }
+
+    /// The errors and warnings from parsing the current text.
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// Replaces the text between `start` and `end` with `text`.
+    pub fn edit(&mut self, start: Position, end: Position, text: &str) {
+        let start_offset = offset(&self.text, start);
+        let end_offset = offset(&self.text, end).max(start_offset);
+
+        self.text.replace_range(start_offset..end_offset, text);
+
+        // Tokens report one-based lines.
+        let kept = self
+            .declarations
+            .iter()
+            .take_while(|declaration| {
+                declaration.is_reusable && declaration.seen_line < start.line + 1
+            })
+            .count();
+
+        self.reparse(kept);
+    }
+
+    /// Replaces the whole text.
+    pub fn replace(&mut self, text: String) {
+        self.text = text;
+
+        self.reparse(0);
+    }
+
+    /// Keeps the first `kept` declarations and parses everything after them.
+    fn reparse(&mut self, kept: usize) {
+        self.declarations.truncate(kept);
+
+        let (resume_offset, resume_line) = self
+            .declarations
+            .last()
+            .map_or((0, 1), |declaration| (declaration.end, declaration.line));
+
+        self.statements.truncate(
+            self.declarations
+                .iter()
+                .map(|declaration| declaration.statements)
+                .sum(),
+        );
+
+        self.diagnostics.clear();
+
+        let scanner = Scanner::new(&self.text)
+            .compat(self.compat)
+            .resume_at(resume_offset, resume_line);
+
+        let mut parser = Parser::new(scanner, &mut self.diagnostics);
+
+        // Declarations come in order, so finding where each one ends only
+        // needs to look forward from the previous one.
+        let mut lines = Lines {
+            text: &self.text,
+            line: resume_line,
+            start: self.text[..resume_offset].rfind('\n').map_or(0, |i| i + 1),
+        };
+
+        while let Some(top_level) = parser.top_level() {
+            let last = &top_level.last;
+
+            self.declarations.push(Declaration {
+                statements: top_level.statements.len(),
+                end: lines.offset(last.line, last.column) + last.lexeme.len(),
+                line: last.line,
+                seen_line: top_level.seen_line,
+                // Picking up after a `;` or `}` starts on a fresh statement.
+                is_reusable: top_level.is_clean
+                    && matches!(
+                        last.token_type,
+                        TokenType::SemiColon | TokenType::RightBrace
+                    ),
+            });
+
+            self.statements.extend(top_level.statements);
+        }
+    }
+}
+
+/// The byte offset of `position` in `text`, clamped to the end of its line
+/// and of the text.
+fn offset(text: &str, position: Position) -> usize {
+    Lines {
+        text,
+        line: 1,
+        start: 0,
+    }
+    .offset(position.line + 1, position.character)
+}
+
+/// Walks forward through the lines of a text, remembering where the current
+/// one starts.
+struct Lines<'a> {
+    text: &'a str,
+    /// The current line, counting from one like tokens do.
+    line: usize,
+    /// Byte offset of the start of `line`.
+    start: usize,
+}
+
+impl Lines<'_> {
+    /// The byte offset of `column` characters into `line`, which mustn't be
+    /// before the current line. Clamps to the end of the line and the text.
+    fn offset(&mut self, line: usize, column: usize) -> usize {
+        while self.line < line {
+            match self.text[self.start..].find('\n') {
+                Some(i) => {
+                    self.start += i + 1;
+                    self.line += 1;
+                }
+                None => return self.text.len(),
+            }
+        }
+
+        let rest = &self.text[self.start..];
+        let rest = &rest[..rest.find('\n').unwrap_or(rest.len())];
+
+        self.start
+            + rest
+                .char_indices()
+                .nth(column)
+                .map_or(rest.len(), |(i, _)| i)
+    }
+}
@@ -1,100 +1,236 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::collections::{HashMap, HashSet};
 
-use crate::lox_type::LoxType;
+use crate::{
+    function::Function,
+    heap::Heap,
+    lox_type::LoxType,
+    shared::{Locked, Shared},
+};
 
+/// A scope's variables. The global scope is keyed by name; local scopes
+/// keep their values in the slots the resolver assigned, in declaration
+/// order, so resolved lookups index a `Vec` instead of hashing names.
 #[derive(Clone, Debug)]
 pub struct Environment {
-    values: HashMap<String, LoxType>,
-    pub enclosing: Option<Rc<RefCell<Environment>>>,
+    values: HashMap<Shared<str>, LoxType>,
+    slots: Vec<LoxType>,
+    names: Vec<Shared<str>>,
+    /// Names bound with `const` in this scope.
+    constants: HashSet<Shared<str>>,
+    pub enclosing: Option<Shared<Locked<Environment>>>,
+    /// Tracks every environment in this chain, shared by all of them.
+    heap: Shared<Heap>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Self {
             values: HashMap::new(),
+            slots: Vec::new(),
+            names: Vec::new(),
+            constants: HashSet::new(),
             enclosing: None,
+            heap: Shared::new(Heap::default()),
         }
     }
 
-    pub fn with_enclosing(enclosing: &Rc<RefCell<Environment>>) -> Self {
-        Self {
+    /// Creates a scope nested in `enclosing`, tracked by its heap.
+    pub fn child(enclosing: &Shared<Locked<Environment>>) -> Shared<Locked<Environment>> {
+        let heap = Shared::clone(&enclosing.borrow().heap);
+
+        let env = Shared::new(Locked::new(Self {
             values: HashMap::new(),
-            enclosing: Some(Rc::clone(enclosing)),
-        }
+            slots: Vec::new(),
+            names: Vec::new(),
+            constants: HashSet::new(),
+            enclosing: Some(Shared::clone(enclosing)),
+            heap: Shared::clone(&heap),
+        }));
+
+        heap.track_environment(&env);
+
+        env
     }
 
-    pub fn get(&self, name: &str) -> Option<LoxType> {
-        let res = self.values.get(name);
+    /// Creates a scope beside `env`, in the same enclosing scope and with
+    /// copies of its bindings, so closures over `env` keep the old values.
+    pub fn copy(env: &Shared<Locked<Environment>>) -> Shared<Locked<Environment>> {
+        let copy = Shared::new(Locked::new(env.borrow().clone()));
+
+        env.borrow().heap.track_environment(&copy);
 
-        if res.is_some() {
-            res.cloned()
+        copy
+    }
+
+    pub fn heap(&self) -> &Shared<Heap> {
+        &self.heap
+    }
+
+    /// Looks a variable up by name, for globals and other lookups the
+    /// resolver didn't assign a slot to.
+    pub fn get(&self, name: &str) -> Option<LoxType> {
+        if let Some(value) = self.values.get(name) {
+            Some(value.clone())
+        } else if let Some(slot) = self.slot_of(name) {
+            Some(self.slots[slot].clone())
+        } else if let Some(ref enclosing) = self.enclosing {
+            enclosing.borrow().get(name)
         } else {
-            if let Some(ref enclosing) = self.enclosing {
-                enclosing.borrow().get(name)
-            } else {
-                None
-            }
+            None
         }
     }
 
-    pub fn get_at(&self, distance: usize, name: &str) -> Option<LoxType> {
+    pub fn get_at(&self, distance: usize, slot: usize) -> Option<LoxType> {
         if distance > 0 {
-            Some(
-                self.ancestor(distance)
-                    .borrow()
-                    .values
-                    .get(name)
-                    .expect(&format!("Undefined variable '{}'", name))
-                    .clone(),
-            )
+            self.ancestor(distance).borrow().slots.get(slot).cloned()
         } else {
-            Some(
-                self.values
-                    .get(name)
-                    .expect(&format!("Undefined variable '{}'", name))
-                    .clone(),
-            )
+            self.slots.get(slot).cloned()
         }
     }
 
     pub fn assign(&mut self, name: &str, value: LoxType) -> bool {
-        if self.values.contains_key(name) {
-            self.define(name, value);
+        if let Some(existing) = self.values.get_mut(name) {
+            *existing = value;
+
+            true
+        } else if let Some(slot) = self.slot_of(name) {
+            self.slots[slot] = value;
+
+            true
+        } else if let Some(ref enclosing) = self.enclosing {
+            enclosing.borrow_mut().assign(name, value)
+        } else {
+            false
+        }
+    }
+
+    pub fn assign_at(&mut self, distance: usize, slot: usize, value: LoxType) -> bool {
+        if distance > 0 {
+            if let Some(existing) = self.ancestor(distance).borrow_mut().slots.get_mut(slot) {
+                *existing = value;
+
+                return true;
+            }
+
+            false
+        } else if let Some(existing) = self.slots.get_mut(slot) {
+            *existing = value;
 
             true
         } else {
-            if let Some(ref enclosing) = self.enclosing {
-                enclosing.borrow_mut().assign(name, value)
-            } else {
-                false
+            false
+        }
+    }
+
+    /// The bindings defined directly in this environment, sorted by name.
+    pub fn values(&self) -> Vec<(&Shared<str>, &LoxType)> {
+        let mut values: Vec<_> = self
+            .values
+            .iter()
+            .chain(self.names.iter().zip(self.slots.iter()))
+            .collect();
+
+        values.sort_by_key(|&(name, _)| name);
+
+        values
+    }
+
+    /// Lists the bindings of every scope from this one out to the globals,
+    /// one scope per `depth` heading, leaving out native functions.
+    pub fn describe(&self) -> String {
+        let mut lines = Vec::new();
+
+        self.describe_at(0, &mut lines);
+
+        lines.join("\n")
+    }
+
+    fn describe_at(&self, depth: usize, lines: &mut Vec<String>) {
+        if let Some(ref enclosing) = self.enclosing {
+            lines.push(format!("depth {}:", depth));
+
+            for (name, value) in self.values() {
+                lines.push(format!("  {} = {}", name, value));
+            }
+
+            enclosing.borrow().describe_at(depth + 1, lines);
+        } else {
+            lines.push("globals:".to_string());
+
+            for (name, value) in self.values() {
+                if let LoxType::Callable(Function::Native { .. }) = value {
+                    continue;
+                }
+
+                lines.push(format!("  {} = {}", name, value));
             }
         }
     }
 
-    pub fn assign_at(&mut self, distance: usize, name: &str, value: LoxType) -> bool {
+    /// Defines a variable. Local scopes take the next slot, which matches the
+    /// slot the resolver gave the declaration.
+    pub fn define(&mut self, name: Shared<str>, value: LoxType) {
+        self.constants.remove(&name);
+
+        if self.enclosing.is_some() {
+            self.names.push(name);
+            self.slots.push(value);
+        } else {
+            self.values.insert(name, value);
+        }
+    }
+
+    /// Defines a variable that can't be assigned to.
+    pub fn define_constant(&mut self, name: Shared<str>, value: LoxType) {
+        self.define(Shared::clone(&name), value);
+
+        self.constants.insert(name);
+    }
+
+    /// Whether `name` is a constant in this scope, for globals looked up by
+    /// name.
+    pub fn is_constant(&self, name: &str) -> bool {
+        self.constants.contains(name)
+    }
+
+    pub fn is_constant_at(&self, distance: usize, slot: usize) -> bool {
+        let is_constant = |env: &Environment| {
+            env.names
+                .get(slot)
+                .is_some_and(|name| env.constants.contains(name))
+        };
+
         if distance > 0 {
-            self.ancestor(distance)
-                .borrow_mut()
-                .values
-                .insert(name.to_string(), value);
+            is_constant(&self.ancestor(distance).borrow())
         } else {
-            self.values.insert(name.to_string(), value);
+            is_constant(self)
         }
+    }
+
+    /// Every value bound directly in this environment.
+    pub fn bindings(&self) -> impl Iterator<Item = &LoxType> {
+        self.values.values().chain(self.slots.iter())
+    }
 
-        true
+    /// Drops every binding, breaking any cycles that run through them.
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.slots.clear();
+        self.names.clear();
+        self.constants.clear();
     }
 
-    pub fn define(&mut self, name: &str, value: LoxType) {
-        self.values.insert(name.to_string(), value);
+    fn slot_of(&self, name: &str) -> Option<usize> {
+        self.names.iter().rposition(|local| &**local == name)
     }
 
-    fn ancestor(&self, distance: usize) -> Rc<RefCell<Environment>> {
+    fn ancestor(&self, distance: usize) -> Shared<Locked<Environment>> {
         // Get first ancestor
         let parent = self
             .enclosing
             .clone()
-            .expect(&format!("No enclosing environment at {}", 1));
-        let mut environment = Rc::clone(&parent);
+            .unwrap_or_else(|| panic!("No enclosing environment at {}", 1));
+        let mut environment = Shared::clone(&parent);
 
         // Get next ancestors
         for i in 1..distance {
@@ -102,8 +238,8 @@ impl Environment {
                 .borrow()
                 .enclosing
                 .clone()
-                .expect(&format!("No enclosing environment at {}", i));
-            environment = Rc::clone(&parent);
+                .unwrap_or_else(|| panic!("No enclosing environment at {}", i));
+            environment = Shared::clone(&parent);
         }
 
         environment
@@ -0,0 +1,144 @@
+use std::fmt;
+
+use crate::{interpreter::RuntimeError, token::Token, token_type::TokenType};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoxError {
+    Compile {
+        line: usize,
+        location: String,
+        message: String,
+    },
+    Runtime {
+        line: Option<usize>,
+        message: String,
+    },
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use LoxError::*;
+
+        match self {
+            Compile {
+                line,
+                location,
+                message,
+            } => write!(f, "[line {}] Error{}: {}", line, location, message),
+            Runtime {
+                line: Some(line),
+                message,
+            } => write!(f, "{}\n[line {}]", message, line),
+            Runtime {
+                line: None,
+                message,
+            } => write!(f, "{}", message),
+        }
+    }
+}
+
+/// A problem worth reporting that doesn't stop the program from running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub line: usize,
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Warning{}: {}",
+            self.line, self.location, self.message
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Diagnostics {
+    errors: Vec<LoxError>,
+    warnings: Vec<Warning>,
+    opt_exit_code: Option<i32>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn error(&mut self, line: usize, message: &str) {
+        self.report(line, "", message);
+    }
+
+    pub fn token_error(&mut self, token: &Token, message: &str) {
+        self.report(token.line, &location(token), message)
+    }
+
+    pub fn warning(&mut self, token: &Token, message: &str) {
+        self.warnings.push(Warning {
+            line: token.line,
+            location: location(token),
+            message: message.to_string(),
+        });
+    }
+
+    pub fn runtime_error(&mut self, err: &RuntimeError) {
+        self.errors.push(LoxError::Runtime {
+            line: err.token.as_ref().map(|token| token.line),
+            message: err.message.clone(),
+        });
+    }
+
+    /// Records that the script asked to stop with `code`.
+    pub fn exit(&mut self, code: i32) {
+        self.opt_exit_code = Some(code);
+    }
+
+    /// The code the script passed to `exit()`, if it called it.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.opt_exit_code
+    }
+
+    pub fn had_error(&self) -> bool {
+        self.errors
+            .iter()
+            .any(|err| matches!(err, LoxError::Compile { .. }))
+    }
+
+    pub fn had_runtime_error(&self) -> bool {
+        self.errors
+            .iter()
+            .any(|err| matches!(err, LoxError::Runtime { .. }))
+    }
+
+    pub fn errors(&self) -> &[LoxError] {
+        &self.errors
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    pub fn clear(&mut self) {
+        self.errors.clear();
+        self.warnings.clear();
+        self.opt_exit_code = None;
+    }
+
+    fn report(&mut self, line: usize, location: &str, message: &str) {
+        self.errors.push(LoxError::Compile {
+            line,
+            location: location.to_string(),
+            message: message.to_string(),
+        });
+    }
+}
+
+fn location(token: &Token) -> String {
+    if token.token_type == TokenType::Eof {
+        " at end".to_string()
+    } else {
+        format!(" at '{}'", token.lexeme)
+    }
+}
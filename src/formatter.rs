@@ -0,0 +1,414 @@
+use std::{fs, path::PathBuf};
+
+use crate::{
+    error::Diagnostics, lox::Options, parser::Parser, scanner::Scanner, token::Token,
+    token_type::TokenType,
+};
+
+const INDENT: &str = "  ";
+
+/// Formats every file in `files`, or with `check` only reports the ones
+/// whose formatting would change. Returns whether all of them were readable,
+/// parsed, and (with `check`) already formatted.
+pub fn format_files(files: &[PathBuf], check: bool, options: &Options) -> bool {
+    let mut ok = true;
+
+    for file in files {
+        let src = match fs::read_to_string(file) {
+            Ok(src) => src,
+            Err(err) => {
+                println!("{}: {}", file.display(), err);
+
+                ok = false;
+
+                continue;
+            }
+        };
+
+        let formatted = match format_source(&src, options.compat) {
+            Ok(formatted) => formatted,
+            Err(diagnostics) => {
+                for err in diagnostics.errors() {
+                    println!("{}: {}", file.display(), err);
+                }
+
+                ok = false;
+
+                continue;
+            }
+        };
+
+        if formatted == src {
+            continue;
+        }
+
+        if check {
+            println!("{}: not formatted", file.display());
+
+            ok = false;
+        } else if let Err(err) = fs::write(file, formatted) {
+            println!("{}: {}", file.display(), err);
+
+            ok = false;
+        } else {
+            println!("Formatted {}.", file.display());
+        }
+    }
+
+    ok
+}
+
+/// Re-emits `src` with canonical indentation, spacing and brace placement,
+/// keeping its comments. Source that doesn't parse is left alone and its
+/// errors are returned instead.
+pub fn format_source(src: &str, compat: bool) -> Result<String, Diagnostics> {
+    let mut diagnostics = Diagnostics::new();
+
+    let mut tokens = Scanner::new(src)
+        .compat(compat)
+        .scan_tokens(&mut diagnostics);
+
+    if !diagnostics.had_error() {
+        Parser::new(tokens.iter().cloned().map(Ok), &mut diagnostics).parse();
+    }
+
+    if diagnostics.had_error() {
+        return Err(diagnostics);
+    }
+
+    demote_extension_keywords(&mut tokens);
+
+    let mut formatter = Formatter::default();
+
+    for (index, token) in tokens.iter().enumerate() {
+        formatter.token(token, &tokens[index + 1..]);
+    }
+
+    Ok(formatter.output)
+}
+
+#[derive(Default)]
+struct Formatter {
+    output: String,
+    indent: usize,
+    /// Open parentheses, so `;` inside a `for` header stays on the line.
+    parens: usize,
+    /// For each open `{`, whether a `case` body added an indent level.
+    braces: Vec<bool>,
+    /// For each open `{`, whether it starts the body of a `do` loop, whose
+    /// `while` follows the `}` on the same line.
+    do_bodies: Vec<bool>,
+    /// `do` loops whose body is a single statement without braces and
+    /// hasn't ended yet.
+    braceless_dos: usize,
+    /// Inside the braces of a pattern like `var {x, y} = point;`, which
+    /// stay on one line.
+    in_pattern: bool,
+    /// The next token starts a new line.
+    line_break: bool,
+    /// Source line of the last token or comment written.
+    last_line: usize,
+    opt_previous: Option<Token>,
+    previous_is_unary: bool,
+}
+
+impl Formatter {
+    /// Writes `token`, given the tokens that come after it.
+    fn token(&mut self, token: &Token, rest: &[Token]) {
+        let next_type = rest.first().map(|next| &next.token_type);
+
+        let mut closes_do_body = false;
+
+        match token.token_type {
+            TokenType::RightBrace if self.in_pattern => (),
+            TokenType::RightBrace => {
+                closes_do_body = self.do_bodies.pop() == Some(true);
+
+                if self.braces.pop() == Some(true) {
+                    self.indent -= 1;
+                }
+
+                self.indent = self.indent.saturating_sub(1);
+
+                self.line_break = !self.output.ends_with('{');
+            }
+            TokenType::Case | TokenType::Default => {
+                if let Some(in_case) = self.braces.last_mut() {
+                    if *in_case {
+                        *in_case = false;
+
+                        self.indent -= 1;
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        let space = self.space_before(token);
+
+        self.comments(token);
+
+        if token.token_type == TokenType::Eof {
+            if !self.output.is_empty() {
+                self.output.push('\n');
+            }
+
+            return;
+        }
+
+        if self.line_break {
+            self.new_line(start_line(token), token.token_type != TokenType::RightBrace);
+        } else if space {
+            self.output.push(' ');
+        }
+
+        self.output.push_str(&token.lexeme);
+
+        self.last_line = token.line;
+
+        self.previous_is_unary = matches!(
+            token.token_type,
+            TokenType::Bang | TokenType::Minus | TokenType::Tilde
+        ) && !self.opt_previous.as_ref().is_some_and(ends_operand);
+
+        match token.token_type {
+            TokenType::LeftParen => self.parens += 1,
+            TokenType::RightParen => self.parens = self.parens.saturating_sub(1),
+            TokenType::LeftBrace if starts_pattern(rest) => self.in_pattern = true,
+            TokenType::RightBrace if self.in_pattern => self.in_pattern = false,
+            TokenType::LeftBrace => {
+                self.indent += 1;
+
+                self.braces.push(false);
+
+                self.do_bodies.push(
+                    self.opt_previous
+                        .as_ref()
+                        .is_some_and(|previous| previous.token_type == TokenType::Do),
+                );
+
+                self.line_break = next_type != Some(&TokenType::RightBrace);
+            }
+            TokenType::RightBrace => {
+                self.line_break = !(closes_do_body && next_type == Some(&TokenType::While)
+                    || matches!(
+                        next_type,
+                        Some(
+                            TokenType::Else
+                                | TokenType::Catch
+                                | TokenType::Finally
+                                | TokenType::SemiColon
+                                | TokenType::RightParen
+                                | TokenType::Comma
+                                | TokenType::Dot
+                                | TokenType::QuestionDot
+                        )
+                    ));
+            }
+            TokenType::Colon => {
+                if let Some(in_case) = self.braces.last_mut() {
+                    *in_case = true;
+
+                    self.indent += 1;
+                }
+
+                self.line_break = true;
+            }
+            TokenType::Do if next_type != Some(&TokenType::LeftBrace) => self.braceless_dos += 1,
+            TokenType::SemiColon => {
+                self.line_break = self.parens == 0;
+
+                if self.line_break && self.braceless_dos > 0 && next_type == Some(&TokenType::While)
+                {
+                    self.braceless_dos -= 1;
+
+                    self.line_break = false;
+                }
+            }
+            _ => (),
+        }
+
+        self.opt_previous = Some(token.clone());
+    }
+
+    /// Writes the comments before `token`. One on the line of the previous
+    /// token stays at the end of that line; the rest get lines of their own.
+    fn comments(&mut self, token: &Token) {
+        for comment in &token.comments {
+            if comment.line == self.last_line && !self.output.is_empty() {
+                self.output.push(' ');
+            } else {
+                let allow_blank = token.token_type != TokenType::RightBrace;
+
+                self.new_line(comment.line, allow_blank);
+            }
+
+            self.output.push_str(&comment.text);
+
+            self.last_line = comment.line;
+            self.line_break = true;
+        }
+    }
+
+    /// Starts a new, indented line for something that started on `line` in
+    /// the source, keeping at most one blank line from the original.
+    fn new_line(&mut self, line: usize, allow_blank: bool) {
+        if !self.output.is_empty() {
+            if allow_blank && line > self.last_line + 1 && !self.output.ends_with('{') {
+                self.output.push('\n');
+            }
+
+            self.output.push('\n');
+        }
+
+        for _ in 0..self.indent {
+            self.output.push_str(INDENT);
+        }
+
+        self.line_break = false;
+    }
+
+    fn space_before(&self, token: &Token) -> bool {
+        let previous = match &self.opt_previous {
+            Some(previous) => previous,
+            None => return false,
+        };
+
+        if self.previous_is_unary || is_continuation(token) {
+            return false;
+        }
+
+        match previous.token_type {
+            TokenType::LeftParen
+            | TokenType::Dot
+            | TokenType::QuestionDot
+            | TokenType::DotDot
+            | TokenType::Ellipsis
+            | TokenType::Interpolation => return false,
+            TokenType::LeftBrace => {
+                return !self.in_pattern && token.token_type != TokenType::RightBrace
+            }
+            TokenType::SemiColon => {
+                return !matches!(
+                    token.token_type,
+                    TokenType::SemiColon | TokenType::RightParen
+                )
+            }
+            _ => (),
+        }
+
+        match token.token_type {
+            TokenType::SemiColon
+            | TokenType::Comma
+            | TokenType::Dot
+            | TokenType::QuestionDot
+            | TokenType::DotDot
+            | TokenType::Colon
+            | TokenType::RightParen => false,
+            TokenType::RightBrace => !self.in_pattern,
+            TokenType::LeftParen => !ends_operand(previous),
+            _ => true,
+        }
+    }
+}
+
+/// Turns the extension keywords the parser reads as identifiers into
+/// identifiers, working out which ones those are from their neighbours, so
+/// they're spaced like names.
+fn demote_extension_keywords(tokens: &mut [Token]) {
+    for index in 0..tokens.len() {
+        let opt_previous = index.checked_sub(1).map(|previous| &tokens[previous]);
+        let opt_next_type = tokens.get(index + 1).map(|next| next.token_type);
+
+        let starts_statement = opt_previous.is_none_or(|previous| {
+            matches!(
+                previous.token_type,
+                TokenType::SemiColon
+                    | TokenType::LeftBrace
+                    | TokenType::RightBrace
+                    | TokenType::RightParen
+                    | TokenType::Colon
+                    | TokenType::Else
+                    | TokenType::Do
+            )
+        });
+
+        let is_keyword = match tokens[index].token_type {
+            TokenType::Case | TokenType::Default => {
+                starts_statement
+                    && !opt_next_type
+                        .is_some_and(|next| next != TokenType::Colon && next.follows_operand())
+            }
+            TokenType::Catch | TokenType::Finally => {
+                opt_previous.is_some_and(|previous| previous.token_type == TokenType::RightBrace)
+            }
+            TokenType::Is => opt_previous.is_some_and(ends_operand),
+            TokenType::Const
+            | TokenType::Do
+            | TokenType::Switch
+            | TokenType::Throw
+            | TokenType::Try => {
+                starts_statement && !opt_next_type.is_some_and(TokenType::follows_operand)
+            }
+            _ => continue,
+        };
+
+        if !is_keyword {
+            tokens[index].token_type = TokenType::Identifier;
+        }
+    }
+}
+
+/// Whether `token` can end an operand, making a following `-` binary and a
+/// following `(` a call.
+fn ends_operand(token: &Token) -> bool {
+    matches!(
+        token.token_type,
+        TokenType::Identifier
+            | TokenType::String
+            | TokenType::Number
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Nil
+            | TokenType::This
+            | TokenType::Super
+            | TokenType::RightParen
+    )
+}
+
+/// Whether the tokens after a `{` are the rest of a pattern like
+/// `{x, y} = point`: names, then `}` and `=`. A block never looks like that.
+fn starts_pattern(rest: &[Token]) -> bool {
+    let mut index = 0;
+
+    while rest
+        .get(index)
+        .is_some_and(|name| name.token_type == TokenType::Identifier)
+    {
+        match rest.get(index + 1).map(|token| token.token_type) {
+            Some(TokenType::Comma) => index += 2,
+            Some(TokenType::RightBrace) => {
+                return rest
+                    .get(index + 2)
+                    .is_some_and(|token| token.token_type == TokenType::Equal)
+            }
+            _ => return false,
+        }
+    }
+
+    false
+}
+
+/// Whether `token` is a string segment that resumes after an interpolated
+/// expression.
+fn is_continuation(token: &Token) -> bool {
+    matches!(
+        token.token_type,
+        TokenType::String | TokenType::Interpolation
+    ) && token.lexeme.starts_with('}')
+}
+
+/// The line a token starts on. A string's line is the one it ends on.
+fn start_line(token: &Token) -> usize {
+    token.line - token.lexeme.matches('\n').count()
+}
@@ -1,64 +1,198 @@
-use std::{cell::RefCell, fmt, rc::Rc};
+use std::fmt;
 
 use crate::{
     ast::Stmt,
     environment::Environment,
     interpreter::{Interpreter, InterpreterError},
     lox_type::LoxType,
+    shared::{Locked, Shared},
     token::Token,
 };
 
+/// How many arguments a function accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+    Range(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(self, count: usize) -> bool {
+        use Arity::*;
+
+        match self {
+            Exact(n) => count == n,
+            AtLeast(min) => count >= min,
+            Range(min, max) => count >= min && count <= max,
+        }
+    }
+}
+
+impl Arity {
+    /// The arity left once the first argument is supplied.
+    fn after_first(self) -> Self {
+        use Arity::*;
+
+        match self {
+            Exact(n) => Exact(n.saturating_sub(1)),
+            AtLeast(min) => AtLeast(min.saturating_sub(1)),
+            Range(min, max) => Range(min.saturating_sub(1), max.saturating_sub(1)),
+        }
+    }
+}
+
+impl From<usize> for Arity {
+    fn from(n: usize) -> Self {
+        Arity::Exact(n)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Arity::*;
+
+        match self {
+            Exact(n) => write!(f, "{}", n),
+            AtLeast(min) => write!(f, "at least {}", min),
+            Range(min, max) => write!(f, "{} to {}", min, max),
+        }
+    }
+}
+
+/// The body of a native function. Closures may capture host state.
+#[cfg(not(feature = "sync"))]
+pub type NativeFn =
+    Shared<dyn Fn(&mut Interpreter, &[LoxType]) -> Result<LoxType, InterpreterError>>;
+
+/// The body of a native function. Closures may capture host state.
+#[cfg(feature = "sync")]
+pub type NativeFn =
+    Shared<dyn Fn(&mut Interpreter, &[LoxType]) -> Result<LoxType, InterpreterError> + Send + Sync>;
+
 #[derive(Clone)]
 pub enum Function {
+    /// A function added to a class at runtime by `define_method`. Bound to
+    /// an instance, it receives the instance as its first argument.
+    Attached {
+        function: Box<Function>,
+        opt_receiver: Option<Box<LoxType>>,
+    },
     Native {
-        arity: usize,
-        body: fn(&[LoxType]) -> Result<LoxType, InterpreterError>,
+        arity: Arity,
+        body: NativeFn,
     },
     User {
-        name: Box<Token>,
-        params: Vec<Token>,
-        body: Vec<Stmt>,
-        closure: Rc<RefCell<Environment>>,
+        name: Shared<Token>,
+        params: Shared<[Token]>,
+        /// Name of the parameter that collects the arguments past `params`
+        /// into a tuple.
+        opt_rest: Option<Shared<str>>,
+        body: Shared<[Stmt]>,
+        closure: Shared<Locked<Environment>>,
         is_initializer: bool,
     },
 }
 
 impl Function {
-    pub fn arity(&self) -> usize {
+    pub fn arity(&self) -> Arity {
         use Function::*;
 
         match self {
+            Attached {
+                function,
+                opt_receiver: Some(_),
+            } => function.arity().after_first(),
+            Attached { function, .. } => function.arity(),
             Native { arity, .. } => *arity,
-            User { params, .. } => params.len(),
+            User {
+                params,
+                opt_rest: Some(_),
+                ..
+            } => Arity::AtLeast(params.len()),
+            User { params, .. } => Arity::Exact(params.len()),
         }
     }
 
+    /// Calls the function. Calls in tail position run in a loop here rather
+    /// than nesting, so tail recursion doesn't grow the stack.
     pub fn call(
         &self,
         interpreter: &mut Interpreter,
         arguments: &[LoxType],
+    ) -> Result<LoxType, InterpreterError> {
+        let mut res = self.call_once(interpreter, arguments, false);
+
+        while let Err(InterpreterError::TailCall(tail_call)) = res {
+            let (function, arguments) = *tail_call;
+
+            res = function.call_once(interpreter, &arguments, true);
+        }
+
+        res
+    }
+
+    fn call_once(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[LoxType],
+        is_tail_call: bool,
     ) -> Result<LoxType, InterpreterError> {
         use Function::*;
 
         match self {
-            Native { body, .. } => body(arguments),
+            Attached {
+                function,
+                opt_receiver: Some(receiver),
+            } => {
+                let mut arguments = arguments.to_vec();
+
+                arguments.insert(0, (**receiver).clone());
+
+                function.call_once(interpreter, &arguments, is_tail_call)
+            }
+            Attached { function, .. } => function.call_once(interpreter, arguments, is_tail_call),
+            Native { body, .. } => body(interpreter, arguments),
             User {
+                name,
                 body,
                 params,
+                opt_rest,
                 closure,
                 is_initializer,
-                ..
             } => {
-                let env = Rc::new(RefCell::new(Environment::with_enclosing(closure)));
+                let env = Environment::child(closure);
 
                 for (param, arg) in params.iter().zip(arguments) {
-                    env.borrow_mut().define(&param.lexeme, arg.clone());
+                    env.borrow_mut()
+                        .define(Shared::clone(&param.lexeme), arg.clone());
+                }
+
+                if let Some(rest) = opt_rest {
+                    let extra = arguments.get(params.len()..).unwrap_or_default();
+
+                    env.borrow_mut()
+                        .define(Shared::clone(rest), LoxType::Tuple(extra.into()));
                 }
 
-                match interpreter.execute_block(body, env) {
+                interpreter.enter_call(name)?;
+
+                interpreter.notify_hook(|hook, interpreter| {
+                    if is_tail_call {
+                        hook.on_tail_call(interpreter, name, arguments)
+                    } else {
+                        hook.on_call(interpreter, name, arguments)
+                    }
+                });
+
+                let res = interpreter.execute_block(body, env);
+
+                interpreter.exit_call();
+
+                let res = match res {
                     Ok(()) => {
                         if *is_initializer {
-                            if let Some(value) = closure.borrow().get_at(0, "this") {
+                            if let Some(value) = closure.borrow().get("this") {
                                 Ok(value)
                             } else {
                                 Err(InterpreterError::runtime_error(
@@ -72,7 +206,7 @@ impl Function {
                     }
                     Err(InterpreterError::Return(value)) => {
                         if *is_initializer {
-                            if let Some(value) = closure.borrow().get_at(0, "this") {
+                            if let Some(value) = closure.borrow().get("this") {
                                 Ok(value)
                             } else {
                                 Err(InterpreterError::runtime_error(
@@ -85,27 +219,40 @@ impl Function {
                         }
                     }
                     Err(err) => Err(err),
+                };
+
+                if let Ok(ref value) = res {
+                    interpreter
+                        .notify_hook(|hook, interpreter| hook.on_return(interpreter, name, value));
                 }
+
+                res
             }
         }
     }
 
     pub fn bind(&self, instance: LoxType) -> Self {
         match self {
+            Self::Attached { function, .. } => Self::Attached {
+                function: function.clone(),
+                opt_receiver: Some(Box::new(instance)),
+            },
             Self::User {
                 name,
                 params,
+                opt_rest,
                 body,
                 closure,
                 is_initializer,
             } => {
-                let env = Rc::new(RefCell::new(Environment::with_enclosing(closure)));
+                let env = Environment::child(closure);
 
-                env.borrow_mut().define("this", instance);
+                env.borrow_mut().define(Shared::from("this"), instance);
 
                 Self::User {
                     name: name.clone(),
                     params: params.clone(),
+                    opt_rest: opt_rest.clone(),
                     body: body.clone(),
                     closure: env,
                     is_initializer: *is_initializer,
@@ -116,11 +263,69 @@ impl Function {
     }
 }
 
+/// Functions are equal when they are the same value: the same native, or the
+/// same declaration closed over the same environment. Each `instance.method`
+/// binds a new function, so bound methods are equal when they bind the same
+/// method to the same instance.
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        use Function::*;
+
+        match (self, other) {
+            (
+                Attached {
+                    function: f,
+                    opt_receiver: f_receiver,
+                },
+                Attached {
+                    function: g,
+                    opt_receiver: g_receiver,
+                },
+            ) => f == g && f_receiver == g_receiver,
+            (Native { body: f, .. }, Native { body: g, .. }) => Shared::ptr_eq(f, g),
+            (
+                User {
+                    body: f,
+                    closure: f_closure,
+                    ..
+                },
+                User {
+                    body: g,
+                    closure: g_closure,
+                    ..
+                },
+            ) => {
+                Shared::ptr_eq(f, g)
+                    && (Shared::ptr_eq(f_closure, g_closure)
+                        || is_same_binding(f_closure, g_closure))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether two closures are the scopes `bind` made for the same method and
+/// the same `this`.
+fn is_same_binding(f_closure: &Locked<Environment>, g_closure: &Locked<Environment>) -> bool {
+    let (f_closure, g_closure) = (f_closure.borrow(), g_closure.borrow());
+
+    let is_same_enclosing = match (&f_closure.enclosing, &g_closure.enclosing) {
+        (Some(f_enclosing), Some(g_enclosing)) => Shared::ptr_eq(f_enclosing, g_enclosing),
+        _ => false,
+    };
+
+    match (f_closure.get("this"), g_closure.get("this")) {
+        (Some(f_this), Some(g_this)) => is_same_enclosing && f_this == g_this,
+        _ => false,
+    }
+}
+
 impl fmt::Debug for Function {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use Function::*;
 
         match self {
+            Attached { function, .. } => write!(f, "{}", function),
             Native { .. } => write!(f, "<native func>"),
             User { name, .. } => write!(f, "<fn {}>", name.lexeme),
         }
@@ -132,6 +337,7 @@ impl fmt::Display for Function {
         use Function::*;
 
         match self {
+            Attached { function, .. } => write!(f, "{}", function),
             Native { .. } => write!(f, "<native func>"),
             User { name, .. } => write!(f, "<fn {}>", name.lexeme),
         }
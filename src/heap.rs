@@ -0,0 +1,332 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasherDefault, Hasher},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    class::{LoxClass, LoxInstance},
+    environment::Environment,
+    function::Function,
+    lox_type::LoxType,
+    shared::{Locked, Shared, Weak},
+};
+
+/// Tracks the environments and instances a program allocates.
+///
+/// Values are reference counted, so a closure stored in a local variable, or
+/// an instance holding a method bound to itself, forms a cycle that is never
+/// freed. `collect` finds the objects no longer reachable from the roots and
+/// clears them, which breaks their cycles and lets the counts drop to zero.
+///
+/// Besides the roots it's given, any object with more references than the
+/// heap can account for is held from outside, e.g. by a host or the Rust
+/// stack, and is kept along with everything it reaches.
+#[derive(Debug, Default)]
+pub struct Heap {
+    environments: Locked<Vec<Weak<Locked<Environment>>>>,
+    instances: Locked<Vec<Weak<Locked<LoxInstance>>>>,
+    created: AtomicUsize,
+    collected: AtomicUsize,
+}
+
+enum Object {
+    Environment(Shared<Locked<Environment>>),
+    Class(Shared<Locked<LoxClass>>),
+    Instance(Shared<Locked<LoxInstance>>),
+    Tuple(Shared<[LoxType]>),
+}
+
+impl Object {
+    /// The address of the object, which identifies it whatever its kind.
+    fn address(&self) -> *const () {
+        match self {
+            Object::Environment(env) => Shared::as_ptr(env).cast(),
+            Object::Class(class) => Shared::as_ptr(class).cast(),
+            Object::Instance(instance) => Shared::as_ptr(instance).cast(),
+            Object::Tuple(values) => Shared::as_ptr(values).cast(),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Environment(env) => Shared::strong_count(env),
+            Object::Class(class) => Shared::strong_count(class),
+            Object::Instance(instance) => Shared::strong_count(instance),
+            Object::Tuple(values) => Shared::strong_count(values),
+        }
+    }
+
+    /// Pushes every object this one holds a reference to.
+    fn references(&self, pending: &mut Vec<Object>) {
+        match self {
+            Object::Environment(env) => {
+                let env = env.borrow();
+
+                for value in env.bindings() {
+                    trace(value, pending);
+                }
+
+                if let Some(ref enclosing) = env.enclosing {
+                    pending.push(Object::Environment(Shared::clone(enclosing)));
+                }
+            }
+            Object::Class(class) => {
+                let class = class.borrow();
+
+                for method in class.methods() {
+                    trace_function(method, pending);
+                }
+
+                pending.push(Object::Environment(Shared::clone(class.closure())));
+
+                if let Some(superclass) = class.superclass() {
+                    pending.push(Object::Class(Shared::clone(superclass)));
+                }
+            }
+            Object::Instance(instance) => {
+                let instance = instance.borrow();
+
+                pending.push(Object::Class(Shared::clone(instance.class())));
+
+                for value in instance.fields() {
+                    trace(value, pending);
+                }
+            }
+            Object::Tuple(values) => {
+                for value in values.iter() {
+                    trace(value, pending);
+                }
+            }
+        }
+    }
+}
+
+impl Heap {
+    pub fn track_environment(&self, env: &Shared<Locked<Environment>>) {
+        self.created.fetch_add(1, Ordering::Relaxed);
+
+        self.environments.borrow_mut().push(Shared::downgrade(env));
+    }
+
+    pub fn track_instance(&self, instance: &Shared<Locked<LoxInstance>>) {
+        self.instances
+            .borrow_mut()
+            .push(Shared::downgrade(instance));
+    }
+
+    /// Environments created so far, not counting the global one.
+    pub fn created(&self) -> usize {
+        self.created.load(Ordering::Relaxed)
+    }
+
+    /// Objects cleared by every collection so far.
+    pub fn collected(&self) -> usize {
+        self.collected.load(Ordering::Relaxed)
+    }
+
+    /// Objects tracked since they were allocated that may still be alive.
+    pub fn tracked(&self) -> usize {
+        self.environments.borrow().len() + self.instances.borrow().len()
+    }
+
+    /// Clears every tracked object that can't be reached from `roots` or
+    /// from outside the heap, and returns how many there were.
+    pub fn collect(&self, roots: &[Shared<Locked<Environment>>]) -> usize {
+        let graph = Graph::build(self, roots);
+
+        let marks = graph.mark();
+
+        let mut garbage = Vec::new();
+
+        for (object, is_marked) in graph.objects.into_iter().zip(marks) {
+            if !is_marked && matches!(object, Object::Environment(_) | Object::Instance(_)) {
+                garbage.push(object);
+            }
+        }
+
+        let count = garbage.len();
+
+        for object in &garbage {
+            match object {
+                Object::Environment(env) => env.borrow_mut().clear(),
+                Object::Instance(instance) => instance.borrow_mut().clear(),
+                _ => (),
+            }
+        }
+
+        drop(garbage);
+
+        self.environments
+            .borrow_mut()
+            .retain(|env| env.strong_count() > 0);
+        self.instances
+            .borrow_mut()
+            .retain(|instance| instance.strong_count() > 0);
+
+        self.collected.fetch_add(count, Ordering::Relaxed);
+
+        count
+    }
+}
+
+/// Where each object went in `Graph::objects`, keyed by address.
+type Indexes = HashMap<*const (), usize, BuildHasherDefault<AddressHasher>>;
+
+/// Hashes addresses, which are already unique, by spreading their bits
+/// rather than running a general purpose hash over them.
+#[derive(Default)]
+struct AddressHasher(u64);
+
+impl Hasher for AddressHasher {
+    fn finish(&self) -> u64 {
+        // Addresses are aligned, so the low bits need mixing in from above.
+        self.0 ^ self.0 >> 32
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 << 8 | u64::from(byte)).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        }
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.0 = (n as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+}
+
+/// Every object reachable from the roots and the tracked objects, with the
+/// references between them.
+struct Graph {
+    objects: Vec<Object>,
+    /// Indexes into `objects` of what each object refers to, for one object
+    /// after another.
+    references: Vec<usize>,
+    /// Where each object's run in `references` starts.
+    starts: Vec<usize>,
+    /// How many references to each object come from other objects.
+    referrers: Vec<usize>,
+    /// Indexes of the roots the collection was given.
+    roots: Vec<usize>,
+}
+
+impl Graph {
+    /// Walks from `roots` and from every tracked object still alive.
+    fn build(heap: &Heap, roots: &[Shared<Locked<Environment>>]) -> Self {
+        let mut graph = Graph {
+            objects: Vec::new(),
+            references: Vec::new(),
+            starts: Vec::new(),
+            referrers: Vec::new(),
+            roots: Vec::new(),
+        };
+
+        let mut indexes = HashMap::default();
+
+        for root in roots {
+            let index = graph.add(Object::Environment(Shared::clone(root)), &mut indexes);
+
+            graph.roots.push(index);
+        }
+
+        let tracked = heap
+            .environments
+            .borrow()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(Object::Environment)
+            .chain(
+                heap.instances
+                    .borrow()
+                    .iter()
+                    .filter_map(Weak::upgrade)
+                    .map(Object::Instance),
+            )
+            .collect::<Vec<_>>();
+
+        for object in tracked {
+            graph.add(object, &mut indexes);
+        }
+
+        // Objects are added as they're found, so this visits each once.
+        let mut found = Vec::new();
+        let mut next = 0;
+
+        while next < graph.objects.len() {
+            graph.objects[next].references(&mut found);
+
+            graph.starts.push(graph.references.len());
+
+            for object in found.drain(..) {
+                let index = graph.add(object, &mut indexes);
+
+                graph.referrers[index] += 1;
+                graph.references.push(index);
+            }
+
+            next += 1;
+        }
+
+        graph.starts.push(graph.references.len());
+
+        graph
+    }
+
+    fn add(&mut self, object: Object, indexes: &mut Indexes) -> usize {
+        *indexes.entry(object.address()).or_insert_with(|| {
+            self.objects.push(object);
+            self.referrers.push(0);
+
+            self.objects.len() - 1
+        })
+    }
+
+    /// Which objects are reachable from the roots or from those with more
+    /// strong references than the graph accounts for. Each object is also
+    /// held once by `objects` itself.
+    fn mark(&self) -> Vec<bool> {
+        let mut marks = vec![false; self.objects.len()];
+
+        let mut pending: Vec<usize> = (0..self.objects.len())
+            .filter(|&index| self.objects[index].strong_count() - 1 > self.referrers[index])
+            .chain(self.roots.iter().copied())
+            .collect();
+
+        while let Some(index) = pending.pop() {
+            if !marks[index] {
+                marks[index] = true;
+
+                pending.extend(&self.references[self.starts[index]..self.starts[index + 1]]);
+            }
+        }
+
+        marks
+    }
+}
+
+fn trace(value: &LoxType, pending: &mut Vec<Object>) {
+    match value {
+        LoxType::Callable(function) => trace_function(function, pending),
+        LoxType::Class(class) => pending.push(Object::Class(Shared::clone(class))),
+        LoxType::Instance(instance) => pending.push(Object::Instance(Shared::clone(instance))),
+        LoxType::Tuple(values) => pending.push(Object::Tuple(Shared::clone(values))),
+        _ => (),
+    }
+}
+
+fn trace_function(function: &Function, pending: &mut Vec<Object>) {
+    match function {
+        Function::Attached {
+            function,
+            opt_receiver,
+        } => {
+            trace_function(function, pending);
+
+            if let Some(receiver) = opt_receiver {
+                trace(receiver, pending);
+            }
+        }
+        Function::User { closure, .. } => pending.push(Object::Environment(Shared::clone(closure))),
+        Function::Native { .. } => (),
+    }
+}
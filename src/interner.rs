@@ -0,0 +1,24 @@
+use std::collections::HashSet;
+
+use crate::shared::Shared;
+
+/// Hands out one shared allocation per distinct string, so tokens,
+/// environments and instances can copy names without allocating.
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: HashSet<Shared<str>>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, s: &str) -> Shared<str> {
+        if let Some(interned) = self.strings.get(s) {
+            return Shared::clone(interned);
+        }
+
+        let interned: Shared<str> = Shared::from(s);
+
+        self.strings.insert(Shared::clone(&interned));
+
+        interned
+    }
+}
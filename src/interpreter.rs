@@ -1,29 +1,71 @@
 use std::{
-    cell::RefCell,
     collections::HashMap,
-    rc::Rc,
-    time::{SystemTime, UNIX_EPOCH},
+    fs,
+    io::{stdout, Write},
+    path::{Path, PathBuf},
+    slice,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
+use indexmap::IndexMap;
+
 use crate::{
-    ast::{Expr, Stmt},
-    class::{LoxClass, LoxInstance},
+    ast::{Expr, ExprId, ExprVisitor, Stmt, StmtVisitor},
+    class::{is_private, LoxClass, LoxInstance},
+    coverage::Coverage,
     environment::Environment,
-    function::Function,
-    lox,
+    error::Diagnostics,
+    function::{Arity, Function},
+    lox::{Limits, Options},
     lox_type::LoxType,
+    natives, optimizer,
+    origin::Origins,
+    parser::Parser,
+    profile::Profile,
+    random::Random,
+    resolver::Resolver,
+    scanner::Scanner,
+    shared::{Locked, MaybeSync, Shared},
+    stack::StackGuard,
+    stats::Stats,
     token::Token,
     token_type::TokenType,
 };
 
+/// Tracked objects allocated before the first garbage collection.
+const GC_THRESHOLD: usize = 1024;
+
 pub enum InterpreterError {
-    RuntimeError(RuntimeError),
+    RuntimeError(Box<RuntimeError>),
     Return(LoxType),
+    /// A value raised by `throw`, along with the `throw` keyword.
+    Thrown(LoxType, Box<Token>),
+    /// `return f(...)` of a user function, unwound so the caller can run it
+    /// without growing the stack.
+    TailCall(Box<(Function, Vec<LoxType>)>),
+    /// `exit(code)`, unwinding to whoever is running the script.
+    Exit(i32),
 }
 
 impl InterpreterError {
     pub fn runtime_error(token: Option<Token>, message: &str) -> Self {
-        Self::RuntimeError(RuntimeError::new(token, message))
+        Self::RuntimeError(Box::new(RuntimeError::new(token, message)))
+    }
+
+    /// Reports a runtime error that doesn't carry a location yet at `token`.
+    pub fn or_at(self, token: &Token) -> Self {
+        match self {
+            Self::RuntimeError(mut err) if err.token.is_none() => {
+                err.token = Some(token.clone());
+
+                Self::RuntimeError(err)
+            }
+            err => err,
+        }
     }
 }
 
@@ -41,187 +83,548 @@ impl RuntimeError {
     }
 }
 
+/// Stops an interpreter from another thread, e.g. when a host gives up
+/// waiting for a script.
+#[derive(Clone)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Makes the interpreter fail with "Interrupted." at the next statement
+    /// it runs. Every statement keeps failing until the error reaches the
+    /// host, so `catch` and `finally` can't keep the script going. An
+    /// interrupt sent while nothing is running is dropped when the next run
+    /// starts.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+enum ModuleState {
+    Loading,
+    Loaded,
+}
+
+/// What a for-in loop walks over.
+pub(crate) enum Iteration {
+    /// Values known up front, from a range or a string.
+    Values(Box<dyn Iterator<Item = LoxType>>),
+    /// An instance following the iterator protocol: `done()` says whether
+    /// it's exhausted and `next()` returns the next value.
+    Protocol(LoxType),
+}
+
+/// Where `print` statements, REPL echo and error reports go.
+#[cfg(not(feature = "sync"))]
+pub type Output = Box<dyn Write>;
+
+/// Where `print` statements, REPL echo and error reports go.
+#[cfg(feature = "sync")]
+pub type Output = Box<dyn Write + Send>;
+
+/// Observes execution, e.g. to implement a debugger. Installed with
+/// `Interpreter::set_hook`.
+pub trait Hook: MaybeSync {
+    fn before_statement(
+        &mut self,
+        interpreter: &Interpreter,
+        stmt: &Stmt,
+    ) -> Result<(), InterpreterError>;
+
+    /// Called when a user function is entered, after its arguments are
+    /// bound.
+    fn on_call(&mut self, _interpreter: &Interpreter, _name: &Token, _arguments: &[LoxType]) {}
+
+    /// Called instead of `on_call` when a user function is entered through
+    /// a tail call, which replaces the caller's frame rather than nesting.
+    fn on_tail_call(&mut self, interpreter: &Interpreter, name: &Token, arguments: &[LoxType]) {
+        self.on_call(interpreter, name, arguments);
+    }
+
+    /// Called when a user function returns a value. Calls that raise an
+    /// error or hand off to a tail call don't return here, so a chain of
+    /// tail calls returns once, from the last function called.
+    fn on_return(&mut self, _interpreter: &Interpreter, _name: &Token, _value: &LoxType) {}
+}
+
 pub struct Interpreter {
-    globals: Rc<RefCell<Environment>>,
-    env: Rc<RefCell<Environment>>,
-    locals: HashMap<Token, usize>,
+    globals: Shared<Locked<Environment>>,
+    env: Shared<Locked<Environment>>,
+    /// Scope distance and slot of each resolved local variable use.
+    locals: HashMap<ExprId, (usize, usize)>,
+    modules: HashMap<PathBuf, ModuleState>,
+    script: PathBuf,
+    origins: Option<Origins>,
+    args: Vec<String>,
+    hook: Option<Box<dyn Hook>>,
+    output: Output,
+    call_depth: usize,
+    /// Measures the stack from roughly where the current run began.
+    stack: StackGuard,
+    statements: usize,
+    calls: usize,
+    random: Random,
+    /// When `Limits::opt_max_time` runs out.
+    opt_deadline: Option<Instant>,
+    /// Whether the deadline has been seen to pass, after which every
+    /// statement fails without reading the clock.
+    is_past_deadline: bool,
+    /// Set through an `InterruptHandle`.
+    interrupted: Arc<AtomicBool>,
+    opt_profile: Option<Profile>,
+    opt_coverage: Option<Coverage>,
+    /// Tracked objects that trigger the next garbage collection.
+    next_gc: usize,
+    options: Options,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        let env = Rc::new(RefCell::new(Environment::new()));
+        Self::with_options(Options::default())
+    }
+
+    pub fn with_options(options: Options) -> Self {
+        Self::with_output(options, Box::new(stdout()))
+    }
+
+    /// An interpreter for untrusted code, which gives up on programs that
+    /// go over `limits`.
+    pub fn with_limits(limits: Limits) -> Self {
+        Self::with_options(Options {
+            limits,
+            ..Options::default()
+        })
+    }
+
+    pub fn with_output(options: Options, output: Output) -> Self {
+        let env = Shared::new(Locked::new(Environment::new()));
+
+        natives::define_globals(&mut env.borrow_mut(), &options.capabilities);
+
+        Self {
+            globals: Shared::clone(&env),
+            env: Shared::clone(&env),
+            locals: HashMap::new(),
+            modules: HashMap::new(),
+            script: PathBuf::from("<repl>"),
+            origins: if options.track_origins {
+                Some(Origins::default())
+            } else {
+                None
+            },
+            args: Vec::new(),
+            hook: None,
+            output,
+            call_depth: 0,
+            stack: StackGuard::new(options.limits.stack_size),
+            statements: 0,
+            calls: 0,
+            random: Random::default(),
+            opt_deadline: options
+                .limits
+                .opt_max_time
+                .map(|max_time| Instant::now() + max_time),
+            is_past_deadline: false,
+            interrupted: Arc::new(AtomicBool::new(false)),
+            opt_profile: if options.profile {
+                Some(Profile::default())
+            } else {
+                None
+            },
+            opt_coverage: if options.coverage || options.opt_lcov_path.is_some() {
+                Some(Coverage::default())
+            } else {
+                None
+            },
+            next_gc: GC_THRESHOLD,
+            options,
+        }
+    }
+
+    /// Names of every global variable, for completion in the REPL.
+    pub(crate) fn is_global(&self, name: &str) -> bool {
+        self.globals.borrow().get(name).is_some()
+    }
+
+    pub fn global_names(&self) -> Vec<String> {
+        self.globals
+            .borrow()
+            .values()
+            .into_iter()
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
 
-        env.borrow_mut().define(
-            "clock",
+    /// Defines a global native function, letting host programs extend the
+    /// language without touching the interpreter.
+    pub fn define_native<A, F>(&mut self, name: &str, arity: A, body: F)
+    where
+        A: Into<Arity>,
+        F: Fn(&mut Interpreter, &[LoxType]) -> Result<LoxType, InterpreterError>
+            + MaybeSync
+            + 'static,
+    {
+        self.globals.borrow_mut().define(
+            Shared::from(name),
             LoxType::Callable(Function::Native {
-                arity: 0,
-                body: |_| {
-                    SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .map(|duration| LoxType::Number(duration.as_millis() as f64))
-                        .map_err(|_| {
-                            InterpreterError::runtime_error(None, "could not retrieve time.")
-                        })
-                },
+                arity: arity.into(),
+                body: Shared::new(body),
             }),
         );
+    }
 
-        Self {
-            globals: Rc::clone(&env),
-            env: Rc::clone(&env),
-            locals: HashMap::new(),
+    /// Calls a function or class from host code, e.g. a callback a script
+    /// defined, the way a call in Lox would. Values the host holds keep
+    /// their closures alive, so this works however long ago they were made.
+    pub fn call_function(
+        &mut self,
+        callee: &LoxType,
+        arguments: &[LoxType],
+    ) -> Result<LoxType, InterpreterError> {
+        self.start_run();
+
+        self.call(callee.clone(), None, arguments.to_vec())
+    }
+
+    /// Like `call_function`, but calls the global named `name`.
+    pub fn call_global(
+        &mut self,
+        name: &str,
+        arguments: &[LoxType],
+    ) -> Result<LoxType, InterpreterError> {
+        let callee = self.globals.borrow().get(name).ok_or_else(|| {
+            InterpreterError::runtime_error(None, &format!("Undefined variable '{}'.", name))
+        })?;
+
+        self.call_function(&callee, arguments)
+    }
+
+    /// A handle that stops this interpreter from another thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(Arc::clone(&self.interrupted))
+    }
+
+    /// Defines a global variable, e.g. to hand a script values from the host.
+    pub fn define_global(&mut self, name: &str, value: LoxType) {
+        self.globals.borrow_mut().define(Shared::from(name), value);
+    }
+
+    /// Sets the command-line arguments exposed to scripts through `arg_count`
+    /// and `arg`.
+    pub fn set_args(&mut self, args: &[String]) {
+        self.args = args.to_vec();
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// The generator behind the random number natives.
+    pub(crate) fn random(&mut self) -> &mut Random {
+        &mut self.random
+    }
+
+    /// The sink `print` statements, REPL echo and error reports write to.
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut *self.output
+    }
+
+    pub fn set_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hook = Some(hook);
+    }
+
+    /// The environment statements are currently executing in.
+    pub fn environment(&self) -> Shared<Locked<Environment>> {
+        Shared::clone(&self.env)
+    }
+
+    /// The number of user function calls currently on the stack.
+    pub fn call_depth(&self) -> usize {
+        self.call_depth
+    }
+
+    pub(crate) fn enter_call(&mut self, name: &Token) -> Result<(), InterpreterError> {
+        if self.call_depth >= self.options.limits.max_call_depth || self.stack.is_exhausted() {
+            return Err(InterpreterError::runtime_error(None, "Stack overflow."));
+        }
+
+        self.call_depth += 1;
+        self.calls += 1;
+
+        if let Some(ref mut profile) = self.opt_profile {
+            profile.enter(name);
         }
+
+        Ok(())
     }
 
-    pub fn interpret(&mut self, statements: &[Stmt]) {
-        for statement in statements {
-            if let Err(err) = self.execute(statement) {
-                lox::runtime_error(err);
+    pub(crate) fn exit_call(&mut self) {
+        self.call_depth -= 1;
 
-                break;
-            }
+        if let Some(ref mut profile) = self.opt_profile {
+            profile.exit();
+        }
+    }
+
+    /// Runs `f` with the installed hook, if any.
+    pub(crate) fn notify_hook(&mut self, f: impl FnOnce(&mut dyn Hook, &Interpreter)) {
+        if let Some(mut hook) = self.hook.take() {
+            f(hook.as_mut(), self);
+
+            self.hook = Some(hook);
         }
     }
 
-    pub fn resolve(&mut self, name: &Token, depth: usize) {
-        self.locals.insert(name.clone(), depth);
+    /// Starts tracking which lines of `statements`, parsed from the current
+    /// script, run. Does nothing unless coverage is on.
+    pub fn track_coverage(&mut self, statements: &[Stmt]) {
+        if let Some(ref mut coverage) = self.opt_coverage {
+            coverage.add_file(&self.script, statements);
+        }
     }
 
-    fn execute(&mut self, stmt: &Stmt) -> Result<(), InterpreterError> {
-        match stmt {
-            Stmt::Block(stmts) => {
-                self.execute_block(
-                    stmts,
-                    Rc::new(RefCell::new(Environment::with_enclosing(&self.env))),
-                )?;
-            }
-            Stmt::Class {
-                name,
-                methods,
-                opt_superclass,
-            } => {
-                let superclass_value = opt_superclass
-                    .as_ref()
-                    .map(|expr| {
-                        if let LoxType::Class(class) = self.evaluate(&expr)? {
-                            Ok(Rc::clone(&class))
-                        } else if let Expr::Variable(name) = expr {
-                            Err(InterpreterError::runtime_error(
-                                Some(name.clone()),
-                                "Superclass must be a class.",
-                            ))
-                        } else {
-                            unreachable!();
-                        }
-                    })
-                    .transpose()?;
-
-                self.env.borrow_mut().define(&name.lexeme, LoxType::Nil);
-
-                if let Some(ref superclass) = superclass_value {
-                    self.env = Rc::new(RefCell::new(Environment::with_enclosing(&self.env)));
-
-                    self.env
-                        .borrow_mut()
-                        .define("super", LoxType::Class(Rc::clone(superclass)));
-                }
+    /// Work counters for everything run so far. `elapsed` is left for the
+    /// caller to fill in.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            statements: self.statements,
+            calls: self.calls,
+            environments: self.globals.borrow().heap().created(),
+            collected: self.globals.borrow().heap().collected(),
+            opt_profile: self.opt_profile.clone(),
+            opt_coverage: self.opt_coverage.clone(),
+            ..Stats::default()
+        }
+    }
 
-                let mut class_methods = HashMap::new();
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
 
-                for method in methods {
-                    if let Stmt::Function {
-                        name: function_name,
-                        params,
-                        body,
-                    } = method
-                    {
-                        let function = Function::User {
-                            name: Box::new(function_name.clone()),
-                            params: params.clone(),
-                            body: body.clone(),
-                            closure: Rc::clone(&self.env),
-                            is_initializer: name.lexeme == "init",
-                        };
-
-                        class_methods.insert(function_name.lexeme.to_string(), function);
-                    } else {
-                        unreachable!()
-                    }
-                }
+    /// Sets the script being run. Relative `import` paths are resolved against
+    /// its directory.
+    pub fn set_script(&mut self, script: &Path) {
+        self.script = script.to_path_buf();
+    }
+
+    /// Records the location of every string literal in `tokens` when origin
+    /// tracking is enabled.
+    pub fn record_origins(&mut self, tokens: &[Token]) {
+        if let Some(ref mut origins) = self.origins {
+            origins.record(&self.script.to_string_lossy(), tokens);
+        }
+    }
 
-                let class = Rc::new(RefCell::new(LoxClass::new(
-                    &name.lexeme,
-                    class_methods,
-                    superclass_value.clone(),
-                )));
+    pub fn origin(&self, value: &LoxType) -> Option<&str> {
+        self.origins
+            .as_ref()
+            .and_then(|origins| origins.lookup(value))
+    }
 
-                if superclass_value.is_some() {
-                    let parent = self.env.borrow().enclosing.clone().unwrap();
+    pub fn interpret(&mut self, statements: &[Stmt], diagnostics: &mut Diagnostics) {
+        self.start_run();
 
-                    self.env = parent;
-                }
+        self.execute_all(statements, diagnostics);
+    }
 
-                self.env
-                    .borrow_mut()
-                    .assign(&name.lexeme, LoxType::Class(class));
-            }
-            Stmt::Expression(expr) => {
-                self.evaluate(expr)?;
-            }
-            Stmt::Function { name, body, params } => {
-                let function = LoxType::Callable(Function::User {
-                    name: Box::new(name.clone()),
-                    body: body.to_vec(),
-                    params: params.to_vec(),
-                    closure: Rc::clone(&self.env),
-                    is_initializer: false,
-                });
+    /// Like `interpret`, but returns the value of the last statement if it's
+    /// an expression, or nil if it isn't. Returns None if the program
+    /// stopped early.
+    pub fn interpret_to_value(
+        &mut self,
+        statements: &[Stmt],
+        diagnostics: &mut Diagnostics,
+    ) -> Option<LoxType> {
+        self.start_run();
+
+        let (opt_last, rest) = match statements.split_last() {
+            Some((Stmt::Expression(expr), rest)) => (Some(expr), rest),
+            _ => (None, statements),
+        };
 
-                self.env.borrow_mut().define(&name.lexeme, function);
-            }
-            Stmt::If {
-                condition,
-                then_branch,
-                opt_else_branch,
-            } => {
-                if bool::from(self.evaluate(condition)?) {
-                    self.execute(then_branch)?;
-                } else if let Some(else_branch) = opt_else_branch {
-                    self.execute(else_branch)?
-                }
+        if !self.execute_all(rest, diagnostics) {
+            return None;
+        }
+
+        let opt_res = opt_last.map(|expr| self.evaluate(expr));
+
+        // The value is held here, which keeps it through a collection.
+        self.maybe_collect_garbage();
+
+        match opt_res {
+            Some(Ok(value)) => Some(value),
+            Some(Err(err)) => {
+                self.report_uncaught(err, diagnostics);
+
+                None
             }
-            Stmt::Print(expr) => {
-                let value = self.evaluate(expr)?;
+            None => Some(LoxType::Nil),
+        }
+    }
+
+    /// Forgets an interrupt left over from before this run, e.g. one that
+    /// arrived while the interpreter was idle.
+    fn start_run(&mut self) {
+        self.interrupted.store(false, Ordering::Relaxed);
+
+        self.stack.reset();
+    }
+
+    /// Runs `statements` until one fails, returning whether they all ran.
+    fn execute_all(&mut self, statements: &[Stmt], diagnostics: &mut Diagnostics) -> bool {
+        for statement in statements {
+            let res = self.execute(statement);
 
-                println!("{}", value);
+            self.maybe_collect_garbage();
+
+            if let Err(err) = res {
+                self.report_uncaught(err, diagnostics);
+
+                return false;
             }
-            Stmt::Return { value, .. } => {
-                let value = match *value {
-                    Expr::Literal(LoxType::Nil) => LoxType::Nil,
-                    _ => self.evaluate(value)?,
-                };
+        }
 
-                return Err(InterpreterError::Return(value));
+        true
+    }
+
+    /// Records an error that escaped to the top level. A thrown instance is
+    /// shown through its `toString()` when that succeeds.
+    fn report_uncaught(&mut self, err: InterpreterError, diagnostics: &mut Diagnostics) {
+        match err {
+            InterpreterError::RuntimeError(err) => diagnostics.runtime_error(&err),
+            InterpreterError::Thrown(value, keyword) => {
+                let message = self.stringify(&value).unwrap_or_else(|_| value.to_string());
+
+                diagnostics.runtime_error(&RuntimeError::new(
+                    Some(*keyword),
+                    &format!("Uncaught exception: {}", message),
+                ))
             }
-            Stmt::Var { name, initializer } => {
-                let value = self.evaluate(initializer)?;
+            InterpreterError::Exit(code) => diagnostics.exit(code),
+            InterpreterError::Return(_) | InterpreterError::TailCall(_) => (),
+        }
+    }
 
-                self.env.borrow_mut().define(&name.lexeme, value);
+    /// Like `interpret`, but a lone expression statement has its value
+    /// printed, the way the REPL echoes results.
+    pub fn interpret_and_echo(&mut self, statements: &[Stmt], diagnostics: &mut Diagnostics) {
+        self.start_run();
+
+        if let [Stmt::Expression(expr)] = statements {
+            let res = self.evaluate(expr).and_then(|value| {
+                let value = self.stringify(&value)?;
+
+                writeln!(self.output, "{}", value).map_err(|err| {
+                    InterpreterError::runtime_error(
+                        None,
+                        &format!("Could not write output: {}.", err),
+                    )
+                })
+            });
+
+            self.maybe_collect_garbage();
+
+            if let Err(err) = res {
+                self.report_uncaught(err, diagnostics);
             }
-            Stmt::While { condition, body } => {
-                while bool::from(self.evaluate(condition)?) {
-                    self.execute(body)?;
-                }
+        } else {
+            self.interpret(statements, diagnostics);
+        }
+    }
+
+    /// Frees environments and instances that are only kept alive by
+    /// reference cycles, returning how many were cleared. Values the host
+    /// still holds, and everything they reach, are kept.
+    pub fn collect_garbage(&mut self) -> usize {
+        let heap = Shared::clone(self.globals.borrow().heap());
+
+        let collected = heap.collect(&[Shared::clone(&self.globals), Shared::clone(&self.env)]);
+
+        self.next_gc = GC_THRESHOLD.max(heap.tracked() * 2);
+
+        collected
+    }
+
+    /// Collects once enough objects have been allocated. Only called between
+    /// top-level statements, so a collection doesn't run in the middle of
+    /// every loop.
+    fn maybe_collect_garbage(&mut self) {
+        if self.globals.borrow().heap().tracked() >= self.next_gc {
+            self.collect_garbage();
+        }
+    }
+
+    pub fn resolve(&mut self, id: ExprId, depth: usize, slot: usize) {
+        self.locals.insert(id, (depth, slot));
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), InterpreterError> {
+        self.statements += 1;
+
+        self.check_budget()?;
+
+        if let Some(ref mut coverage) = self.opt_coverage {
+            if let Some(line) = stmt.line().filter(|_| !matches!(stmt, Stmt::Block(_))) {
+                coverage.record(&self.script, line, stmt);
             }
         }
 
-        Ok(())
+        if let Some(mut hook) = self.hook.take() {
+            let res = hook.before_statement(self, stmt);
+
+            self.hook = Some(hook);
+
+            res?;
+        }
+
+        stmt.accept(self)
+    }
+
+    /// Fails once the program has run more statements or for longer than
+    /// its limits allow, has been interrupted, or is nested so deep that the
+    /// stack is about to run out. The clock is only read
+    /// every so often, but once the deadline has passed every statement
+    /// fails, so catching the error can't keep the script going.
+    fn check_budget(&mut self) -> Result<(), InterpreterError> {
+        if self.interrupted.load(Ordering::Relaxed) {
+            return Err(InterpreterError::runtime_error(None, "Interrupted."));
+        }
+
+        if self.stack.is_exhausted() {
+            return Err(InterpreterError::runtime_error(None, "Stack overflow."));
+        }
+
+        let is_over_steps = self
+            .options
+            .limits
+            .opt_max_steps
+            .is_some_and(|max_steps| self.statements > max_steps);
+
+        if !self.is_past_deadline && self.statements.is_multiple_of(1024) {
+            self.is_past_deadline = self
+                .opt_deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+        }
+
+        if is_over_steps || self.is_past_deadline {
+            Err(InterpreterError::runtime_error(
+                None,
+                "Execution budget exceeded.",
+            ))
+        } else {
+            Ok(())
+        }
     }
 
     pub fn execute_block(
         &mut self,
         stmts: &[Stmt],
-        env: Rc<RefCell<Environment>>,
+        env: Shared<Locked<Environment>>,
     ) -> Result<(), InterpreterError> {
         let previous = self.env.clone();
 
@@ -242,271 +645,646 @@ impl Interpreter {
         res
     }
 
-    fn evaluate(&mut self, expr: &Expr) -> Result<LoxType, InterpreterError> {
-        match expr {
-            Expr::Assign { name, value } => {
-                let value = self.evaluate(value)?;
-
-                let success = if let Some(distance) = self.locals.get(name) {
-                    self.env
-                        .borrow_mut()
-                        .assign_at(*distance, &name.lexeme, value.clone())
-                } else {
-                    self.env.borrow_mut().assign(&name.lexeme, value.clone())
-                };
+    fn import(&mut self, keyword: &Token, path: &str) -> Result<(), InterpreterError> {
+        if !self.options.capabilities.allow_fs {
+            return Err(InterpreterError::runtime_error(
+                Some(keyword.clone()),
+                "Imports need file system access.",
+            ));
+        }
 
-                if success {
-                    Ok(value)
-                } else {
-                    Err(InterpreterError::runtime_error(
-                        Some(name.clone()),
-                        &format!("Undefined variable '{}'.", name.lexeme),
-                    ))
-                }
+        let base_dir = self.script.parent().unwrap_or_else(|| Path::new(""));
+
+        let module_path = std::iter::once(base_dir)
+            .chain(self.options.module_paths.iter().map(PathBuf::as_path))
+            .find_map(|dir| fs::canonicalize(dir.join(path)).ok())
+            .ok_or_else(|| {
+                InterpreterError::runtime_error(
+                    Some(keyword.clone()),
+                    &format!("Could not find module '{}'.", path),
+                )
+            })?;
+
+        match self.modules.get(&module_path) {
+            Some(ModuleState::Loaded) => return Ok(()),
+            Some(ModuleState::Loading) => {
+                return Err(InterpreterError::runtime_error(
+                    Some(keyword.clone()),
+                    &format!("Import cycle detected while loading '{}'.", path),
+                ))
             }
-            Expr::Binary {
-                left,
-                operator,
-                right,
-            } => {
-                let left_value = self.evaluate(left)?;
-                let right_value = self.evaluate(right)?;
+            None => (),
+        }
 
-                match operator.token_type {
-                    TokenType::Minus => {
-                        let (n, m) =
-                            Self::check_number_operands(operator.clone(), left_value, right_value)?;
+        let src = fs::read_to_string(&module_path).map_err(|_| {
+            InterpreterError::runtime_error(
+                Some(keyword.clone()),
+                &format!("Could not read module '{}'.", path),
+            )
+        })?;
 
-                        Ok(LoxType::Number(n - m))
-                    }
-                    TokenType::Plus => match (left_value, right_value) {
-                        (LoxType::Number(n), LoxType::Number(m)) => Ok(LoxType::Number(n + m)),
-                        (LoxType::String(mut n), LoxType::String(m)) => {
-                            n.push_str(&m);
-
-                            Ok(LoxType::String(n))
-                        }
-                        _ => Err(InterpreterError::runtime_error(
-                            Some(operator.clone()),
-                            "Operands must be two numbers or two strings.",
-                        )),
-                    },
-                    TokenType::Slash => {
-                        let (n, m) =
-                            Self::check_number_operands(operator.clone(), left_value, right_value)?;
-
-                        Ok(LoxType::Number(n / m))
-                    }
-                    TokenType::Star => {
-                        let (n, m) =
-                            Self::check_number_operands(operator.clone(), left_value, right_value)?;
+        let mut diagnostics = Diagnostics::new();
 
-                        Ok(LoxType::Number(n * m))
-                    }
-                    TokenType::Greater => {
-                        let (n, m) =
-                            Self::check_number_operands(operator.clone(), left_value, right_value)?;
+        let tokens = Scanner::new(&src)
+            .compat(self.options.compat)
+            .scan_tokens(&mut diagnostics);
 
-                        Ok(LoxType::Boolean(n > m))
-                    }
-                    TokenType::GreaterEqual => {
-                        let (n, m) =
-                            Self::check_number_operands(operator.clone(), left_value, right_value)?;
+        if let Some(ref mut origins) = self.origins {
+            origins.record(&module_path.to_string_lossy(), &tokens);
+        }
 
-                        Ok(LoxType::Boolean(n >= m))
-                    }
-                    TokenType::Less => {
-                        let (n, m) =
-                            Self::check_number_operands(operator.clone(), left_value, right_value)?;
+        let mut statements = if diagnostics.had_error() {
+            Vec::new()
+        } else {
+            Parser::new(tokens.into_iter().map(Ok), &mut diagnostics)
+                .limits(self.options.limits.clone())
+                .parse()
+        };
 
-                        Ok(LoxType::Boolean(n < m))
-                    }
-                    TokenType::LessEqual => {
-                        let (n, m) =
-                            Self::check_number_operands(operator.clone(), left_value, right_value)?;
+        if let Some(ref mut coverage) = self.opt_coverage {
+            coverage.add_file(&module_path, &statements);
+        }
 
-                        Ok(LoxType::Boolean(n <= m))
+        if self.options.optimize && !diagnostics.had_error() {
+            statements = optimizer::optimize(&statements);
+        }
+
+        if !diagnostics.had_error() {
+            Resolver::new(self, &mut diagnostics).resolve(&statements);
+        }
+
+        if diagnostics.had_error() {
+            let errors: Vec<String> = diagnostics
+                .errors()
+                .iter()
+                .map(|err| err.to_string())
+                .collect();
+
+            return Err(InterpreterError::runtime_error(
+                Some(keyword.clone()),
+                &format!(
+                    "Could not compile module '{}'.\n{}",
+                    path,
+                    errors.join("\n")
+                ),
+            ));
+        }
+
+        self.modules
+            .insert(module_path.clone(), ModuleState::Loading);
+
+        let previous_script = std::mem::replace(&mut self.script, module_path.clone());
+
+        let res = self.execute_block(&statements, Shared::clone(&self.globals));
+
+        self.script = previous_script;
+
+        match res {
+            Ok(()) => {
+                self.modules.insert(module_path, ModuleState::Loaded);
+
+                Ok(())
+            }
+            Err(err) => {
+                self.modules.remove(&module_path);
+
+                Err(err)
+            }
+        }
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<LoxType, InterpreterError> {
+        if self.stack.is_exhausted() {
+            return Err(InterpreterError::runtime_error(None, "Stack overflow."));
+        }
+
+        expr.accept(self)
+    }
+
+    fn evaluate_call(
+        &mut self,
+        callee: &Expr,
+        arguments: &[Expr],
+    ) -> Result<(LoxType, Vec<LoxType>), InterpreterError> {
+        let callee_value = self.evaluate(callee)?;
+
+        Ok((callee_value, self.evaluate_arguments(arguments)?))
+    }
+
+    /// Evaluates call arguments in order, expanding spread tuples.
+    fn evaluate_arguments(&mut self, arguments: &[Expr]) -> Result<Vec<LoxType>, InterpreterError> {
+        let mut arguments_values = Vec::new();
+
+        for argument in arguments {
+            match argument {
+                Expr::Spread { ellipsis, value } => match self.evaluate(value)? {
+                    LoxType::Tuple(values) => arguments_values.extend(values.iter().cloned()),
+                    value => {
+                        return Err(InterpreterError::runtime_error(
+                            Some(ellipsis.clone()),
+                            &format!("Can only spread a tuple, not a {}.", value.type_name()),
+                        ))
                     }
-                    TokenType::BangEqual => Ok(LoxType::Boolean(left_value != right_value)),
-                    TokenType::EqualEqual => Ok(LoxType::Boolean(left_value == right_value)),
-                    _ => unreachable!(),
-                }
+                },
+                argument => arguments_values.push(self.evaluate(argument)?),
             }
+        }
+
+        Ok(arguments_values)
+    }
+
+    /// Evaluates the gets and calls of an optional chain, or returns `None`
+    /// as soon as a `?.` finds nil so the rest of the chain is skipped.
+    fn evaluate_chain(&mut self, expr: &Expr) -> Result<Option<LoxType>, InterpreterError> {
+        match expr {
+            Expr::Get {
+                object,
+                name,
+                is_optional,
+            } => match self.evaluate_chain(object)? {
+                Some(LoxType::Nil) if *is_optional => Ok(None),
+                Some(object_value) => self.get(object_value, name).map(Some),
+                None => Ok(None),
+            },
             Expr::Call {
                 callee,
                 paren,
                 arguments,
-            } => {
-                let callee_value = self.evaluate(callee)?;
-
-                let mut arguments_values = Vec::new();
+            } => match self.evaluate_chain(callee)? {
+                Some(callee_value) => {
+                    let arguments_values = self.evaluate_arguments(arguments)?;
 
-                for argument in arguments {
-                    arguments_values.push(self.evaluate(argument)?);
+                    self.call(callee_value, Some(paren), arguments_values)
+                        .map(Some)
                 }
+                None => Ok(None),
+            },
+            expr => self.evaluate(expr).map(Some),
+        }
+    }
 
-                match callee_value {
-                    LoxType::Callable(function) => {
-                        if arguments_values.len() == function.arity() {
-                            function.call(self, &arguments_values)
-                        } else {
-                            Err(InterpreterError::runtime_error(
-                                Some(paren.clone()),
-                                &format!(
-                                    "Expected {} arguments but got {}.",
-                                    function.arity(),
-                                    arguments_values.len()
-                                ),
-                            ))
-                        }
-                    }
-                    LoxType::Class(class) => {
-                        let instance = LoxInstance::new(&class);
-                        let instance_type = LoxType::Instance(Rc::new(RefCell::new(instance)));
-
-                        if let Some(initializer) = class.borrow().find_method("init") {
-                            if arguments_values.len() == initializer.arity() {
-                                initializer
-                                    .bind(instance_type.clone())
-                                    .call(self, &arguments_values)?;
-                            } else {
-                                return Err(InterpreterError::runtime_error(
-                                    Some(paren.clone()),
-                                    &format!(
-                                        "Expected {} arguments but got {}.",
-                                        initializer.arity(),
-                                        arguments_values.len()
-                                    ),
-                                ));
-                            }
-                        }
-
-                        Ok(instance_type)
-                    }
-                    _ => Err(InterpreterError::runtime_error(
-                        Some(paren.clone()),
-                        "Can only call functions and classes.",
-                    )),
-                }
-            }
-            Expr::Get { name, object } => {
-                let object_value = self.evaluate(object)?;
+    /// Reads the property `name` of `object_value`.
+    fn get(&mut self, object_value: LoxType, name: &Token) -> Result<LoxType, InterpreterError> {
+        if let LoxType::Instance(ref instance) = object_value {
+            self.check_private_access(name, instance)?;
+
+            instance.borrow().get(name, &object_value)
+        } else {
+            Err(InterpreterError::runtime_error(
+                Some(name.clone()),
+                "Only instances have properties.",
+            ))
+        }
+    }
 
-                if let LoxType::Instance(ref instance) = object_value {
-                    Ok(instance.borrow().get(name, &object_value)?)
+    fn call(
+        &mut self,
+        callee_value: LoxType,
+        opt_paren: Option<&Token>,
+        arguments_values: Vec<LoxType>,
+    ) -> Result<LoxType, InterpreterError> {
+        match callee_value {
+            LoxType::Callable(function) => {
+                if function.arity().accepts(arguments_values.len()) {
+                    function
+                        .call(self, &arguments_values)
+                        .map_err(|err| match opt_paren {
+                            Some(paren) => err.or_at(paren),
+                            None => err,
+                        })
                 } else {
                     Err(InterpreterError::runtime_error(
-                        Some(name.clone()),
-                        "Only instances have properties.",
+                        opt_paren.cloned(),
+                        &format!(
+                            "Expected {} arguments but got {}.",
+                            function.arity(),
+                            arguments_values.len()
+                        ),
                     ))
                 }
             }
-            Expr::Grouping(grouped_expr) => self.evaluate(grouped_expr),
-            Expr::Literal(value) => Ok(value.clone()),
-            Expr::Logical {
-                left,
-                operator,
-                right,
-            } => {
-                let left_value = self.evaluate(left)?;
+            LoxType::Class(class) => {
+                let instance = LoxInstance::new(&class);
+                let instance = Shared::new(Locked::new(instance));
 
-                let is_left_truthy = bool::from(left_value.clone());
+                self.globals.borrow().heap().track_instance(&instance);
 
-                if operator.token_type == TokenType::Or {
-                    if is_left_truthy {
-                        return Ok(left_value);
-                    }
-                } else {
-                    if !is_left_truthy {
-                        return Ok(left_value);
+                let instance_type = LoxType::Instance(instance);
+
+                self.initialize_fields(&class, &instance_type)?;
+
+                if let Some(initializer) = class.borrow().find_method("init") {
+                    if initializer.arity().accepts(arguments_values.len()) {
+                        initializer
+                            .bind(instance_type.clone())
+                            .call(self, &arguments_values)?;
+                    } else {
+                        return Err(InterpreterError::runtime_error(
+                            opt_paren.cloned(),
+                            &format!(
+                                "Expected {} arguments but got {}.",
+                                initializer.arity(),
+                                arguments_values.len()
+                            ),
+                        ));
                     }
                 }
 
-                self.evaluate(right)
+                Ok(instance_type)
             }
-            Expr::Set {
-                name,
-                object,
-                value,
-            } => {
-                let object_value = self.evaluate(object)?;
+            _ => Err(InterpreterError::runtime_error(
+                opt_paren.cloned(),
+                "Can only call functions and classes.",
+            )),
+        }
+    }
 
-                if let LoxType::Instance(instance) = object_value {
-                    let value = self.evaluate(value)?;
+    /// Runs a tail call that unwound to `res` as an ordinary call, for
+    /// statements that must see its outcome before their function returns.
+    fn finish_tail_call(
+        &mut self,
+        res: Result<(), InterpreterError>,
+    ) -> Result<(), InterpreterError> {
+        match res {
+            Err(InterpreterError::TailCall(tail_call)) => {
+                let (function, arguments) = *tail_call;
 
-                    instance.borrow_mut().set(name, value.clone());
+                Err(InterpreterError::Return(function.call(self, &arguments)?))
+            }
+            res => res,
+        }
+    }
 
-                    Ok(value)
-                } else {
-                    Err(InterpreterError::runtime_error(
-                        Some(name.clone()),
-                        "Only instances have fields.",
-                    ))
-                }
+    fn lookup_variable(&self, id: ExprId, name: &Token) -> Result<LoxType, InterpreterError> {
+        let opt_value = if let Some(&(distance, slot)) = self.locals.get(&id) {
+            self.env.borrow().get_at(distance, slot)
+        } else {
+            self.globals.borrow().get(&name.lexeme)
+        };
+
+        match opt_value {
+            Some(LoxType::Uninitialized) => Err(InterpreterError::runtime_error(
+                Some(name.clone()),
+                "Variable used before initialization.",
+            )),
+            Some(value) => Ok(value),
+            None => Err(InterpreterError::runtime_error(
+                Some(name.clone()),
+                &format!("Undefined variable '{}'.", name.lexeme),
+            )),
+        }
+    }
+
+    /// Rejects getting or setting a private property unless the running code
+    /// belongs to a class that `instance`'s class is or inherits from, or
+    /// the other way around.
+    fn check_private_access(
+        &self,
+        name: &Token,
+        instance: &Shared<Locked<LoxInstance>>,
+    ) -> Result<(), InterpreterError> {
+        self.check_private_access_to(&name.lexeme, instance)
+            .map_err(|err| err.or_at(name))
+    }
+
+    /// Like `check_private_access`, for natives that get the property name
+    /// as a string. The error has no location, for the caller to fill in.
+    pub(crate) fn check_private_access_to(
+        &self,
+        name: &str,
+        instance: &Shared<Locked<LoxInstance>>,
+    ) -> Result<(), InterpreterError> {
+        if self.options.compat || !is_private(name) {
+            return Ok(());
+        }
+
+        let is_allowed = match self.env.borrow().get("this") {
+            Some(LoxType::Instance(this)) => {
+                let this_class = Shared::clone(this.borrow().class());
+                let class = Shared::clone(instance.borrow().class());
+
+                class.borrow().is_subclass_of(&this_class)
+                    || this_class.borrow().is_subclass_of(&class)
             }
-            Expr::Super { keyword, method } => {
-                let distance = self.locals.get(keyword).unwrap();
+            _ => false,
+        };
+
+        if is_allowed {
+            Ok(())
+        } else {
+            Err(InterpreterError::runtime_error(
+                None,
+                &format!(
+                    "Can't access private property '{}' from another class.",
+                    name
+                ),
+            ))
+        }
+    }
+
+    /// Sets the fields declared in the bodies of `class` and its
+    /// superclasses on a new instance, outermost superclass first.
+    fn initialize_fields(
+        &mut self,
+        class: &Shared<Locked<LoxClass>>,
+        instance: &LoxType,
+    ) -> Result<(), InterpreterError> {
+        let (opt_superclass, fields, closure) = {
+            let class = class.borrow();
+
+            (
+                class.superclass().cloned(),
+                class.fields(),
+                Shared::clone(class.closure()),
+            )
+        };
 
-                let opt_superclass = self.env.borrow().get_at(*distance, "super");
+        if let Some(superclass) = opt_superclass {
+            self.initialize_fields(&superclass, instance)?;
+        }
 
-                let instance = self.env.borrow().get_at(*distance - 1, "this").unwrap();
+        if fields.is_empty() {
+            return Ok(());
+        }
 
-                if let Some(LoxType::Class(ref superclass)) = opt_superclass {
-                    if let Some(function) = superclass.borrow().find_method(&method.lexeme) {
-                        Ok(LoxType::Callable(function.bind(instance)))
-                    } else {
-                        Err(InterpreterError::runtime_error(
-                            Some(method.clone()),
-                            &format!("Undefined property '{}'.", method.lexeme),
-                        ))
-                    }
+        let env = Environment::child(&closure);
+
+        env.borrow_mut()
+            .define(Shared::from("this"), instance.clone());
+
+        let previous = std::mem::replace(&mut self.env, env);
+
+        let res = fields.iter().try_for_each(|(name, value)| {
+            let value = self.evaluate(value)?;
+
+            if let LoxType::Instance(instance) = instance {
+                instance.borrow_mut().set(name, value);
+            }
+
+            Ok(())
+        });
+
+        self.env = previous;
+
+        res
+    }
+
+    /// Starts iterating over `value`. An instance with an `iter()` method is
+    /// asked for its iterator; any other instance is its own iterator.
+    pub(crate) fn iterate(
+        &mut self,
+        value: LoxType,
+        token: &Token,
+    ) -> Result<Iteration, InterpreterError> {
+        match value {
+            LoxType::String(s) => {
+                let chars: Vec<LoxType> = s
+                    .chars()
+                    .map(|c| LoxType::String(c.to_string().into()))
+                    .collect();
+
+                Ok(Iteration::Values(Box::new(chars.into_iter())))
+            }
+            LoxType::Tuple(values) => Ok(Iteration::Values(Box::new(
+                (0..values.len()).map(move |index| values[index].clone()),
+            ))),
+            LoxType::Instance(ref instance) => {
+                let has_iter = instance.borrow().has_property("iter");
+
+                if has_iter {
+                    Ok(Iteration::Protocol(self.call_method(
+                        &value,
+                        "iter",
+                        token,
+                        Vec::new(),
+                    )?))
                 } else {
-                    Err(InterpreterError::runtime_error(
-                        Some(method.clone()),
-                        &format!("Undefined property '{}'.", keyword.lexeme),
-                    ))
+                    Ok(Iteration::Protocol(value))
                 }
             }
-            Expr::This(keyword) => self.lookup_variable(keyword),
-            Expr::Unary { operator, right } => {
-                let right_value = self.evaluate(right)?;
+            _ => Err(InterpreterError::runtime_error(
+                Some(token.clone()),
+                "Can only iterate over ranges, strings, tuples and instances.",
+            )),
+        }
+    }
 
-                match operator.token_type {
-                    TokenType::Bang => {
-                        let b = bool::from(right_value);
+    /// The next value of `iteration`, or `None` once it's exhausted.
+    pub(crate) fn next_item(
+        &mut self,
+        iteration: &mut Iteration,
+        token: &Token,
+    ) -> Result<Option<LoxType>, InterpreterError> {
+        match iteration {
+            Iteration::Values(values) => Ok(values.next()),
+            Iteration::Protocol(iterator) => {
+                if bool::from(self.call_method(iterator, "done", token, Vec::new())?) {
+                    Ok(None)
+                } else {
+                    self.call_method(iterator, "next", token, Vec::new())
+                        .map(Some)
+                }
+            }
+        }
+    }
 
-                        Ok(LoxType::Boolean(!b))
-                    }
-                    TokenType::Minus => {
-                        let n = Self::check_number_operand(operator.clone(), right_value)?;
+    /// Calls the method `name` on `object`, reporting errors at `token`.
+    fn call_method(
+        &mut self,
+        object: &LoxType,
+        name: &str,
+        token: &Token,
+        arguments: Vec<LoxType>,
+    ) -> Result<LoxType, InterpreterError> {
+        let name = Token::new(
+            TokenType::Identifier,
+            Shared::from(name),
+            None,
+            token.line,
+            token.column,
+        );
 
-                        Ok(LoxType::Number(-n))
-                    }
-                    _ => unreachable!(),
+        let method = match object {
+            LoxType::Instance(instance) => instance.borrow().get(&name, object)?,
+            _ => {
+                return Err(InterpreterError::runtime_error(
+                    Some(name),
+                    "Only instances have properties.",
+                ))
+            }
+        };
+
+        self.call(method, Some(&name), arguments)
+    }
+
+    /// Converts `value` to the string `print` shows. Instances with a
+    /// `toString()` method are shown as what it returns. Errors from the call
+    /// itself have no location, for the caller to fill in.
+    pub(crate) fn stringify(&mut self, value: &LoxType) -> Result<String, InterpreterError> {
+        let opt_method = match value {
+            LoxType::Instance(instance) => instance.borrow().find_method("toString"),
+            _ => None,
+        };
+
+        match opt_method {
+            Some(method) if method.arity().accepts(0) => {
+                match method.bind(value.clone()).call(self, &[])? {
+                    LoxType::String(s) => Ok(s.to_string()),
+                    _ => Err(InterpreterError::runtime_error(
+                        None,
+                        "toString() must return a string.",
+                    )),
                 }
             }
-            Expr::Variable(name) => self.lookup_variable(name),
+            Some(_) => Err(InterpreterError::runtime_error(
+                None,
+                "toString() can't take arguments.",
+            )),
+            None => match value {
+                LoxType::Tuple(values) => {
+                    let values = values
+                        .iter()
+                        .map(|value| self.stringify(value))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    Ok(format!("({})", values.join(", ")))
+                }
+                _ => Ok(value.to_string()),
+            },
         }
     }
 
-    fn lookup_variable(&self, name: &Token) -> Result<LoxType, InterpreterError> {
-        let opt_value = if let Some(distance) = self.locals.get(name) {
-            self.env.borrow().get_at(*distance, &name.lexeme)
+    /// Assigns `value` to the variable `name`, which the resolver found
+    /// under `id` unless it's global.
+    fn assign(&mut self, id: ExprId, name: &Token, value: LoxType) -> Result<(), InterpreterError> {
+        let is_const = match self.locals.get(&id) {
+            Some(&(distance, slot)) => self.env.borrow().is_constant_at(distance, slot),
+            None => self.globals.borrow().is_constant(&name.lexeme),
+        };
+
+        if is_const {
+            return Err(InterpreterError::runtime_error(
+                Some(name.clone()),
+                &format!("Can't assign to constant '{}'.", name.lexeme),
+            ));
+        }
+
+        let success = if let Some(&(distance, slot)) = self.locals.get(&id) {
+            self.env.borrow_mut().assign_at(distance, slot, value)
         } else {
-            self.globals.borrow().get(&name.lexeme)
+            self.globals.borrow_mut().assign(&name.lexeme, value)
         };
 
-        match opt_value {
-            Some(value) => Ok(value),
-            None => Err(InterpreterError::runtime_error(
+        if success {
+            Ok(())
+        } else {
+            Err(InterpreterError::runtime_error(
                 Some(name.clone()),
                 &format!("Undefined variable '{}'.", name.lexeme),
+            ))
+        }
+    }
+
+    /// The values `names` unpack from `value`: the elements of a tuple after
+    /// `(`, or the properties of an instance after `{`.
+    fn destructure(
+        &mut self,
+        opening: &Token,
+        names: &[Token],
+        value: LoxType,
+    ) -> Result<Vec<LoxType>, InterpreterError> {
+        if opening.token_type == TokenType::LeftBrace {
+            return self.destructure_fields(opening, names, value);
+        }
+
+        match value {
+            LoxType::Tuple(values) if values.len() == names.len() => Ok(values.to_vec()),
+            value => Err(InterpreterError::runtime_error(
+                Some(opening.clone()),
+                &format!(
+                    "Expect a tuple of {} values but got {}.",
+                    names.len(),
+                    describe_destructured(&value)
+                ),
             )),
         }
     }
 
+    /// The properties `names` of `value` for `var {a, b} = value;`, read
+    /// the same way as `value.a` and `value.b`.
+    fn destructure_fields(
+        &mut self,
+        opening: &Token,
+        names: &[Token],
+        value: LoxType,
+    ) -> Result<Vec<LoxType>, InterpreterError> {
+        let instance = match value {
+            LoxType::Instance(ref instance) => instance,
+            value => {
+                return Err(InterpreterError::runtime_error(
+                    Some(opening.clone()),
+                    &format!(
+                        "Expect an instance to unpack but got {}.",
+                        describe_destructured(&value)
+                    ),
+                ))
+            }
+        };
+
+        names
+            .iter()
+            .map(|name| {
+                self.check_private_access(name, instance)?;
+
+                instance.borrow().get(name, &value)
+            })
+            .collect()
+    }
+
+    /// Under strict math, rejects arithmetic that produced NaN instead of
+    /// letting it propagate.
+    fn check_arithmetic(&self, operator: &Token, n: f64) -> Result<LoxType, InterpreterError> {
+        if self.options.strict_math && n.is_nan() {
+            Err(InterpreterError::runtime_error(
+                Some(operator.clone()),
+                "Arithmetic result is not a number.",
+            ))
+        } else {
+            Ok(LoxType::Number(n))
+        }
+    }
+
+    fn check_comparison_operands(
+        &self,
+        operator: &Token,
+        left: LoxType,
+        right: LoxType,
+    ) -> Result<(f64, f64), InterpreterError> {
+        self.check_not_nan(operator, &left, &right)?;
+
+        Self::check_number_operands(operator.clone(), left, right)
+    }
+
+    fn check_not_nan(
+        &self,
+        operator: &Token,
+        left: &LoxType,
+        right: &LoxType,
+    ) -> Result<(), InterpreterError> {
+        let is_nan = |value: &LoxType| matches!(value, LoxType::Number(n) if n.is_nan());
+
+        if self.options.strict_math && (is_nan(left) || is_nan(right)) {
+            Err(InterpreterError::runtime_error(
+                Some(operator.clone()),
+                "Can't compare NaN.",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
     fn check_number_operand(token: Token, operand: LoxType) -> Result<f64, InterpreterError> {
-        if let LoxType::Number(n) = operand {
+        if let Some(n) = operand.as_number() {
             Ok(n)
         } else {
             Err(InterpreterError::runtime_error(
@@ -521,7 +1299,7 @@ impl Interpreter {
         left: LoxType,
         right: LoxType,
     ) -> Result<(f64, f64), InterpreterError> {
-        if let (LoxType::Number(n), LoxType::Number(m)) = (left, right) {
+        if let (Some(n), Some(m)) = (left.as_number(), right.as_number()) {
             Ok((n, m))
         } else {
             Err(InterpreterError::runtime_error(
@@ -531,3 +1309,816 @@ impl Interpreter {
         }
     }
 }
+
+impl StmtVisitor<Result<(), InterpreterError>> for Interpreter {
+    fn visit_block(&mut self, stmts: &[Stmt]) -> Result<(), InterpreterError> {
+        self.execute_block(stmts, Environment::child(&self.env))
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        fields: &[(Token, Expr)],
+        methods: &[Stmt],
+        opt_superclass: Option<&Expr>,
+    ) -> Result<(), InterpreterError> {
+        let superclass_value = opt_superclass
+            .map(|expr| {
+                if let LoxType::Class(class) = self.evaluate(expr)? {
+                    Ok(Shared::clone(&class))
+                } else if let Expr::Variable { name, .. } = expr {
+                    Err(InterpreterError::runtime_error(
+                        Some(name.clone()),
+                        "Superclass must be a class.",
+                    ))
+                } else {
+                    unreachable!();
+                }
+            })
+            .transpose()?;
+
+        self.env
+            .borrow_mut()
+            .define(Shared::clone(&name.lexeme), LoxType::Nil);
+
+        if let Some(ref superclass) = superclass_value {
+            self.env = Environment::child(&self.env);
+
+            self.env.borrow_mut().define(
+                Shared::from("super"),
+                LoxType::Class(Shared::clone(superclass)),
+            );
+        }
+
+        let mut class_methods = IndexMap::new();
+
+        for method in methods {
+            if let Stmt::Function {
+                name: function_name,
+                params,
+                opt_rest,
+                body,
+            } = method
+            {
+                let function = Function::User {
+                    name: Shared::new(function_name.clone()),
+                    params: params.as_slice().into(),
+                    opt_rest: opt_rest.as_ref().map(|rest| Shared::clone(&rest.lexeme)),
+                    body: Shared::from(body.as_slice()),
+                    closure: Shared::clone(&self.env),
+                    is_initializer: &*function_name.lexeme == "init",
+                };
+
+                class_methods.insert(Shared::clone(&function_name.lexeme), function);
+            } else {
+                unreachable!()
+            }
+        }
+
+        let class = Shared::new(Locked::new(LoxClass::new(
+            Shared::clone(&name.lexeme),
+            Shared::from(fields),
+            class_methods,
+            superclass_value.clone(),
+            Shared::clone(&self.env),
+        )));
+
+        if superclass_value.is_some() {
+            let parent = self.env.borrow().enclosing.clone().unwrap();
+
+            self.env = parent;
+        }
+
+        self.env
+            .borrow_mut()
+            .assign(&name.lexeme, LoxType::Class(class));
+
+        Ok(())
+    }
+
+    fn visit_destructure(
+        &mut self,
+        opening: &Token,
+        names: &[Token],
+        initializer: &Expr,
+    ) -> Result<(), InterpreterError> {
+        let value = self.evaluate(initializer)?;
+
+        let values = self.destructure(opening, names, value)?;
+
+        for (name, value) in names.iter().zip(values) {
+            self.env
+                .borrow_mut()
+                .define(Shared::clone(&name.lexeme), value);
+        }
+
+        Ok(())
+    }
+
+    fn visit_destructure_assign(
+        &mut self,
+        opening: &Token,
+        targets: &[(ExprId, Token)],
+        value: &Expr,
+    ) -> Result<(), InterpreterError> {
+        let value = self.evaluate(value)?;
+
+        let names: Vec<Token> = targets.iter().map(|(_, name)| name.clone()).collect();
+
+        let values = self.destructure(opening, &names, value)?;
+
+        for ((id, name), value) in targets.iter().zip(values) {
+            self.assign(*id, name, value)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_do_while(
+        &mut self,
+        _keyword: &Token,
+        body: &Stmt,
+        condition: &Expr,
+    ) -> Result<(), InterpreterError> {
+        loop {
+            self.execute(body)?;
+
+            if !bool::from(self.evaluate(condition)?) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &Expr) -> Result<(), InterpreterError> {
+        self.evaluate(expr)?;
+
+        Ok(())
+    }
+
+    fn visit_for(
+        &mut self,
+        _keyword: &Token,
+        initializers: &[Stmt],
+        condition: &Expr,
+        opt_increment: Option<&Expr>,
+        body: &Stmt,
+    ) -> Result<(), InterpreterError> {
+        let previous = Shared::clone(&self.env);
+
+        // One scope holds the initializers' variables for the whole loop.
+        let mut run_loop = || -> Result<(), InterpreterError> {
+            self.env = Environment::child(&previous);
+
+            for initializer in initializers {
+                self.execute(initializer)?;
+            }
+
+            while bool::from(self.evaluate(condition)?) {
+                self.execute(body)?;
+
+                // Closures from this pass keep its scope; the next pass and
+                // the increment work on a copy.
+                if self.options.fresh_loop_bindings {
+                    self.env = Environment::copy(&self.env);
+                }
+
+                if let Some(increment) = opt_increment {
+                    self.evaluate(increment)?;
+                }
+            }
+
+            Ok(())
+        };
+
+        let res = run_loop();
+
+        self.env = previous;
+
+        res
+    }
+
+    fn visit_for_in(
+        &mut self,
+        keyword: &Token,
+        name: &Token,
+        value: &Expr,
+        opt_end: Option<&Expr>,
+        body: &Stmt,
+    ) -> Result<(), InterpreterError> {
+        let value = self.evaluate(value)?;
+
+        let mut iteration = match opt_end {
+            Some(end) => match (value, self.evaluate(end)?) {
+                (LoxType::Integer(start), LoxType::Integer(end)) => {
+                    Iteration::Values(Box::new((start..end).map(LoxType::Integer)))
+                }
+                _ => {
+                    return Err(InterpreterError::runtime_error(
+                        Some(keyword.clone()),
+                        "Range bounds must be integers.",
+                    ))
+                }
+            },
+            None => self.iterate(value, keyword)?,
+        };
+
+        // One scope holds the loop variable for the whole loop, unless each
+        // pass gets its own.
+        let mut env = Environment::child(&self.env);
+
+        env.borrow_mut()
+            .define(Shared::clone(&name.lexeme), LoxType::Uninitialized);
+
+        while let Some(item) = self.next_item(&mut iteration, keyword)? {
+            if self.options.fresh_loop_bindings {
+                env = Environment::copy(&env);
+            }
+
+            env.borrow_mut().assign_at(0, 0, item);
+
+            self.execute_block(slice::from_ref(body), Shared::clone(&env))?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_function(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        opt_rest: Option<&Token>,
+        body: &[Stmt],
+    ) -> Result<(), InterpreterError> {
+        let function = LoxType::Callable(Function::User {
+            name: Shared::new(name.clone()),
+            body: Shared::from(body),
+            params: params.into(),
+            opt_rest: opt_rest.map(|rest| Shared::clone(&rest.lexeme)),
+            closure: Shared::clone(&self.env),
+            is_initializer: false,
+        });
+
+        self.env
+            .borrow_mut()
+            .define(Shared::clone(&name.lexeme), function);
+
+        Ok(())
+    }
+
+    fn visit_if(
+        &mut self,
+        _keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        opt_else_branch: Option<&Stmt>,
+    ) -> Result<(), InterpreterError> {
+        if bool::from(self.evaluate(condition)?) {
+            self.execute(then_branch)?;
+        } else if let Some(else_branch) = opt_else_branch {
+            self.execute(else_branch)?
+        }
+
+        Ok(())
+    }
+
+    fn visit_import(&mut self, keyword: &Token, path: &str) -> Result<(), InterpreterError> {
+        self.import(keyword, path)
+    }
+
+    fn visit_print(&mut self, keyword: &Token, expr: &Expr) -> Result<(), InterpreterError> {
+        let value = self.evaluate(expr)?;
+
+        let value = self.stringify(&value).map_err(|err| err.or_at(keyword))?;
+
+        writeln!(self.output, "{}", value).map_err(|err| {
+            InterpreterError::runtime_error(
+                Some(keyword.clone()),
+                &format!("Could not write output: {}.", err),
+            )
+        })?;
+
+        Ok(())
+    }
+
+    fn visit_return(&mut self, _keyword: &Token, value: &Expr) -> Result<(), InterpreterError> {
+        let value = match value {
+            Expr::Literal(LoxType::Nil) => LoxType::Nil,
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                let (callee_value, arguments_values) = self.evaluate_call(callee, arguments)?;
+
+                match callee_value {
+                    LoxType::Callable(function @ Function::User { .. })
+                        if function.arity().accepts(arguments_values.len()) =>
+                    {
+                        return Err(InterpreterError::TailCall(Box::new((
+                            function,
+                            arguments_values,
+                        ))));
+                    }
+                    callee_value => self.call(callee_value, Some(paren), arguments_values)?,
+                }
+            }
+            _ => self.evaluate(value)?,
+        };
+
+        Err(InterpreterError::Return(value))
+    }
+
+    fn visit_switch(
+        &mut self,
+        _keyword: &Token,
+        value: &Expr,
+        cases: &[(Expr, Vec<Stmt>)],
+        opt_default: Option<&[Stmt]>,
+    ) -> Result<(), InterpreterError> {
+        let value = self.evaluate(value)?;
+
+        let mut opt_body = opt_default;
+
+        for (case_value, body) in cases {
+            if self.evaluate(case_value)? == value {
+                opt_body = Some(body.as_slice());
+
+                break;
+            }
+        }
+
+        if let Some(body) = opt_body {
+            self.execute_block(body, Environment::child(&self.env))?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_throw(&mut self, keyword: &Token, value: &Expr) -> Result<(), InterpreterError> {
+        let value = self.evaluate(value)?;
+
+        Err(InterpreterError::Thrown(value, Box::new(keyword.clone())))
+    }
+
+    fn visit_try(
+        &mut self,
+        _keyword: &Token,
+        body: &[Stmt],
+        opt_catch: Option<(&Token, &[Stmt])>,
+        opt_finally: Option<&[Stmt]>,
+    ) -> Result<(), InterpreterError> {
+        // A call in tail position has to finish before leaving the `try` so
+        // the `catch` and `finally` see what it does.
+        let res = self.execute_block(body, Environment::child(&self.env));
+
+        let mut res = self.finish_tail_call(res);
+
+        if let Some((name, catch_body)) = opt_catch {
+            let opt_caught = match res {
+                Err(InterpreterError::Thrown(ref value, _)) => Some(value.clone()),
+                Err(InterpreterError::RuntimeError(ref err)) => {
+                    Some(LoxType::String(err.message.as_str().into()))
+                }
+                _ => None,
+            };
+
+            if let Some(caught) = opt_caught {
+                let env = Environment::child(&self.env);
+
+                env.borrow_mut().define(Shared::clone(&name.lexeme), caught);
+
+                let catch_res = self.execute_block(catch_body, env);
+
+                res = self.finish_tail_call(catch_res);
+            }
+        }
+
+        if let Some(finally_body) = opt_finally {
+            self.execute_block(finally_body, Environment::child(&self.env))?;
+        }
+
+        res
+    }
+
+    fn visit_var(
+        &mut self,
+        name: &Token,
+        initializer: &Expr,
+        is_const: bool,
+    ) -> Result<(), InterpreterError> {
+        let value = match self.evaluate(initializer)? {
+            // The book's Lox reads a variable declared without a value as nil.
+            LoxType::Uninitialized if self.options.compat => LoxType::Nil,
+            value => value,
+        };
+
+        if is_const {
+            self.env
+                .borrow_mut()
+                .define_constant(Shared::clone(&name.lexeme), value);
+        } else {
+            self.env
+                .borrow_mut()
+                .define(Shared::clone(&name.lexeme), value);
+        }
+
+        Ok(())
+    }
+
+    fn visit_while(
+        &mut self,
+        _keyword: &Token,
+        condition: &Expr,
+        body: &Stmt,
+    ) -> Result<(), InterpreterError> {
+        while bool::from(self.evaluate(condition)?) {
+            self.execute(body)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ExprVisitor<Result<LoxType, InterpreterError>> for Interpreter {
+    fn visit_assign(
+        &mut self,
+        id: ExprId,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<LoxType, InterpreterError> {
+        let value = self.evaluate(value)?;
+
+        self.assign(id, name, value.clone())?;
+
+        Ok(value)
+    }
+
+    fn visit_binary(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<LoxType, InterpreterError> {
+        let left_value = self.evaluate(left)?;
+        let right_value = self.evaluate(right)?;
+
+        if let LoxType::Instance(ref instance) = left_value {
+            if let Some(method) = operator_method(&operator.token_type) {
+                let has_method = instance.borrow().has_property(method);
+
+                if has_method {
+                    let value =
+                        self.call_method(&left_value, method, operator, vec![right_value])?;
+
+                    return if operator.token_type == TokenType::BangEqual {
+                        Ok(LoxType::Boolean(!bool::from(value)))
+                    } else {
+                        Ok(value)
+                    };
+                }
+            }
+        }
+
+        if let (LoxType::Integer(n), LoxType::Integer(m)) = (&left_value, &right_value) {
+            if let Some(value) = integer_binary(&operator.token_type, *n, *m) {
+                return Ok(value);
+            }
+        }
+
+        match operator.token_type {
+            TokenType::Minus => {
+                let (n, m) =
+                    Self::check_number_operands(operator.clone(), left_value, right_value)?;
+
+                self.check_arithmetic(operator, n - m)
+            }
+            TokenType::Plus => match (left_value, right_value) {
+                (LoxType::String(n), LoxType::String(m)) => {
+                    Ok(LoxType::String(format!("{}{}", n, m).into()))
+                }
+                (LoxType::String(n), m) if m.as_number().is_some() => {
+                    Ok(LoxType::String(format!("{}{}", n, m).into()))
+                }
+                (n, LoxType::String(m)) if n.as_number().is_some() => {
+                    Ok(LoxType::String(format!("{}{}", n, m).into()))
+                }
+                (n, m) => match (n.as_number(), m.as_number()) {
+                    (Some(n), Some(m)) => self.check_arithmetic(operator, n + m),
+                    _ => Err(InterpreterError::runtime_error(
+                        Some(operator.clone()),
+                        "Operands must be two numbers or two strings.",
+                    )),
+                },
+            },
+            TokenType::Interpolation => {
+                let left_text = self
+                    .stringify(&left_value)
+                    .map_err(|err| err.or_at(operator))?;
+                let right_text = self
+                    .stringify(&right_value)
+                    .map_err(|err| err.or_at(operator))?;
+
+                Ok(LoxType::String(
+                    format!("{}{}", left_text, right_text).into(),
+                ))
+            }
+            TokenType::Slash => {
+                let (n, m) =
+                    Self::check_number_operands(operator.clone(), left_value, right_value)?;
+
+                if self.options.strict_math && m == 0.0 {
+                    return Err(InterpreterError::runtime_error(
+                        Some(operator.clone()),
+                        "Division by zero.",
+                    ));
+                }
+
+                self.check_arithmetic(operator, n / m)
+            }
+            TokenType::Star => {
+                let (n, m) =
+                    Self::check_number_operands(operator.clone(), left_value, right_value)?;
+
+                self.check_arithmetic(operator, n * m)
+            }
+            TokenType::Greater => {
+                let (n, m) = self.check_comparison_operands(operator, left_value, right_value)?;
+
+                Ok(LoxType::Boolean(n > m))
+            }
+            TokenType::GreaterEqual => {
+                let (n, m) = self.check_comparison_operands(operator, left_value, right_value)?;
+
+                Ok(LoxType::Boolean(n >= m))
+            }
+            TokenType::Less => {
+                let (n, m) = self.check_comparison_operands(operator, left_value, right_value)?;
+
+                Ok(LoxType::Boolean(n < m))
+            }
+            TokenType::LessEqual => {
+                let (n, m) = self.check_comparison_operands(operator, left_value, right_value)?;
+
+                Ok(LoxType::Boolean(n <= m))
+            }
+            TokenType::BangEqual => {
+                self.check_not_nan(operator, &left_value, &right_value)?;
+
+                Ok(LoxType::Boolean(left_value != right_value))
+            }
+            TokenType::EqualEqual => {
+                self.check_not_nan(operator, &left_value, &right_value)?;
+
+                Ok(LoxType::Boolean(left_value == right_value))
+            }
+            TokenType::Ampersand
+            | TokenType::Caret
+            | TokenType::Pipe
+            | TokenType::LessLess
+            | TokenType::GreaterGreater => {
+                let (n, m) =
+                    Self::check_number_operands(operator.clone(), left_value, right_value)?;
+
+                Ok(LoxType::Integer(bitwise(&operator.token_type, n, m)))
+            }
+            TokenType::Is => match (left_value, right_value) {
+                (LoxType::Instance(instance), LoxType::Class(class)) => Ok(LoxType::Boolean(
+                    instance.borrow().class().borrow().is_subclass_of(&class),
+                )),
+                (_, LoxType::Class(_)) => Ok(LoxType::Boolean(false)),
+                _ => Err(InterpreterError::runtime_error(
+                    Some(operator.clone()),
+                    "Right operand of 'is' must be a class.",
+                )),
+            },
+            TokenType::Comma => Ok(right_value),
+            _ => unreachable!(),
+        }
+    }
+
+    fn visit_call(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Expr],
+    ) -> Result<LoxType, InterpreterError> {
+        let (callee_value, arguments_values) = self.evaluate_call(callee, arguments)?;
+
+        self.call(callee_value, Some(paren), arguments_values)
+    }
+
+    fn visit_get(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        is_optional: bool,
+    ) -> Result<LoxType, InterpreterError> {
+        match self.evaluate(object)? {
+            LoxType::Nil if is_optional => Ok(LoxType::Nil),
+            object_value => self.get(object_value, name),
+        }
+    }
+
+    fn visit_grouping(&mut self, grouped_expr: &Expr) -> Result<LoxType, InterpreterError> {
+        self.evaluate(grouped_expr)
+    }
+
+    fn visit_literal(&mut self, value: &LoxType) -> Result<LoxType, InterpreterError> {
+        Ok(value.clone())
+    }
+
+    fn visit_logical(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<LoxType, InterpreterError> {
+        let left_value = self.evaluate(left)?;
+
+        let is_left_truthy = bool::from(left_value.clone());
+
+        let short_circuits = match operator.token_type {
+            TokenType::Or => is_left_truthy,
+            TokenType::QuestionQuestion => !matches!(left_value, LoxType::Nil),
+            _ => !is_left_truthy,
+        };
+
+        if short_circuits {
+            Ok(left_value)
+        } else {
+            self.evaluate(right)
+        }
+    }
+
+    fn visit_optional_chain(&mut self, chain: &Expr) -> Result<LoxType, InterpreterError> {
+        Ok(self.evaluate_chain(chain)?.unwrap_or(LoxType::Nil))
+    }
+
+    fn visit_set(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<LoxType, InterpreterError> {
+        let object_value = self.evaluate(object)?;
+
+        if let LoxType::Instance(instance) = object_value {
+            self.check_private_access(name, &instance)?;
+
+            let value = self.evaluate(value)?;
+
+            instance.borrow_mut().set(name, value.clone());
+
+            Ok(value)
+        } else {
+            Err(InterpreterError::runtime_error(
+                Some(name.clone()),
+                "Only instances have fields.",
+            ))
+        }
+    }
+
+    fn visit_spread(
+        &mut self,
+        ellipsis: &Token,
+        _value: &Expr,
+    ) -> Result<LoxType, InterpreterError> {
+        Err(InterpreterError::runtime_error(
+            Some(ellipsis.clone()),
+            "Can only spread call arguments.",
+        ))
+    }
+
+    fn visit_super(
+        &mut self,
+        id: ExprId,
+        keyword: &Token,
+        method: &Token,
+    ) -> Result<LoxType, InterpreterError> {
+        let (distance, slot) = self.locals[&id];
+
+        let opt_superclass = self.env.borrow().get_at(distance, slot);
+
+        // `this` is the only variable in the scope just inside the one
+        // holding `super`.
+        let instance = self.env.borrow().get_at(distance - 1, 0).unwrap();
+
+        if let Some(LoxType::Class(ref superclass)) = opt_superclass {
+            if let Some(function) = superclass.borrow().find_method(&method.lexeme) {
+                Ok(LoxType::Callable(function.bind(instance)))
+            } else {
+                Err(InterpreterError::runtime_error(
+                    Some(method.clone()),
+                    &format!("Undefined property '{}'.", method.lexeme),
+                ))
+            }
+        } else {
+            Err(InterpreterError::runtime_error(
+                Some(method.clone()),
+                &format!("Undefined property '{}'.", keyword.lexeme),
+            ))
+        }
+    }
+
+    fn visit_this(&mut self, id: ExprId, keyword: &Token) -> Result<LoxType, InterpreterError> {
+        self.lookup_variable(id, keyword)
+    }
+
+    fn visit_tuple(&mut self, elements: &[Expr]) -> Result<LoxType, InterpreterError> {
+        let values = elements
+            .iter()
+            .map(|element| self.evaluate(element))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(LoxType::Tuple(values.into()))
+    }
+
+    fn visit_unary(&mut self, operator: &Token, right: &Expr) -> Result<LoxType, InterpreterError> {
+        let right_value = self.evaluate(right)?;
+
+        match operator.token_type {
+            TokenType::Bang => {
+                let b = bool::from(right_value);
+
+                Ok(LoxType::Boolean(!b))
+            }
+            TokenType::Minus => match right_value {
+                LoxType::Integer(n) => Ok(n
+                    .checked_neg()
+                    .map_or(LoxType::Number(-(n as f64)), LoxType::Integer)),
+                right_value => {
+                    let n = Self::check_number_operand(operator.clone(), right_value)?;
+
+                    Ok(LoxType::Number(-n))
+                }
+            },
+            TokenType::Tilde => {
+                let n = Self::check_number_operand(operator.clone(), right_value)?;
+
+                Ok(LoxType::Integer(!(n as i64)))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn visit_variable(&mut self, id: ExprId, name: &Token) -> Result<LoxType, InterpreterError> {
+        self.lookup_variable(id, name)
+    }
+}
+
+/// Applies a bitwise or shift operator. The operands are truncated to 64-bit
+/// integers and the result converted back, so `5.7 & 3` is `1`. Shift counts
+/// wrap at 64.
+pub(crate) fn bitwise(operator: &TokenType, n: f64, m: f64) -> i64 {
+    let (n, m) = (n as i64, m as i64);
+
+    match operator {
+        TokenType::Ampersand => n & m,
+        TokenType::Caret => n ^ m,
+        TokenType::Pipe => n | m,
+        TokenType::LessLess => n.wrapping_shl(m as u32),
+        TokenType::GreaterGreater => n.wrapping_shr(m as u32),
+        _ => unreachable!(),
+    }
+}
+
+/// Applies an arithmetic or comparison operator to two integers exactly.
+/// Returns `None` for other operators, and for results that aren't integers
+/// or don't fit, which are then computed with floats.
+pub(crate) fn integer_binary(operator: &TokenType, n: i64, m: i64) -> Option<LoxType> {
+    let value = match operator {
+        TokenType::Plus => LoxType::Integer(n.checked_add(m)?),
+        TokenType::Minus => LoxType::Integer(n.checked_sub(m)?),
+        TokenType::Star => LoxType::Integer(n.checked_mul(m)?),
+        TokenType::Slash if n.checked_rem(m)? == 0 => LoxType::Integer(n.checked_div(m)?),
+        TokenType::Greater => LoxType::Boolean(n > m),
+        TokenType::GreaterEqual => LoxType::Boolean(n >= m),
+        TokenType::Less => LoxType::Boolean(n < m),
+        TokenType::LessEqual => LoxType::Boolean(n <= m),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+/// The method an instance on the left of `operator` can define to overload
+/// it. `!=` negates `equals`.
+fn operator_method(operator: &TokenType) -> Option<&'static str> {
+    match operator {
+        TokenType::Plus => Some("plus"),
+        TokenType::Minus => Some("minus"),
+        TokenType::Star => Some("times"),
+        TokenType::Slash => Some("divide"),
+        TokenType::EqualEqual | TokenType::BangEqual => Some("equals"),
+        _ => None,
+    }
+}
+
+/// How a value that can't be unpacked is named in the error.
+fn describe_destructured(value: &LoxType) -> String {
+    match value {
+        LoxType::Tuple(values) => values.len().to_string(),
+        LoxType::Instance(_) => "an instance".to_string(),
+        value => format!("a {}", value.type_name()),
+    }
+}
@@ -1,12 +1,48 @@
-mod ast;
+pub mod ast;
+mod ast_cache;
+mod ast_printer;
+pub mod bench;
 mod class;
+pub mod coverage;
+mod debugger;
+#[cfg(not(target_arch = "wasm32"))]
+mod document;
 mod environment;
+pub mod error;
+pub mod formatter;
 mod function;
+mod heap;
+mod interner;
 mod interpreter;
+#[cfg(not(target_arch = "wasm32"))]
+mod line_editor;
 pub mod lox;
 mod lox_type;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod lsp;
+pub mod manifest;
+mod natives;
+mod optimizer;
+mod origin;
 mod parser;
-mod resolver;
-mod scanner;
-mod token;
-mod token_type;
+pub mod profile;
+pub mod project;
+mod random;
+pub mod resolver;
+pub mod scanner;
+mod shared;
+mod stack;
+pub mod stats;
+pub mod test_runner;
+pub mod token;
+pub mod token_filter;
+pub mod token_type;
+mod tracer;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use crate::{
+    function::Arity,
+    interpreter::{Interpreter, InterpreterError, InterruptHandle},
+    lox_type::LoxType,
+};
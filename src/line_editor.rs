@@ -0,0 +1,209 @@
+use std::{borrow::Cow, env, path::PathBuf};
+
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::{CmdKind, Highlighter},
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator,
+    Context, Editor, Helper,
+};
+
+use crate::scanner::{self, is_alpha_numberic, EXTENSION_KEYWORDS};
+
+const HISTORY_FILE: &str = ".rlox_history";
+
+const KEYWORD_COLOR: &str = "\x1b[35m";
+const STRING_COLOR: &str = "\x1b[32m";
+const NUMBER_COLOR: &str = "\x1b[33m";
+const COMMENT_COLOR: &str = "\x1b[90m";
+const RESET: &str = "\x1b[0m";
+
+/// What a call to `LineEditor::read_line` got from the user.
+pub enum Line {
+    Input(String),
+    /// Ctrl-C: the current line was abandoned.
+    Interrupted,
+    /// Ctrl-D or the end of piped input.
+    Eof,
+}
+
+/// Reads REPL input with history, highlighting and tab completion of
+/// keywords and global names.
+pub struct LineEditor {
+    editor: Editor<LoxHelper, DefaultHistory>,
+    opt_history: Option<PathBuf>,
+}
+
+impl LineEditor {
+    pub fn new(compat: bool) -> rustyline::Result<Self> {
+        let mut editor = Editor::new()?;
+
+        let keywords = scanner::keywords()
+            .filter(|keyword| !compat || !EXTENSION_KEYWORDS.contains(keyword))
+            .map(str::to_string)
+            .collect();
+
+        editor.set_helper(Some(LoxHelper {
+            keywords,
+            globals: Vec::new(),
+        }));
+
+        let opt_history = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+
+        if let Some(ref history) = opt_history {
+            let _ = editor.load_history(history);
+        }
+
+        Ok(Self {
+            editor,
+            opt_history,
+        })
+    }
+
+    /// Replaces the global names offered by tab completion.
+    pub fn set_globals(&mut self, globals: Vec<String>) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.globals = globals;
+        }
+    }
+
+    pub fn read_line(&mut self, prompt: &str) -> rustyline::Result<Line> {
+        match self.editor.readline(prompt) {
+            Ok(input) => {
+                if !input.trim().is_empty() {
+                    self.editor.add_history_entry(input.as_str())?;
+                }
+
+                Ok(Line::Input(input))
+            }
+            Err(ReadlineError::Interrupted) => Ok(Line::Interrupted),
+            Err(ReadlineError::Eof) => Ok(Line::Eof),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Drop for LineEditor {
+    fn drop(&mut self) {
+        if let Some(ref history) = self.opt_history {
+            let _ = self.editor.save_history(history);
+        }
+    }
+}
+
+struct LoxHelper {
+    keywords: Vec<String>,
+    globals: Vec<String>,
+}
+
+impl Completer for LoxHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !is_alpha_numberic(c))
+            .map_or(0, |(index, c)| index + c.len_utf8());
+
+        let prefix = &line[start..pos];
+
+        if prefix.is_empty() {
+            return Ok((start, Vec::new()));
+        }
+
+        let mut candidates: Vec<_> = self
+            .keywords
+            .iter()
+            .chain(self.globals.iter())
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| Pair {
+                display: name.clone(),
+                replacement: name.clone(),
+            })
+            .collect();
+
+        candidates.sort_by(|a, b| a.display.cmp(&b.display));
+        candidates.dedup_by(|a, b| a.display == b.display);
+
+        Ok((start, candidates))
+    }
+}
+
+impl Highlighter for LoxHelper {
+    fn highlight<'l>(&self, line: &'l str, _: usize) -> Cow<'l, str> {
+        Cow::Owned(highlight(line, &self.keywords))
+    }
+
+    fn highlight_char(&self, _: &str, _: usize, _: CmdKind) -> bool {
+        true
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
+
+/// Colors keywords, literals and comments with ANSI escapes.
+fn highlight(line: &str, keywords: &[String]) -> String {
+    let mut out = String::new();
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if c == '/' && line[start..].starts_with("//") {
+            out.push_str(&format!("{}{}{}", COMMENT_COLOR, &line[start..], RESET));
+
+            break;
+        } else if c == '"' {
+            let mut end = line.len();
+
+            for (index, c) in chars.by_ref() {
+                if c == '"' {
+                    end = index + 1;
+
+                    break;
+                }
+            }
+
+            out.push_str(&format!("{}{}{}", STRING_COLOR, &line[start..end], RESET));
+        } else if is_alpha_numberic(c) {
+            let start_is_digit = c.is_ascii_digit();
+            let mut end = start + c.len_utf8();
+
+            while let Some(&(index, c)) = chars.peek() {
+                if !(is_alpha_numberic(c) || (c == '.' && start_is_digit)) {
+                    break;
+                }
+
+                end = index + c.len_utf8();
+
+                chars.next();
+            }
+
+            let word = &line[start..end];
+
+            if start_is_digit {
+                out.push_str(&format!("{}{}{}", NUMBER_COLOR, word, RESET));
+            } else if keywords.iter().any(|keyword| keyword == word) {
+                out.push_str(&format!("{}{}{}", KEYWORD_COLOR, word, RESET));
+            } else {
+                out.push_str(word);
+            }
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
@@ -1,158 +1,697 @@
 use std::{
-    fs::File,
-    io::{stdin, stdout, Read, Write},
-    path::Path,
-    sync::atomic::{AtomicBool, Ordering},
+    fs,
+    io::{self, stdout, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
 use crate::{
-    interpreter::{Interpreter, InterpreterError},
+    ast::Stmt,
+    ast_cache,
+    ast_printer::AstPrinter,
+    debugger::Debugger,
+    error::Diagnostics,
+    interpreter::{Interpreter, Output},
+    lox_type::LoxType,
+    optimizer,
     parser::Parser,
-    resolver::Resolver,
+    resolver::{ResolvedProgram, Resolver},
     scanner::Scanner,
-    token::Token,
-    token_type::TokenType,
+    shared::{Locked, Shared},
+    stats::Stats,
+    token_filter::TokenFilter,
+    tracer::Tracer,
 };
 
-static HAD_ERROR: AtomicBool = AtomicBool::new(false);
-static HAD_RUNTIME_ERROR: AtomicBool = AtomicBool::new(false);
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread::{self, JoinHandle};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::line_editor::{Line, LineEditor};
+
+/// Stack size for threads that run Lox. Every Lox call nests several Rust
+/// frames, so a default thread stack overflows long before
+/// `max_call_depth` calls.
+pub const STACK_SIZE: usize = 512 * 1024 * 1024;
+
+/// The stack `Limits` assumes the interpreter has by default: what Rust
+/// gives threads it spawns, or wasm32's whole stack.
+#[cfg(not(target_arch = "wasm32"))]
+pub const DEFAULT_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+/// The stack `Limits` assumes the interpreter has by default: what Rust
+/// gives threads it spawns, or wasm32's whole stack.
+#[cfg(target_arch = "wasm32")]
+pub const DEFAULT_STACK_SIZE: usize = 1024 * 1024;
+
+/// Default limit on nested user function calls. Only threads with about
+/// `STACK_SIZE` of stack reach it; on smaller ones `Limits::stack_size`
+/// stops recursion first.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+/// Bounds that keep hostile or runaway programs from exhausting the host,
+/// reporting an error instead.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Parameters a function can declare and arguments a call can pass.
+    pub max_arguments: usize,
+    /// Statements and expressions nested inside each other before parsing
+    /// fails with "Too much nesting.". Each link of a chain like `a + b + c`
+    /// or `f()()` counts as a level too.
+    pub max_nesting_depth: usize,
+    /// Nested user function calls allowed before raising "Stack overflow.".
+    pub max_call_depth: usize,
+    /// Bytes of stack the thread running the interpreter has. Calls and
+    /// expressions that would come close to using it up also raise "Stack
+    /// overflow.", and parsing or resolving that would fails with "Too much
+    /// nesting.", so deep code fails with an error rather than crashing. Set this to
+    /// the thread's real stack size to allow deeper recursion, e.g.
+    /// `STACK_SIZE` on a thread spawned with it. Setting it higher than
+    /// the real size makes deep recursion crash the process again.
+    pub stack_size: usize,
+    /// Statements executed before raising "Execution budget exceeded.".
+    pub opt_max_steps: Option<usize>,
+    /// Time from creating the interpreter until statements raise
+    /// "Execution budget exceeded.". Not supported in the browser.
+    pub opt_max_time: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_arguments: 255,
+            max_nesting_depth: 200,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            stack_size: DEFAULT_STACK_SIZE,
+            opt_max_steps: None,
+            opt_max_time: None,
+        }
+    }
+}
+
+/// Which groups of native functions scripts can call, so embedders can run
+/// untrusted code. Everything is allowed by default; a denied native isn't
+/// defined at all.
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    /// `read_file`, `write_file`, `append_file`, `file_exists` and `import`.
+    pub allow_fs: bool,
+    /// `readline`.
+    pub allow_stdin: bool,
+    /// `clock`, `sleep`, `now_iso` and `elapsed`.
+    pub allow_time: bool,
+    /// `arg`, `arg_count` and `exit`.
+    pub allow_process: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            allow_fs: true,
+            allow_stdin: true,
+            allow_time: true,
+            allow_process: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Treat keywords added on top of the book as plain identifiers.
+    pub compat: bool,
+    /// Remember where string literals came from so `origin(value)` can report
+    /// it.
+    pub track_origins: bool,
+    /// Print the parsed program as s-expressions instead of running it.
+    pub print_ast: bool,
+    /// Print the scanned tokens instead of running the program.
+    pub print_tokens: bool,
+    /// Give `for` loops a new binding of their variables on each pass, so
+    /// closures created in the body keep that pass's values.
+    pub fresh_loop_bindings: bool,
+    /// Raise runtime errors for division by zero and NaN instead of
+    /// following IEEE semantics.
+    pub strict_math: bool,
+    /// Report local variables that shadow outer ones, and make unused locals
+    /// and code after `return` errors rather than warnings.
+    pub strict: bool,
+    /// Run the program under the interactive debugger.
+    pub debug: bool,
+    /// Log each statement to stderr as it runs.
+    pub trace: bool,
+    /// Also log user function calls with their arguments and return values.
+    pub trace_calls: bool,
+    /// Directories searched for `import` paths that aren't found next to the
+    /// importing file.
+    pub module_paths: Vec<PathBuf>,
+    /// Caps on arguments, nesting and call depth.
+    pub limits: Limits,
+    pub capabilities: Capabilities,
+    /// Print execution counters and wall time after running a script.
+    pub stats: bool,
+    /// Print call counts and time spent per function after running a script.
+    pub profile: bool,
+    /// Print which lines of the script and its modules ran.
+    pub coverage: bool,
+    /// Write line coverage as an lcov tracefile to this path.
+    pub opt_lcov_path: Option<PathBuf>,
+    /// Fold constant expressions and drop dead branches before resolving.
+    pub optimize: bool,
+    /// Save the parsed statements of scripts next to them and reuse them
+    /// while the source is unchanged, skipping scanning and parsing.
+    pub cache_ast: bool,
+}
 
-pub fn run_file(path_name: &str) {
-    let file_path = Path::new(path_name);
+/// An in-memory output sink whose clones share one buffer, so the contents
+/// can be read back after the interpreter that owns a clone is done.
+#[derive(Clone, Default)]
+pub(crate) struct Capture(Shared<Locked<Vec<u8>>>);
 
-    let file_res = File::open(file_path);
+impl Capture {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
 
-    match file_res {
-        Ok(mut src_file) => {
-            let mut src = String::new();
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
 
-            let read_res = src_file.read_to_string(&mut src);
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
-            match read_res {
-                Ok(_) => {
-                    let mut interpreter = Interpreter::new();
+pub fn run_file(path_name: &str, args: &[String], options: &Options) {
+    match run_script_with_stats(Path::new(path_name), args, options, Box::new(stdout())) {
+        Ok((diagnostics, stats)) => {
+            report(&diagnostics, &mut stdout());
 
-                    run(&src, &mut interpreter);
+            if options.stats {
+                println!("{}", stats);
+            }
 
-                    if had_error() {
-                        std::process::exit(65);
-                    }
+            if let Some(profile) = stats.opt_profile {
+                println!("{}", profile);
+            }
+
+            if let Some(coverage) = stats.opt_coverage {
+                if options.coverage {
+                    println!("{}", coverage);
+                }
 
-                    if had_runtime_error() {
-                        std::process::exit(70);
+                if let Some(ref lcov_path) = options.opt_lcov_path {
+                    if let Err(err) = fs::write(lcov_path, coverage.lcov()) {
+                        println!("error: could not write {}: {}", lcov_path.display(), err);
                     }
                 }
-                Err(_) => println!("error: could not read {}", path_name),
             }
+
+            if diagnostics.had_error() {
+                std::process::exit(65);
+            }
+
+            if diagnostics.had_runtime_error() {
+                std::process::exit(70);
+            }
+
+            if let Some(code) = diagnostics.exit_code() {
+                std::process::exit(code);
+            }
+        }
+        Err(_) => println!("error: could not read {}", path_name),
+    }
+}
+
+/// Runs the script at `file_path` in a fresh interpreter and returns the
+/// diagnostics it produced without printing them.
+pub fn run_script(file_path: &Path, args: &[String], options: &Options) -> io::Result<Diagnostics> {
+    run_script_with_output(file_path, args, options, Box::new(stdout()))
+}
+
+/// Like `run_script`, but sends the output of `print` statements to `output`.
+pub fn run_script_with_output(
+    file_path: &Path,
+    args: &[String],
+    options: &Options,
+    output: Output,
+) -> io::Result<Diagnostics> {
+    run_script_with_stats(file_path, args, options, output).map(|(diagnostics, _)| diagnostics)
+}
+
+/// Like `run_script_with_output`, but also returns the work counters and
+/// wall time of the run.
+pub fn run_script_with_stats(
+    file_path: &Path,
+    args: &[String],
+    options: &Options,
+    output: Output,
+) -> io::Result<(Diagnostics, Stats)> {
+    // `-` reads the script from stdin, e.g. when it's piped in.
+    let is_stdin = file_path == Path::new("-");
+
+    let src = if is_stdin {
+        io::read_to_string(io::stdin())?
+    } else {
+        fs::read_to_string(file_path)?
+    };
+
+    let mut interpreter = Interpreter::with_output(options.clone(), output);
+    let mut diagnostics = Diagnostics::new();
+
+    interpreter.set_args(args);
+
+    interpreter.set_script(if is_stdin {
+        Path::new("<stdin>")
+    } else {
+        file_path
+    });
+
+    if options.debug {
+        interpreter.set_hook(Box::new(Debugger::new()));
+    } else if options.trace || options.trace_calls {
+        interpreter.set_hook(Box::new(Tracer::new(options.trace_calls)));
+    }
+
+    let start = Instant::now();
+
+    // Origins and token dumps need the tokens, which the cache skips.
+    let can_cache =
+        options.cache_ast && !is_stdin && !options.track_origins && !options.print_tokens;
+
+    let opt_cached = if can_cache {
+        ast_cache::load(file_path, &src, options.compat)
+    } else {
+        None
+    };
+
+    let opt_statements = opt_cached.or_else(|| {
+        let opt_statements = parse_source(&src, &mut interpreter, &mut diagnostics, &mut []);
+
+        if let Some(statements) = opt_statements.as_ref().filter(|_| can_cache) {
+            ast_cache::store(file_path, &src, options.compat, statements);
         }
-        Err(_) => println!("error: could not open {}", path_name),
+
+        opt_statements
+    });
+
+    if let Some(statements) = opt_statements {
+        interpreter.track_coverage(&statements);
+
+        run_statements(statements, &mut interpreter, &mut diagnostics, false);
+    }
+
+    let stats = Stats {
+        elapsed: start.elapsed(),
+        ..interpreter.stats()
     };
+
+    Ok((diagnostics, stats))
 }
 
-pub fn run_prompt() {
-    let mut input = String::new();
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_prompt(options: &Options) {
+    let mut interpreter = Interpreter::with_options(options.clone());
+    let mut diagnostics = Diagnostics::new();
 
-    let mut interpreter = Interpreter::new();
+    let mut editor = match LineEditor::new(options.compat) {
+        Ok(editor) => editor,
+        Err(err) => {
+            println!("error: could not start the line editor: {}", err);
 
-    loop {
-        print!("> ");
+            return;
+        }
+    };
 
-        let _ = stdout().flush();
+    // Ctrl-C stops the running input instead of the whole REPL. While a line
+    // is being edited the editor reads it as a key, so this doesn't fire.
+    let handle = interpreter.interrupt_handle();
 
-        match stdin().read_line(&mut input) {
-            Ok(_) => {
-                if let Some('\n') = input.chars().next_back() {
-                    input.pop();
-                }
+    if let Err(err) = ctrlc::set_handler(move || handle.interrupt()) {
+        println!("error: could not handle Ctrl-C: {}", err);
+    }
 
-                if let Some('\r') = input.chars().next_back() {
-                    input.pop();
-                }
+    loop {
+        editor.set_globals(interpreter.global_names());
 
-                run(&input, &mut interpreter);
+        let mut input = match editor.read_line("> ") {
+            Ok(Line::Input(input)) => input,
+            Ok(Line::Interrupted) => continue,
+            Ok(Line::Eof) => break,
+            Err(err) => {
+                println!("error: {}", err);
 
-                set_had_error(false);
-                set_had_runtime_error(false);
-            }
-            Err(_) => {
-                println!("error: bad input");
+                break;
             }
+        };
+
+        terminate(&mut input);
+
+        run(&input, &mut interpreter, &mut diagnostics, &mut [], true);
+
+        report(&diagnostics, interpreter.output());
+
+        if let Some(code) = diagnostics.exit_code() {
+            std::process::exit(code);
         }
 
-        input.clear();
+        diagnostics.clear();
+    }
+}
+
+/// Runs a one-liner given on the command line, printing the value of a lone
+/// expression the way the REPL does.
+pub fn run_eval(src: &str, options: &Options) {
+    let mut interpreter = Interpreter::with_options(options.clone());
+    let mut diagnostics = Diagnostics::new();
+
+    let mut input = src.to_string();
+
+    terminate(&mut input);
+
+    run(&input, &mut interpreter, &mut diagnostics, &mut [], true);
+
+    report(&diagnostics, interpreter.output());
+
+    if diagnostics.had_error() {
+        std::process::exit(65);
+    }
+
+    if diagnostics.had_runtime_error() {
+        std::process::exit(70);
+    }
+
+    if let Some(code) = diagnostics.exit_code() {
+        std::process::exit(code);
     }
 }
 
-fn run(src: &str, interpreter: &mut Interpreter) {
-    let mut scanner = Scanner::new(src);
+/// Creates an interpreter on a new thread with a stack big enough for
+/// `max_call_depth` calls, and hands it to `f`. Without the `sync` feature
+/// an interpreter and its values stay on the thread that made them, so only
+/// what `f` returns comes back through the handle.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn spawn_interpreter<F, T>(options: Options, f: F) -> io::Result<JoinHandle<T>>
+where
+    F: FnOnce(&mut Interpreter) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let options = Options {
+        limits: Limits {
+            stack_size: STACK_SIZE,
+            ..options.limits
+        },
+        ..options
+    };
+
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || f(&mut Interpreter::with_options(options)))
+}
+
+/// Runs `src` in a fresh interpreter and returns every diagnostic produced
+/// along the way instead of printing them.
+pub fn run_source(src: &str) -> Diagnostics {
+    run_filtered(src, &mut [])
+}
+
+/// Runs `src` in a fresh interpreter and returns everything it printed along
+/// with its diagnostics, for hosts without a terminal.
+pub fn run_source_captured(src: &str, options: &Options) -> (String, Diagnostics) {
+    let capture = Capture::default();
+
+    let mut interpreter = Interpreter::with_output(options.clone(), Box::new(capture.clone()));
+
+    let diagnostics = run_with(src, &mut interpreter);
+
+    (capture.contents(), diagnostics)
+}
+
+/// Runs `src` in an existing interpreter, e.g. one with host-defined natives.
+pub fn run_with(src: &str, interpreter: &mut Interpreter) -> Diagnostics {
+    let mut diagnostics = Diagnostics::new();
+
+    run(src, interpreter, &mut diagnostics, &mut [], false);
 
-    let tokens = scanner.scan_tokens();
+    diagnostics
+}
 
-    if had_error() {
-        return;
+/// Evaluates `src` in a fresh interpreter with `bindings` defined as
+/// globals, returning the value of its last expression, or nil if it doesn't
+/// end with one. Warnings are dropped unless there was also an error.
+pub fn eval(
+    src: &str,
+    bindings: &[(&str, LoxType)],
+    options: &Options,
+) -> Result<LoxType, Diagnostics> {
+    let mut interpreter = Interpreter::with_options(options.clone());
+
+    for (name, value) in bindings {
+        interpreter.define_global(name, value.clone());
     }
 
-    let mut parser = Parser::new(tokens.clone());
+    eval_with(src, &mut interpreter)
+}
+
+/// Like `eval`, but in an existing interpreter, e.g. one with host-defined
+/// natives. Fails if the program didn't finish, including when it called
+/// `exit()`. The value stays valid across later runs and collections for as
+/// long as it's held.
+pub fn eval_with(src: &str, interpreter: &mut Interpreter) -> Result<LoxType, Diagnostics> {
+    let mut diagnostics = Diagnostics::new();
+
+    let mut input = src.to_string();
+
+    terminate(&mut input);
 
-    let statements = parser.parse();
+    let opt_value = parse_source(&input, interpreter, &mut diagnostics, &mut [])
+        .and_then(|statements| prepare_statements(statements, interpreter, &mut diagnostics, false))
+        .and_then(|statements| interpreter.interpret_to_value(&statements, &mut diagnostics));
 
-    if had_error() {
-        return;
+    match opt_value {
+        Some(value) if diagnostics.errors().is_empty() => Ok(value),
+        _ => Err(diagnostics),
     }
+}
+
+/// Scans and parses `src` without running it, for custom passes over the
+/// AST. The statements are only complete if there were no errors.
+pub fn parse(src: &str, options: &Options) -> (Vec<Stmt>, Diagnostics) {
+    let mut diagnostics = Diagnostics::new();
+
+    let statements = Parser::new(Scanner::new(src).compat(options.compat), &mut diagnostics)
+        .limits(options.limits.clone())
+        .parse();
+
+    (statements, diagnostics)
+}
 
-    let mut resolver = Resolver::new(interpreter);
+/// Scans, parses and resolves `src` without running it. Resolution still
+/// runs after syntax errors so every error in the file is reported at once.
+pub fn check(src: &str, options: &Options) -> Diagnostics {
+    let (statements, mut diagnostics) = parse(src, options);
+
+    let mut interpreter = Interpreter::with_options(options.clone());
+
+    let mut resolver = Resolver::new(&mut interpreter, &mut diagnostics);
 
     resolver.resolve(&statements);
+    resolver.warn_unused_globals();
 
-    if had_error() {
-        return;
-    }
+    diagnostics
+}
+
+/// Like `check`, but also returns the scopes, declarations and variable
+/// uses the resolver found, for tools that work with names.
+pub fn analyze(src: &str, options: &Options) -> (ResolvedProgram, Diagnostics) {
+    let (statements, mut diagnostics) = parse(src, options);
 
-    interpreter.interpret(&statements);
+    let mut interpreter = Interpreter::with_options(options.clone());
+
+    let mut resolver = Resolver::new(&mut interpreter, &mut diagnostics).record_program();
+
+    resolver.resolve(&statements);
+    resolver.warn_unused_globals();
+
+    (resolver.into_program(), diagnostics)
 }
 
-pub fn error(line: usize, message: &str) {
-    report(line, "", message);
+/// Checks each of `files`, printing their warnings and errors prefixed with
+/// the path. Returns false if any file couldn't be read or had errors.
+pub fn check_files(files: &[PathBuf], options: &Options) -> bool {
+    let mut ok = true;
+
+    for file in files {
+        let diagnostics = match fs::read_to_string(file) {
+            Ok(src) => check(&src, options),
+            Err(err) => {
+                println!("{}: {}", file.display(), err);
+
+                ok = false;
+
+                continue;
+            }
+        };
+
+        for warning in diagnostics.warnings() {
+            println!("{}: {}", file.display(), warning);
+        }
+
+        for err in diagnostics.errors() {
+            println!("{}: {}", file.display(), err);
+        }
+
+        if diagnostics.had_error() {
+            ok = false;
+        }
+    }
+
+    ok
 }
 
-fn report(line: usize, where_: &str, message: &str) {
-    println!("[line {}] Error{}: {}", line, where_, message);
+/// Like `run_source`, but passes the scanned tokens through `filters` before
+/// parsing.
+pub fn run_filtered(src: &str, filters: &mut [Box<dyn TokenFilter>]) -> Diagnostics {
+    let mut interpreter = Interpreter::new();
+    let mut diagnostics = Diagnostics::new();
+
+    run(src, &mut interpreter, &mut diagnostics, filters, false);
 
-    set_had_error(true);
+    diagnostics
 }
 
-pub fn parse_error(token: &Token, message: &str) {
-    if token.token_type == TokenType::Eof {
-        report(token.line, " at end", message)
-    } else {
-        report(token.line, &format!(" at '{}'", token.lexeme), message)
+fn run(
+    src: &str,
+    interpreter: &mut Interpreter,
+    diagnostics: &mut Diagnostics,
+    filters: &mut [Box<dyn TokenFilter>],
+    echo: bool,
+) {
+    if let Some(statements) = parse_source(src, interpreter, diagnostics, filters) {
+        run_statements(statements, interpreter, diagnostics, echo);
     }
 }
 
-pub fn runtime_error(err: InterpreterError) {
-    if let InterpreterError::RuntimeError(err) = err {
-        if let Some(token) = err.token {
-            println!("{}\n[line {}]", err.message, token.line);
-        } else {
-            println!("{}", err.message);
+/// Scans and parses `src`, or prints its tokens if asked to. Returns the
+/// statements if there were no errors and there's more to do.
+fn parse_source(
+    src: &str,
+    interpreter: &mut Interpreter,
+    diagnostics: &mut Diagnostics,
+    filters: &mut [Box<dyn TokenFilter>],
+) -> Option<Vec<Stmt>> {
+    let mut tokens = Scanner::new(src)
+        .compat(interpreter.options().compat)
+        .scan_tokens(diagnostics);
+
+    for filter in filters.iter_mut() {
+        tokens = filter.filter(tokens);
+    }
+
+    if interpreter.options().print_tokens {
+        for token in &tokens {
+            match token.literal {
+                Some(ref literal) => println!(
+                    "{:>4} {:?} '{}' {}",
+                    token.line, token.token_type, token.lexeme, literal
+                ),
+                None => println!(
+                    "{:>4} {:?} '{}'",
+                    token.line, token.token_type, token.lexeme
+                ),
+            }
         }
 
-        set_had_runtime_error(true);
+        return None;
+    }
+
+    if diagnostics.had_error() {
+        return None;
+    }
+
+    interpreter.record_origins(&tokens);
+
+    let statements = Parser::new(tokens.into_iter().map(Ok), diagnostics)
+        .limits(interpreter.options().limits.clone())
+        .parse();
+
+    if diagnostics.had_error() {
+        None
+    } else {
+        Some(statements)
     }
 }
 
-fn had_error() -> bool {
-    HAD_ERROR.load(Ordering::Relaxed)
+/// Optimizes, resolves and runs parsed statements, or prints them if asked
+/// to.
+fn run_statements(
+    statements: Vec<Stmt>,
+    interpreter: &mut Interpreter,
+    diagnostics: &mut Diagnostics,
+    echo: bool,
+) {
+    let statements = match prepare_statements(statements, interpreter, diagnostics, echo) {
+        Some(statements) => statements,
+        None => return,
+    };
+
+    if echo {
+        interpreter.interpret_and_echo(&statements, diagnostics);
+    } else {
+        interpreter.interpret(&statements, diagnostics);
+    }
 }
 
-fn set_had_error(b: bool) {
-    HAD_ERROR.store(b, Ordering::Relaxed);
+/// Optimizes and resolves parsed statements, or prints them if asked to.
+/// Returns them if they're ready to run.
+fn prepare_statements(
+    mut statements: Vec<Stmt>,
+    interpreter: &mut Interpreter,
+    diagnostics: &mut Diagnostics,
+    echo: bool,
+) -> Option<Vec<Stmt>> {
+    if interpreter.options().optimize {
+        statements = optimizer::optimize(&statements);
+    }
+
+    if interpreter.options().print_ast {
+        println!("{}", AstPrinter.print(&statements));
+
+        return None;
+    }
+
+    let mut resolver = Resolver::new(interpreter, diagnostics);
+
+    resolver.resolve(&statements);
+
+    // Later REPL entries can still use what this one declares.
+    if !echo {
+        resolver.warn_unused_globals();
+    }
+
+    if diagnostics.had_error() {
+        return None;
+    }
+
+    Some(statements)
 }
 
-fn had_runtime_error() -> bool {
-    HAD_RUNTIME_ERROR.load(Ordering::Relaxed)
+/// Adds the `;` a lone expression needs, so one-liners can leave it off.
+fn terminate(input: &mut String) {
+    let trimmed = input.trim_end();
+
+    if !trimmed.is_empty() && !trimmed.ends_with([';', '}']) {
+        input.push(';');
+    }
 }
 
-fn set_had_runtime_error(b: bool) {
-    HAD_RUNTIME_ERROR.store(b, Ordering::Relaxed);
+pub fn report(diagnostics: &Diagnostics, output: &mut dyn Write) {
+    for warning in diagnostics.warnings() {
+        let _ = writeln!(output, "{}", warning);
+    }
+
+    for err in diagnostics.errors() {
+        let _ = writeln!(output, "{}", err);
+    }
 }
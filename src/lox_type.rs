@@ -1,19 +1,58 @@
-use std::{cell::RefCell, fmt, rc::Rc};
+use std::{collections::HashMap, convert::TryFrom, fmt};
 
 use crate::{
     class::{LoxClass, LoxInstance},
     function::Function,
+    interpreter::InterpreterError,
+    shared::{Locked, Shared},
 };
 
 #[derive(Debug, Clone)]
 pub enum LoxType {
     Boolean(bool),
     Callable(Function),
-    Class(Rc<RefCell<LoxClass>>),
-    Instance(Rc<RefCell<LoxInstance>>),
+    Class(Shared<Locked<LoxClass>>),
+    Instance(Shared<Locked<LoxInstance>>),
+    /// A number written without a decimal point, or computed exactly from
+    /// integers. Arithmetic that would overflow falls back to `Number`.
+    Integer(i64),
     Nil,
     Number(f64),
-    String(String),
+    String(Shared<str>),
+    /// Values returned together by `return a, b;`, unpacked with
+    /// `var (a, b) = ...;`.
+    Tuple(Shared<[LoxType]>),
+    /// Held by a variable declared without an initializer until it's
+    /// assigned. Reading it is an error, so Lox code never sees it.
+    Uninitialized,
+}
+
+impl LoxType {
+    /// The value of an `Integer` or `Number` as a float.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            LoxType::Integer(n) => Some(*n as f64),
+            LoxType::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The name `type()` reports: the kind of value, or the class name of
+    /// an instance.
+    pub fn type_name(&self) -> String {
+        use LoxType::*;
+
+        match self {
+            Boolean(_) => "boolean".to_string(),
+            Callable(_) => "function".to_string(),
+            Class(_) => "class".to_string(),
+            Instance(instance) => instance.borrow().class().borrow().name().to_string(),
+            Integer(_) | Number(_) => "number".to_string(),
+            Nil | Uninitialized => "nil".to_string(),
+            String(_) => "string".to_string(),
+            Tuple(_) => "tuple".to_string(),
+        }
+    }
 }
 
 impl From<LoxType> for bool {
@@ -28,6 +67,120 @@ impl From<LoxType> for bool {
     }
 }
 
+impl From<f64> for LoxType {
+    fn from(n: f64) -> Self {
+        LoxType::Number(n)
+    }
+}
+
+impl From<i64> for LoxType {
+    fn from(n: i64) -> Self {
+        LoxType::Integer(n)
+    }
+}
+
+impl From<bool> for LoxType {
+    fn from(b: bool) -> Self {
+        LoxType::Boolean(b)
+    }
+}
+
+impl From<&str> for LoxType {
+    fn from(s: &str) -> Self {
+        LoxType::String(s.into())
+    }
+}
+
+impl From<String> for LoxType {
+    fn from(s: String) -> Self {
+        LoxType::String(s.into())
+    }
+}
+
+/// `None` becomes nil.
+impl<T: Into<LoxType>> From<Option<T>> for LoxType {
+    fn from(opt_value: Option<T>) -> Self {
+        opt_value.map_or(LoxType::Nil, Into::into)
+    }
+}
+
+/// Lox has no lists, so a `Vec` becomes a tuple.
+impl<T: Into<LoxType>> From<Vec<T>> for LoxType {
+    fn from(values: Vec<T>) -> Self {
+        LoxType::Tuple(values.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Accepts integers and floats, like arithmetic does.
+impl TryFrom<LoxType> for f64 {
+    type Error = InterpreterError;
+
+    fn try_from(value: LoxType) -> Result<Self, Self::Error> {
+        value
+            .as_number()
+            .ok_or_else(|| mismatch("a number", &value))
+    }
+}
+
+/// Accepts integers, and floats with nothing after the decimal point.
+impl TryFrom<LoxType> for i64 {
+    type Error = InterpreterError;
+
+    fn try_from(value: LoxType) -> Result<Self, Self::Error> {
+        match value {
+            LoxType::Integer(n) => Ok(n),
+            LoxType::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Ok(n as i64),
+            _ => Err(mismatch("an integer", &value)),
+        }
+    }
+}
+
+impl TryFrom<LoxType> for String {
+    type Error = InterpreterError;
+
+    fn try_from(value: LoxType) -> Result<Self, Self::Error> {
+        match value {
+            LoxType::String(s) => Ok(s.to_string()),
+            _ => Err(mismatch("a string", &value)),
+        }
+    }
+}
+
+/// Reads the values of a tuple.
+impl<T: TryFrom<LoxType, Error = InterpreterError>> TryFrom<LoxType> for Vec<T> {
+    type Error = InterpreterError;
+
+    fn try_from(value: LoxType) -> Result<Self, Self::Error> {
+        match value {
+            LoxType::Tuple(values) => values.iter().cloned().map(T::try_from).collect(),
+            _ => Err(mismatch("a tuple", &value)),
+        }
+    }
+}
+
+/// Reads the fields of an instance.
+impl<T: TryFrom<LoxType, Error = InterpreterError>> TryFrom<LoxType> for HashMap<String, T> {
+    type Error = InterpreterError;
+
+    fn try_from(value: LoxType) -> Result<Self, Self::Error> {
+        match value {
+            LoxType::Instance(instance) => instance
+                .borrow()
+                .named_fields()
+                .map(|(name, field)| Ok((name.to_string(), T::try_from(field.clone())?)))
+                .collect(),
+            _ => Err(mismatch("an instance", &value)),
+        }
+    }
+}
+
+fn mismatch(expected: &str, value: &LoxType) -> InterpreterError {
+    InterpreterError::runtime_error(
+        None,
+        &format!("Expected {} but got {}.", expected, value.type_name()),
+    )
+}
+
 impl PartialEq for LoxType {
     fn eq(&self, other: &Self) -> bool {
         use LoxType::*;
@@ -36,7 +189,13 @@ impl PartialEq for LoxType {
             (Boolean(n), Boolean(m)) => n == m,
             (Nil, Nil) => true,
             (Number(n), Number(m)) => n == m,
+            (Integer(n), Integer(m)) => n == m,
+            (Integer(n), Number(m)) | (Number(m), Integer(n)) => *n as f64 == *m,
             (String(n), String(m)) => n == m,
+            (Callable(f), Callable(g)) => f == g,
+            (Class(n), Class(m)) => Shared::ptr_eq(n, m),
+            (Instance(n), Instance(m)) => Shared::ptr_eq(n, m),
+            (Tuple(n), Tuple(m)) => n == m,
             _ => false,
         }
     }
@@ -51,9 +210,31 @@ impl fmt::Display for LoxType {
             Class(class) => write!(f, "{}", class.borrow_mut()),
             Callable(function) => write!(f, "{}", function),
             Instance(instance) => write!(f, "{}", instance.borrow_mut()),
+            Integer(n) => write!(f, "{}", n),
             Nil => write!(f, "nil"),
-            Number(ref n) => write!(f, "{}", n),
+            Number(n) => write_number(f, *n),
             String(ref s) => write!(f, "{}", s),
+            Tuple(values) => {
+                let values: Vec<_> = values.iter().map(ToString::to_string).collect();
+
+                write!(f, "({})", values.join(", "))
+            }
+            Uninitialized => write!(f, "uninitialized"),
         }
     }
 }
+
+/// Writes a float the way Lox prints it: integral values without a `.0`,
+/// and plain decimals unless the number is very large or very small, where
+/// it switches to scientific notation like JavaScript.
+fn write_number(f: &mut fmt::Formatter<'_>, n: f64) -> fmt::Result {
+    if n.is_nan() {
+        write!(f, "NaN")
+    } else if n.is_infinite() {
+        write!(f, "{}Infinity", if n < 0.0 { "-" } else { "" })
+    } else if n != 0.0 && (n.abs() >= 1e21 || n.abs() < 1e-6) {
+        write!(f, "{:e}", n)
+    } else {
+        write!(f, "{}", n)
+    }
+}
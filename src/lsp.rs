@@ -0,0 +1,455 @@
+//! A language server speaking LSP over stdin and stdout. It reuses the
+//! scanner, parser and resolver to publish diagnostics, jump from a variable
+//! to its declaration and list the functions and classes in a file.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    ast::Stmt,
+    document::{Document, Position},
+    error::{Diagnostics, LoxError},
+    interpreter::Interpreter,
+    resolver::{ResolvedProgram, Resolver},
+    token::Token,
+};
+
+/// Clients send the changed ranges rather than the whole document.
+const SYNC_INCREMENTAL: u8 = 2;
+
+/// LSP error code for requests the server doesn't implement.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+const SEVERITY_ERROR: u8 = 1;
+const SEVERITY_WARNING: u8 = 2;
+
+const SYMBOL_CLASS: u8 = 5;
+const SYMBOL_METHOD: u8 = 6;
+const SYMBOL_FIELD: u8 = 8;
+const SYMBOL_FUNCTION: u8 = 12;
+
+/// An open document and what the front end learned about its current
+/// version.
+struct Analysis {
+    document: Document,
+    diagnostics: Diagnostics,
+    program: ResolvedProgram,
+}
+
+impl Analysis {
+    fn new(document: Document) -> Self {
+        let mut analysis = Self {
+            document,
+            diagnostics: Diagnostics::new(),
+            program: ResolvedProgram::default(),
+        };
+
+        analysis.resolve();
+
+        analysis
+    }
+
+    /// Resolves the document's statements again after it changed.
+    fn resolve(&mut self) {
+        let mut diagnostics = self.document.diagnostics().clone();
+
+        let mut interpreter = Interpreter::new();
+
+        let mut resolver = Resolver::new(&mut interpreter, &mut diagnostics).record_program();
+
+        resolver.resolve(self.document.statements());
+        resolver.warn_unused_globals();
+
+        self.program = resolver.into_program();
+        self.diagnostics = diagnostics;
+    }
+
+    /// The declaration of the variable under the cursor.
+    fn definition(&self, position: Position) -> Option<&Token> {
+        let reference = self
+            .program
+            .references
+            .iter()
+            .find(|reference| covers(&reference.name, position))?;
+
+        self.program
+            .definition(reference)
+            .map(|definition| &definition.name)
+    }
+}
+
+pub struct Server {
+    compat: bool,
+    documents: HashMap<String, Analysis>,
+    output: Box<dyn Write>,
+}
+
+impl Server {
+    pub fn new(compat: bool, output: Box<dyn Write>) -> Self {
+        Self {
+            compat,
+            documents: HashMap::new(),
+            output,
+        }
+    }
+
+    /// Serves messages from `input` until the client sends `exit` or closes
+    /// the stream.
+    pub fn run(&mut self, input: &mut dyn BufRead) -> io::Result<()> {
+        while let Some(message) = read_message(input)? {
+            if message["method"] == "exit" {
+                break;
+            }
+
+            self.handle(&message)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, message: &Value) -> io::Result<()> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": SYNC_INCREMENTAL,
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "rlox" },
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+
+                return self.open(&document["uri"], &document["text"]);
+            }
+            "textDocument/didChange" => {
+                return self.change(&params["textDocument"]["uri"], &params["contentChanges"]);
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+
+                self.documents.remove(uri);
+
+                return self.notify(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                );
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            _ => {
+                // Notifications we don't handle need no answer.
+                if message["id"].is_null() {
+                    return Ok(());
+                }
+
+                return self.send(&json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("Unsupported method '{}'.", method),
+                    },
+                }));
+            }
+        };
+
+        if message["id"].is_null() {
+            return Ok(());
+        }
+
+        self.send(&json!({ "jsonrpc": "2.0", "id": message["id"], "result": result }))
+    }
+
+    fn open(&mut self, uri: &Value, text: &Value) -> io::Result<()> {
+        let uri = uri.as_str().unwrap_or_default().to_string();
+        let text = text.as_str().unwrap_or_default().to_string();
+
+        let analysis = Analysis::new(Document::new(text, self.compat));
+
+        self.documents.insert(uri.clone(), analysis);
+
+        self.publish(&uri)
+    }
+
+    /// Applies changes in order. Each one replaces either a range or, without
+    /// a range, the whole text.
+    fn change(&mut self, uri: &Value, changes: &Value) -> io::Result<()> {
+        let uri = uri.as_str().unwrap_or_default();
+
+        let analysis = match self.documents.get_mut(uri) {
+            Some(analysis) => analysis,
+            None => return Ok(()),
+        };
+
+        for change in changes.as_array().map_or(&[][..], Vec::as_slice) {
+            let text = change["text"].as_str().unwrap_or_default();
+
+            match change.get("range") {
+                Some(range) => {
+                    let start = position(analysis.document.text(), &range["start"]);
+                    let end = position(analysis.document.text(), &range["end"]);
+
+                    analysis.document.edit(start, end, text)
+                }
+                None => analysis.document.replace(text.to_string()),
+            }
+        }
+
+        analysis.resolve();
+
+        self.publish(uri)
+    }
+
+    fn publish(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(analysis) => publish_diagnostics(analysis.document.text(), &analysis.diagnostics),
+            None => Vec::new(),
+        };
+
+        self.notify(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        )
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+
+        let analysis = match self.documents.get(uri) {
+            Some(analysis) => analysis,
+            None => return Value::Null,
+        };
+
+        let text = analysis.document.text();
+
+        analysis
+            .definition(position(text, &params["position"]))
+            .map_or(
+                Value::Null,
+                |declaration| json!({ "uri": uri, "range": token_range(text, declaration) }),
+            )
+    }
+
+    fn document_symbols(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+
+        match self.documents.get(uri) {
+            Some(analysis) => Value::Array(symbols(
+                analysis.document.text(),
+                analysis.document.statements(),
+                false,
+            )),
+            None => Value::Null,
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let body = message.to_string();
+
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+
+        self.output.flush()
+    }
+}
+
+/// Runs a language server on stdin and stdout.
+pub fn run(compat: bool) -> io::Result<()> {
+    let stdin = io::stdin();
+
+    Server::new(compat, Box::new(io::stdout())).run(&mut stdin.lock())
+}
+
+/// Reads one `Content-Length` framed message, or `None` at the end of input.
+fn read_message(input: &mut dyn BufRead) -> io::Result<Option<Value>> {
+    let mut opt_length = None;
+
+    loop {
+        let mut header = String::new();
+
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some(length) = header.strip_prefix("Content-Length:") {
+            opt_length = length.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = opt_length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+
+    let mut body = vec![0; length];
+
+    input.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Reads an LSP position in `text`. LSP counts UTF-16 code units along a
+/// line, while documents and tokens count characters.
+fn position(text: &str, position: &Value) -> Position {
+    let line = position["line"].as_u64().unwrap_or_default() as usize;
+    let character = position["character"].as_u64().unwrap_or_default() as usize;
+
+    let mut units = 0;
+
+    let column = line_text(text, line)
+        .chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+
+            units <= character
+        })
+        .count();
+
+    Position {
+        line,
+        character: column,
+    }
+}
+
+/// The zero-based `line` of `text`, without its line break.
+fn line_text(text: &str, line: usize) -> &str {
+    text.lines().nth(line).unwrap_or_default()
+}
+
+/// How many UTF-16 code units the first `column` characters of `line` take.
+fn utf16_column(line: &str, column: usize) -> usize {
+    line.chars().take(column).map(char::len_utf16).sum()
+}
+
+/// Diagnostics only carry a line, so each one covers its whole line.
+fn publish_diagnostics(text: &str, diagnostics: &Diagnostics) -> Vec<Value> {
+    let line_range = |line: usize| {
+        let length = text
+            .lines()
+            .nth(line.saturating_sub(1))
+            .map_or(0, |text| text.encode_utf16().count());
+
+        json!({
+            "start": { "line": line.saturating_sub(1), "character": 0 },
+            "end": { "line": line.saturating_sub(1), "character": length },
+        })
+    };
+
+    let errors = diagnostics.errors().iter().filter_map(|err| match err {
+        LoxError::Compile {
+            line,
+            location,
+            message,
+        } => Some(json!({
+            "range": line_range(*line),
+            "severity": SEVERITY_ERROR,
+            "source": "rlox",
+            "message": format!("Error{}: {}", location, message),
+        })),
+        LoxError::Runtime { .. } => None,
+    });
+
+    let warnings = diagnostics.warnings().iter().map(|warning| {
+        json!({
+            "range": line_range(warning.line),
+            "severity": SEVERITY_WARNING,
+            "source": "rlox",
+            "message": format!("Warning{}: {}", warning.location, warning.message),
+        })
+    });
+
+    errors.chain(warnings).collect()
+}
+
+/// Functions and classes declared in `stmts`, with their fields, methods
+/// and nested functions as children.
+fn symbols(text: &str, stmts: &[Stmt], in_class: bool) -> Vec<Value> {
+    let mut symbols = Vec::new();
+
+    for stmt in stmts {
+        match stmt {
+            Stmt::Block(body) => symbols.extend(self::symbols(text, body, false)),
+            Stmt::Class {
+                name,
+                fields,
+                methods,
+                ..
+            } => {
+                let mut members: Vec<Value> = fields
+                    .iter()
+                    .map(|(field_name, _)| symbol(text, field_name, SYMBOL_FIELD, Vec::new()))
+                    .collect();
+
+                members.extend(self::symbols(text, methods, true));
+
+                symbols.push(symbol(text, name, SYMBOL_CLASS, members));
+            }
+            Stmt::Function { name, body, .. } => {
+                let kind = if in_class {
+                    SYMBOL_METHOD
+                } else {
+                    SYMBOL_FUNCTION
+                };
+
+                symbols.push(symbol(text, name, kind, self::symbols(text, body, false)));
+            }
+            _ => (),
+        }
+    }
+
+    symbols
+}
+
+fn symbol(text: &str, name: &Token, kind: u8, children: Vec<Value>) -> Value {
+    json!({
+        "name": &*name.lexeme,
+        "kind": kind,
+        "range": token_range(text, name),
+        "selectionRange": token_range(text, name),
+        "children": children,
+    })
+}
+
+/// Where `token` is in `text`, counted in UTF-16 code units.
+fn token_range(text: &str, token: &Token) -> Value {
+    let line = token.line.saturating_sub(1);
+    let line_text = line_text(text, line);
+
+    let start = utf16_column(line_text, token.column);
+    let end = utf16_column(line_text, token.column + token.lexeme.chars().count());
+
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end },
+    })
+}
+
+/// Whether `position`, counted in characters, falls inside `token`.
+fn covers(token: &Token, position: Position) -> bool {
+    token.line == position.line + 1
+        && token.column <= position.character
+        && position.character <= token.column + token.lexeme.chars().count()
+}
@@ -1,15 +1,313 @@
-use std::env;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process, thread,
+    time::Duration,
+};
 
-use rlox::lox;
+use rlox::{bench, formatter, lox, lsp, manifest::Manifest, project, test_runner};
+
+const USAGE: &str = "Usage: rlox [options] [script [args...]]
+       rlox [options] - [args...]
+       rlox [options] -e <code>
+       rlox init <name>
+       rlox run [options] [args...]
+       rlox test [options] [dir]
+       rlox bench [options] [--runs=N] <script or dir>
+       rlox fmt [options] [--check] <script or dir>...
+       rlox check [options] <script or dir>...
+       rlox lsp [--compat]
+
+Options:
+  --compat              treat keywords added on top of the book as identifiers
+  --origins             track where string literals came from for origin()
+  --ast                 print the parsed program instead of running it
+  --tokens              print the scanned tokens instead of running the program
+  --strict-math         raise errors for division by zero and NaN
+  --strict              warn about shadowing and reject unused locals and dead code
+  --fresh-loop-bindings bind loop variables anew on each pass of a for loop
+  --debug               run under the interactive debugger
+  --trace               log each statement to stderr as it runs
+  --trace-calls         also log function calls and return values
+  --max-call-depth=N    limit nested function calls (default 1024)
+  --max-steps=N         stop after running N statements
+  --max-millis=N        stop after running for N milliseconds
+  --stats               print execution counters and wall time after running
+  --profile             print calls and time per function after running
+  --coverage            print which lines ran after running
+  --lcov=FILE           write line coverage to FILE in lcov format
+  --deny-fs             leave out the file system natives and imports
+  --deny-stdin          leave out readline()
+  --deny-time           leave out clock(), sleep(), now_iso() and elapsed()
+  --deny-process        leave out arg(), arg_count() and exit()
+  -O                    fold constants and drop dead branches before running
+  --cache               reuse the parsed program while the script is unchanged";
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let interpreter = thread::Builder::new()
+        .stack_size(lox::STACK_SIZE)
+        .spawn(run_command)
+        .expect("could not start the interpreter thread");
+
+    if interpreter.join().is_err() {
+        process::exit(101);
+    }
+}
 
-    if args.len() > 2 {
-        println!("Usage: rlox [script]");
-    } else if args.len() == 2 {
-        lox::run_file(args[1].as_str());
+fn run_command() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("init") => init(&args[1..]),
+        Some("run") => run_project(&args[1..]),
+        Some("test") => test_project(&args[1..]),
+        Some("bench") => bench_scripts(&args[1..]),
+        Some("fmt") => format_scripts(&args[1..]),
+        Some("check") => check_scripts(&args[1..]),
+        Some("lsp") => serve_lsp(&args[1..]),
+        _ => run(&args),
+    }
+}
+
+fn init(args: &[String]) {
+    if let [name] = args {
+        match project::init(name) {
+            Ok(root) => println!("Created project in {}.", root.display()),
+            Err(err) => println!("error: could not create project: {}", err),
+        }
     } else {
-        lox::run_prompt();
+        println!("{}", USAGE);
+    }
+}
+
+fn run(args: &[String]) {
+    let (options, mut args) = parse_options(args);
+
+    if args.is_empty() {
+        lox::run_prompt(&options);
+    } else if args[0] == "-e" || args[0] == "--eval" {
+        match args.as_slice() {
+            [_, src] => lox::run_eval(src, &options),
+            _ => println!("{}", USAGE),
+        }
+    } else if args[0].starts_with("--") {
+        println!("{}", USAGE);
+    } else {
+        let path = args.remove(0);
+
+        lox::run_file(&path, &args, &options);
+    }
+}
+
+fn run_project(args: &[String]) {
+    let (mut options, args) = parse_options(args);
+    let manifest = load_manifest();
+
+    options.module_paths = manifest.src.clone();
+
+    lox::run_file(&manifest.entry.to_string_lossy(), &args, &options);
+}
+
+fn test_project(args: &[String]) {
+    let (mut options, args) = parse_options(args);
+
+    let files = match args.as_slice() {
+        [] => {
+            let manifest = load_manifest();
+
+            options.module_paths = manifest.src.clone();
+
+            manifest.test_files()
+        }
+        [dir] => test_runner::discover(Path::new(dir)),
+        _ => {
+            println!("{}", USAGE);
+
+            return;
+        }
+    };
+
+    let summary = test_runner::run_tests(&files, &options);
+
+    if summary.failed > 0 {
+        process::exit(1);
     }
 }
+
+fn bench_scripts(args: &[String]) {
+    let (options, args) = parse_options(args);
+
+    let (runs, target) = match args.as_slice() {
+        [target] => (Ok(bench::DEFAULT_RUNS), target),
+        [runs, target] if runs.starts_with("--runs=") => {
+            (runs["--runs=".len()..].parse::<usize>(), target)
+        }
+        _ => {
+            println!("{}", USAGE);
+
+            return;
+        }
+    };
+
+    let runs = match runs {
+        Ok(runs) if runs > 0 => runs,
+        _ => {
+            println!("{}", USAGE);
+
+            return;
+        }
+    };
+
+    let path = Path::new(target);
+
+    let files = if path.is_dir() {
+        test_runner::discover(path)
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    if !bench::run_benchmarks(&files, runs, &options) {
+        process::exit(1);
+    }
+}
+
+fn format_scripts(args: &[String]) {
+    let (options, args) = parse_options(args);
+
+    let (check, targets) = match args.split_first() {
+        Some((flag, targets)) if flag == "--check" => (true, targets),
+        _ => (false, args.as_slice()),
+    };
+
+    if targets.is_empty() {
+        println!("{}", USAGE);
+
+        return;
+    }
+
+    let files: Vec<_> = targets
+        .iter()
+        .map(Path::new)
+        .flat_map(|path| {
+            if path.is_dir() {
+                test_runner::discover(path)
+            } else {
+                vec![path.to_path_buf()]
+            }
+        })
+        .collect();
+
+    if !formatter::format_files(&files, check, &options) {
+        process::exit(1);
+    }
+}
+
+fn check_scripts(args: &[String]) {
+    let (options, targets) = parse_options(args);
+
+    if targets.is_empty() {
+        println!("{}", USAGE);
+
+        return;
+    }
+
+    let files: Vec<_> = targets
+        .iter()
+        .map(Path::new)
+        .flat_map(|path| {
+            if path.is_dir() {
+                test_runner::discover(path)
+            } else {
+                vec![path.to_path_buf()]
+            }
+        })
+        .collect();
+
+    if !lox::check_files(&files, &options) {
+        process::exit(65);
+    }
+}
+
+fn serve_lsp(args: &[String]) {
+    let (options, args) = parse_options(args);
+
+    if !args.is_empty() {
+        println!("{}", USAGE);
+
+        return;
+    }
+
+    if let Err(err) = lsp::run(options.compat) {
+        eprintln!("error: {}", err);
+
+        process::exit(1);
+    }
+}
+
+fn load_manifest() -> Manifest {
+    Manifest::discover().unwrap_or_else(|err| {
+        println!("error: {}", err);
+
+        process::exit(1);
+    })
+}
+
+/// Splits leading flags (including defaults from `.rloxrc`) from the
+/// remaining arguments.
+fn parse_options(args: &[String]) -> (lox::Options, Vec<String>) {
+    let mut options = lox::Options::default();
+
+    // Everything runs on the thread `main` starts with this much stack.
+    options.limits.stack_size = lox::STACK_SIZE;
+
+    let mut args = project::rc_flags()
+        .into_iter()
+        .chain(args.iter().cloned())
+        .peekable();
+
+    while let Some(arg) = args.peek() {
+        match arg.as_str() {
+            "--compat" => options.compat = true,
+            "--origins" => options.track_origins = true,
+            "--ast" => options.print_ast = true,
+            "--tokens" => options.print_tokens = true,
+            "--strict-math" => options.strict_math = true,
+            "--strict" => options.strict = true,
+            "--fresh-loop-bindings" => options.fresh_loop_bindings = true,
+            "--debug" => options.debug = true,
+            "--trace" => options.trace = true,
+            "--trace-calls" => options.trace_calls = true,
+            "--stats" => options.stats = true,
+            "--profile" => options.profile = true,
+            "--coverage" => options.coverage = true,
+            "-O" => options.optimize = true,
+            "--cache" => options.cache_ast = true,
+            "--deny-fs" => options.capabilities.allow_fs = false,
+            "--deny-stdin" => options.capabilities.allow_stdin = false,
+            "--deny-time" => options.capabilities.allow_time = false,
+            "--deny-process" => options.capabilities.allow_process = false,
+            arg if arg.starts_with("--max-steps=") => match arg["--max-steps=".len()..].parse() {
+                Ok(steps) => options.limits.opt_max_steps = Some(steps),
+                Err(_) => break,
+            },
+            arg if arg.starts_with("--max-millis=") => match arg["--max-millis=".len()..].parse() {
+                Ok(millis) => options.limits.opt_max_time = Some(Duration::from_millis(millis)),
+                Err(_) => break,
+            },
+            arg if arg.starts_with("--max-call-depth=") => {
+                match arg["--max-call-depth=".len()..].parse() {
+                    Ok(depth) => options.limits.max_call_depth = depth,
+                    Err(_) => break,
+                }
+            }
+            arg if arg.starts_with("--lcov=") => {
+                options.opt_lcov_path = Some(PathBuf::from(&arg["--lcov=".len()..]))
+            }
+            _ => break,
+        }
+
+        args.next();
+    }
+
+    (options, args.collect())
+}
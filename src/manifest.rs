@@ -0,0 +1,192 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Name of the project manifest looked up from the current directory upwards.
+pub const MANIFEST_FILE: &str = "lox.toml";
+
+/// The subset of `lox.toml` rlox understands:
+///
+/// ```toml
+/// [project]
+/// entry = "main.lox"
+/// src = ["lib"]
+/// tests = "tests/*_test.lox"
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub root: PathBuf,
+    pub entry: PathBuf,
+    pub src: Vec<PathBuf>,
+    pub tests: String,
+}
+
+impl Manifest {
+    /// Finds and loads the manifest of the project containing the current
+    /// directory.
+    pub fn discover() -> Result<Self, String> {
+        let cwd = env::current_dir().map_err(|err| err.to_string())?;
+
+        let root = cwd
+            .ancestors()
+            .find(|dir| dir.join(MANIFEST_FILE).is_file())
+            .ok_or_else(|| format!("could not find {} in {}", MANIFEST_FILE, cwd.display()))?;
+
+        Self::load(root)
+    }
+
+    pub fn load(root: &Path) -> Result<Self, String> {
+        let path = root.join(MANIFEST_FILE);
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+
+        Self::parse(root, &contents)
+    }
+
+    pub fn parse(root: &Path, contents: &str) -> Result<Self, String> {
+        let mut manifest = Self {
+            root: root.to_path_buf(),
+            entry: root.join("main.lox"),
+            src: Vec::new(),
+            tests: "tests/*.lox".to_string(),
+        };
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+
+            if line.is_empty() || (line.starts_with('[') && !line.contains('=')) {
+                continue;
+            }
+
+            let error = |message: &str| format!("{}:{}: {}", MANIFEST_FILE, index + 1, message);
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected 'key = value'"))?;
+
+            match key.trim() {
+                "entry" => {
+                    manifest.entry = root
+                        .join(parse_string(value).ok_or_else(|| error("entry must be a string"))?)
+                }
+                "src" => {
+                    manifest.src = parse_string_list(value)
+                        .ok_or_else(|| error("src must be a list of strings"))?
+                        .iter()
+                        .map(|dir| root.join(dir))
+                        .collect()
+                }
+                "tests" => {
+                    manifest.tests =
+                        parse_string(value).ok_or_else(|| error("tests must be a string"))?
+                }
+                key => return Err(error(&format!("unknown key '{}'", key))),
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Lists the test scripts matching the manifest's `tests` glob, sorted by
+    /// path.
+    pub fn test_files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+
+        collect_files(&self.root, &mut files);
+
+        let mut tests: Vec<PathBuf> = files
+            .into_iter()
+            .filter(|file| {
+                file.strip_prefix(&self.root)
+                    .map(|relative| {
+                        let relative = relative.to_string_lossy().replace('\\', "/");
+
+                        glob_match(&self.tests, &relative)
+                    })
+                    .unwrap_or(false)
+            })
+            .collect();
+
+        tests.sort();
+
+        tests
+    }
+}
+
+fn parse_string(value: &str) -> Option<String> {
+    let value = value.trim();
+
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        Some(value[1..value.len() - 1].to_string())
+    } else {
+        None
+    }
+}
+
+fn parse_string_list(value: &str) -> Option<Vec<String>> {
+    let value = value.trim();
+
+    if !value.starts_with('[') || !value.ends_with(']') {
+        return None;
+    }
+
+    value[1..value.len() - 1]
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(parse_string)
+        .collect()
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if path.is_dir() {
+                collect_files(&path, files);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+}
+
+/// Matches `/`-separated paths against a pattern where `*` matches within a
+/// single segment and `**` matches any number of segments.
+fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+
+    match_segments(&pattern, &path)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            match_segments(&pattern[1..], path)
+                || (!path.is_empty() && match_segments(pattern, &path[1..]))
+        }
+        (Some(segment), Some(name)) => {
+            match_segment(segment.as_bytes(), name.as_bytes())
+                && match_segments(&pattern[1..], &path[1..])
+        }
+        _ => false,
+    }
+}
+
+fn match_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            match_segment(&pattern[1..], name)
+                || (!name.is_empty() && match_segment(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => match_segment(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) => p == n && match_segment(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
@@ -0,0 +1,495 @@
+use std::convert::TryFrom;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    fs::{self, OpenOptions},
+    io::{stdin, Write},
+    path::Path,
+    sync::OnceLock,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    class::LoxInstance,
+    environment::Environment,
+    function::{Arity, Function},
+    interpreter::{Interpreter, InterpreterError},
+    lox::Capabilities,
+    lox_type::LoxType,
+    shared::{Locked, Shared},
+};
+
+type NativeResult = Result<LoxType, InterpreterError>;
+
+pub fn define_globals(env: &mut Environment, capabilities: &Capabilities) {
+    // The clock, the file system and stdin aren't available to wasm32
+    // builds running in a browser.
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if capabilities.allow_time {
+            define_native(env, "clock", 0, clock);
+            define_native(env, "sleep", 1, sleep);
+            define_native(env, "now_iso", 0, now_iso);
+            define_native(env, "elapsed", 0, elapsed);
+        }
+
+        if capabilities.allow_fs {
+            define_native(env, "read_file", 1, read_file);
+            define_native(env, "write_file", 2, write_file);
+            define_native(env, "append_file", 2, append_file);
+            define_native(env, "file_exists", 1, file_exists);
+        }
+
+        if capabilities.allow_stdin {
+            define_native(env, "readline", 0, readline);
+        }
+    }
+
+    if capabilities.allow_process {
+        define_native(env, "arg_count", 0, arg_count);
+        define_native(env, "arg", 1, arg);
+        define_native(env, "exit", 1, exit);
+    }
+
+    define_native(env, "origin", 1, origin);
+    define_native(env, "str", 1, str);
+    define_native(env, "assert", 2, assert);
+    define_native(env, "env", 0, env_native);
+    define_native(env, "type", 1, type_of);
+    define_native(env, "dir", 1, dir);
+    define_native(env, "getattr", 2, getattr);
+    define_native(env, "setattr", 3, setattr);
+    define_native(env, "hasattr", 2, hasattr);
+    define_native(env, "define_method", 3, define_method);
+    define_native(env, "random", 0, random);
+    define_native(env, "random_int", 2, random_int);
+    define_native(env, "seed", 1, seed);
+    define_native(env, "min", Arity::AtLeast(1), min);
+    define_native(env, "max", Arity::AtLeast(1), max);
+}
+
+fn define_native<A: Into<Arity>>(
+    env: &mut Environment,
+    name: &str,
+    arity: A,
+    body: fn(&mut Interpreter, &[LoxType]) -> NativeResult,
+) {
+    env.define(
+        Shared::from(name),
+        LoxType::Callable(Function::Native {
+            arity: arity.into(),
+            body: Shared::new(body),
+        }),
+    );
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn clock(_: &mut Interpreter, _: &[LoxType]) -> NativeResult {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| LoxType::Number(duration.as_millis() as f64))
+        .map_err(|_| InterpreterError::runtime_error(None, "could not retrieve time."))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn sleep(_: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    match args[0].as_number() {
+        Some(ms) if ms >= 0.0 && ms.is_finite() => {
+            thread::sleep(Duration::from_secs_f64(ms / 1000.0));
+
+            Ok(LoxType::Nil)
+        }
+        _ => Err(InterpreterError::runtime_error(
+            None,
+            "sleep() expects a non-negative number of milliseconds.",
+        )),
+    }
+}
+
+/// The current UTC time, e.g. `2024-05-01T13:45:30.250Z`.
+#[cfg(not(target_arch = "wasm32"))]
+fn now_iso(_: &mut Interpreter, _: &[LoxType]) -> NativeResult {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| InterpreterError::runtime_error(None, "could not retrieve time."))?;
+
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let time_of_day = secs % 86_400;
+
+    Ok(LoxType::String(
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year,
+            month,
+            day,
+            time_of_day / 3600,
+            time_of_day % 3600 / 60,
+            time_of_day % 60,
+            now.subsec_millis()
+        )
+        .into(),
+    ))
+}
+
+/// The year, month and day of a count of days since 1970-01-01, using
+/// Howard Hinnant's `civil_from_days`.
+#[cfg(not(target_arch = "wasm32"))]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// Milliseconds on a monotonic clock, for timing code by subtracting two
+/// readings. Unlike `clock`, it never jumps when the system time changes.
+#[cfg(not(target_arch = "wasm32"))]
+fn elapsed(_: &mut Interpreter, _: &[LoxType]) -> NativeResult {
+    static START: OnceLock<Instant> = OnceLock::new();
+
+    let start = START.get_or_init(Instant::now);
+
+    Ok(LoxType::Number(start.elapsed().as_secs_f64() * 1000.0))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_file(_: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    let path = string_arg("read_file", "path", &args[0])?;
+
+    fs::read_to_string(path)
+        .map(|contents| LoxType::String(contents.into()))
+        .map_err(|err| io_error("read", path, err))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_file(_: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    let path = string_arg("write_file", "path", &args[0])?;
+    let contents = string_arg("write_file", "contents", &args[1])?;
+
+    fs::write(path, contents)
+        .map(|_| LoxType::Nil)
+        .map_err(|err| io_error("write", path, err))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn append_file(_: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    let path = string_arg("append_file", "path", &args[0])?;
+    let contents = string_arg("append_file", "contents", &args[1])?;
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map(|_| LoxType::Nil)
+        .map_err(|err| io_error("append to", path, err))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn file_exists(_: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    let path = string_arg("file_exists", "path", &args[0])?;
+
+    Ok(LoxType::Boolean(Path::new(path).exists()))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn readline(_: &mut Interpreter, _: &[LoxType]) -> NativeResult {
+    let mut line = String::new();
+
+    match stdin().read_line(&mut line) {
+        Ok(0) => Ok(LoxType::Nil),
+        Ok(_) => {
+            if line.ends_with('\n') {
+                line.pop();
+            }
+
+            if line.ends_with('\r') {
+                line.pop();
+            }
+
+            Ok(LoxType::String(line.into()))
+        }
+        Err(err) => Err(InterpreterError::runtime_error(
+            None,
+            &format!("Could not read from stdin: {}.", err),
+        )),
+    }
+}
+
+fn arg_count(interpreter: &mut Interpreter, _: &[LoxType]) -> NativeResult {
+    Ok(LoxType::Integer(interpreter.args().len() as i64))
+}
+
+fn arg(interpreter: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    if let Some(n) = args[0].as_number() {
+        let index = n as usize;
+
+        if n >= 0.0 && n.fract() == 0.0 && index < interpreter.args().len() {
+            return Ok(LoxType::String(interpreter.args()[index].as_str().into()));
+        }
+    }
+
+    Err(InterpreterError::runtime_error(
+        None,
+        &format!(
+            "arg() expects an index between 0 and {}.",
+            interpreter.args().len() as isize - 1
+        ),
+    ))
+}
+
+fn origin(interpreter: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    Ok(interpreter
+        .origin(&args[0])
+        .map_or(LoxType::Nil, |origin| LoxType::String(origin.into())))
+}
+
+/// The variables visible where `env()` is called, scope by scope.
+fn env_native(interpreter: &mut Interpreter, _: &[LoxType]) -> NativeResult {
+    Ok(LoxType::String(
+        interpreter.environment().borrow().describe().into(),
+    ))
+}
+
+fn type_of(_: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    Ok(LoxType::String(args[0].type_name().into()))
+}
+
+/// The names of an instance's fields and methods, or a class's methods,
+/// separated by spaces.
+fn dir(_: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    let names = match &args[0] {
+        LoxType::Instance(instance) => instance.borrow().property_names(),
+        LoxType::Class(class) => class.borrow().method_names(),
+        _ => {
+            return Err(InterpreterError::runtime_error(
+                None,
+                "dir() expects an instance or a class.",
+            ))
+        }
+    };
+
+    Ok(LoxType::String(names.join(" ").into()))
+}
+
+/// The property of an instance named by a string, like `instance.name`.
+fn getattr(interpreter: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    let instance = instance_arg("getattr", &args[0])?;
+    let name = string_arg("getattr", "name", &args[1])?;
+
+    interpreter.check_private_access_to(name, instance)?;
+
+    let opt_value = instance.borrow().find_property(name, &args[0]);
+
+    opt_value.ok_or_else(|| {
+        InterpreterError::runtime_error(None, &format!("Undefined property '{}'.", name))
+    })
+}
+
+/// Sets a field named by a string, like `instance.name = value`.
+fn setattr(interpreter: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    let instance = instance_arg("setattr", &args[0])?;
+    let name = string_arg("setattr", "name", &args[1])?;
+
+    interpreter.check_private_access_to(name, instance)?;
+
+    instance
+        .borrow_mut()
+        .set_field(Shared::from(name), args[2].clone());
+
+    Ok(LoxType::Nil)
+}
+
+/// Whether `instance.name` would find a field or a method. Private
+/// properties the caller can't access don't count.
+fn hasattr(interpreter: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    let instance = instance_arg("hasattr", &args[0])?;
+    let name = string_arg("hasattr", "name", &args[1])?;
+
+    let has_property = interpreter.check_private_access_to(name, instance).is_ok()
+        && instance.borrow().has_property(name);
+
+    Ok(LoxType::Boolean(has_property))
+}
+
+/// Adds `function` to a class as the method `name`, or replaces that method.
+/// The function takes the instance as its first parameter.
+fn define_method(_: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    let class = match &args[0] {
+        LoxType::Class(class) => class,
+        _ => {
+            return Err(InterpreterError::runtime_error(
+                None,
+                "define_method() expects a class.",
+            ))
+        }
+    };
+
+    let name = string_arg("define_method", "name", &args[1])?;
+
+    let function = match &args[2] {
+        LoxType::Callable(function) if function.arity() != Arity::Exact(0) => function,
+        _ => return Err(InterpreterError::runtime_error(
+            None,
+            "define_method() expects a function that takes the instance as its first parameter.",
+        )),
+    };
+
+    class.borrow_mut().define_method(
+        Shared::from(name),
+        Function::Attached {
+            function: Box::new(function.clone()),
+            opt_receiver: None,
+        },
+    );
+
+    Ok(LoxType::Nil)
+}
+
+fn str(interpreter: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    Ok(LoxType::String(interpreter.stringify(&args[0])?.into()))
+}
+
+fn assert(_: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    if bool::from(args[0].clone()) {
+        Ok(LoxType::Nil)
+    } else {
+        Err(InterpreterError::runtime_error(
+            None,
+            &format!("Assertion failed: {}", args[1]),
+        ))
+    }
+}
+
+fn random(interpreter: &mut Interpreter, _: &[LoxType]) -> NativeResult {
+    Ok(LoxType::Number(interpreter.random().next_float()))
+}
+
+/// A random integer from `low` to `high`, both included.
+fn random_int(interpreter: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    match (&args[0], &args[1]) {
+        (LoxType::Integer(low), LoxType::Integer(high)) if low <= high => {
+            Ok(LoxType::Integer(interpreter.random().next_int(*low, *high)))
+        }
+        _ => Err(InterpreterError::runtime_error(
+            None,
+            "random_int() expects two integers, the first no greater than the second.",
+        )),
+    }
+}
+
+fn seed(interpreter: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    if let LoxType::Integer(n) = args[0] {
+        interpreter.random().seed(n as u64);
+
+        Ok(LoxType::Nil)
+    } else {
+        Err(InterpreterError::runtime_error(
+            None,
+            "seed() expects an integer.",
+        ))
+    }
+}
+
+/// Stops the script. The code is handed back to whoever ran it rather than
+/// ending the process here. Only codes a process can exit with are allowed.
+fn exit(_: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    match args[0] {
+        LoxType::Integer(code) => match u8::try_from(code) {
+            Ok(code) => Err(InterpreterError::Exit(i32::from(code))),
+            Err(_) => Err(InterpreterError::runtime_error(
+                None,
+                "exit() code must be between 0 and 255.",
+            )),
+        },
+        _ => Err(InterpreterError::runtime_error(
+            None,
+            "exit() expects an integer.",
+        )),
+    }
+}
+
+fn min(_: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    let numbers = number_args("min", args)?;
+
+    Ok(extreme(args, &numbers, |n, m| n < m))
+}
+
+fn max(_: &mut Interpreter, args: &[LoxType]) -> NativeResult {
+    let numbers = number_args("max", args)?;
+
+    Ok(extreme(args, &numbers, |n, m| n > m))
+}
+
+/// The argument whose number wins every `is_better` comparison, keeping
+/// whether it's an integer.
+fn extreme(args: &[LoxType], numbers: &[f64], is_better: fn(f64, f64) -> bool) -> LoxType {
+    let mut best = 0;
+
+    for (index, n) in numbers.iter().enumerate() {
+        if is_better(*n, numbers[best]) {
+            best = index;
+        }
+    }
+
+    args[best].clone()
+}
+
+fn number_args(native: &str, args: &[LoxType]) -> Result<Vec<f64>, InterpreterError> {
+    args.iter()
+        .map(|arg| match arg.as_number() {
+            Some(n) => Ok(n),
+            None => Err(InterpreterError::runtime_error(
+                None,
+                &format!("{}() expects numbers.", native),
+            )),
+        })
+        .collect()
+}
+
+fn instance_arg<'a>(
+    native: &str,
+    value: &'a LoxType,
+) -> Result<&'a Shared<Locked<LoxInstance>>, InterpreterError> {
+    if let LoxType::Instance(instance) = value {
+        Ok(instance)
+    } else {
+        Err(InterpreterError::runtime_error(
+            None,
+            &format!("{}() expects an instance.", native),
+        ))
+    }
+}
+
+fn string_arg<'a>(
+    native: &str,
+    name: &str,
+    value: &'a LoxType,
+) -> Result<&'a str, InterpreterError> {
+    if let LoxType::String(s) = value {
+        Ok(s)
+    } else {
+        Err(InterpreterError::runtime_error(
+            None,
+            &format!("{}() expects {} to be a string.", native, name),
+        ))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn io_error(action: &str, path: &str, err: std::io::Error) -> InterpreterError {
+    InterpreterError::runtime_error(
+        None,
+        &format!("Could not {} file '{}': {}.", action, path, err),
+    )
+}
@@ -0,0 +1,481 @@
+use crate::{
+    ast::{Expr, ExprId, ExprVisitor, Stmt, StmtVisitor},
+    interpreter::{bitwise, integer_binary},
+    lox_type::LoxType,
+    token::Token,
+    token_type::TokenType,
+};
+
+/// Folds constant expressions and drops branches and loops whose condition
+/// is a constant that never runs them. Runs between parsing and resolution.
+/// Anything that could fail at runtime is left for the interpreter, so the
+/// program behaves the same with or without the pass.
+pub fn optimize(stmts: &[Stmt]) -> Vec<Stmt> {
+    Optimizer.block(stmts)
+}
+
+struct Optimizer;
+
+impl Optimizer {
+    fn block(&mut self, stmts: &[Stmt]) -> Vec<Stmt> {
+        stmts.iter().filter_map(|stmt| stmt.accept(self)).collect()
+    }
+
+    /// A statement that must stay a statement even when it does nothing,
+    /// e.g. the body of a loop.
+    fn statement(&mut self, stmt: &Stmt) -> Stmt {
+        stmt.accept(self).unwrap_or_else(|| Stmt::Block(Vec::new()))
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Expr {
+        expr.accept(self)
+    }
+}
+
+impl StmtVisitor<Option<Stmt>> for Optimizer {
+    fn visit_block(&mut self, stmts: &[Stmt]) -> Option<Stmt> {
+        Some(Stmt::Block(self.block(stmts)))
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        fields: &[(Token, Expr)],
+        methods: &[Stmt],
+        opt_superclass: Option<&Expr>,
+    ) -> Option<Stmt> {
+        Some(Stmt::Class {
+            name: name.clone(),
+            fields: fields
+                .iter()
+                .map(|(field_name, value)| (field_name.clone(), self.expression(value)))
+                .collect(),
+            methods: self.block(methods),
+            opt_superclass: opt_superclass.cloned(),
+        })
+    }
+
+    fn visit_destructure(
+        &mut self,
+        opening: &Token,
+        names: &[Token],
+        initializer: &Expr,
+    ) -> Option<Stmt> {
+        Some(Stmt::Destructure {
+            opening: opening.clone(),
+            names: names.to_vec(),
+            initializer: self.expression(initializer),
+        })
+    }
+
+    fn visit_destructure_assign(
+        &mut self,
+        opening: &Token,
+        targets: &[(ExprId, Token)],
+        value: &Expr,
+    ) -> Option<Stmt> {
+        Some(Stmt::DestructureAssign {
+            opening: opening.clone(),
+            targets: targets.to_vec(),
+            value: self.expression(value),
+        })
+    }
+
+    fn visit_do_while(&mut self, keyword: &Token, body: &Stmt, condition: &Expr) -> Option<Stmt> {
+        let condition = self.expression(condition);
+
+        // A loop that never repeats is just its body.
+        if let Expr::Literal(ref value) = condition {
+            if !bool::from(value.clone()) {
+                return body.accept(self);
+            }
+        }
+
+        Some(Stmt::DoWhile {
+            keyword: keyword.clone(),
+            body: Box::new(self.statement(body)),
+            condition,
+        })
+    }
+
+    fn visit_expression(&mut self, expr: &Expr) -> Option<Stmt> {
+        Some(Stmt::Expression(self.expression(expr)))
+    }
+
+    fn visit_for(
+        &mut self,
+        keyword: &Token,
+        initializers: &[Stmt],
+        condition: &Expr,
+        opt_increment: Option<&Expr>,
+        body: &Stmt,
+    ) -> Option<Stmt> {
+        let initializers = self.block(initializers);
+        let condition = self.expression(condition);
+
+        // The initializers still run when the loop body never does.
+        if let Expr::Literal(ref value) = condition {
+            if !bool::from(value.clone()) {
+                return Some(Stmt::Block(initializers));
+            }
+        }
+
+        Some(Stmt::For {
+            keyword: keyword.clone(),
+            initializers,
+            condition,
+            opt_increment: opt_increment.map(|increment| Box::new(self.expression(increment))),
+            body: Box::new(self.statement(body)),
+        })
+    }
+
+    fn visit_for_in(
+        &mut self,
+        keyword: &Token,
+        name: &Token,
+        value: &Expr,
+        opt_end: Option<&Expr>,
+        body: &Stmt,
+    ) -> Option<Stmt> {
+        Some(Stmt::ForIn {
+            keyword: keyword.clone(),
+            name: name.clone(),
+            value: self.expression(value),
+            opt_end: opt_end.map(|end| Box::new(self.expression(end))),
+            body: Box::new(self.statement(body)),
+        })
+    }
+
+    fn visit_function(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        opt_rest: Option<&Token>,
+        body: &[Stmt],
+    ) -> Option<Stmt> {
+        Some(Stmt::Function {
+            name: name.clone(),
+            params: params.to_vec(),
+            opt_rest: opt_rest.cloned(),
+            body: self.block(body),
+        })
+    }
+
+    fn visit_if(
+        &mut self,
+        keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        opt_else_branch: Option<&Stmt>,
+    ) -> Option<Stmt> {
+        let condition = self.expression(condition);
+
+        match condition {
+            Expr::Literal(value) => {
+                if bool::from(value) {
+                    then_branch.accept(self)
+                } else {
+                    opt_else_branch.and_then(|else_branch| else_branch.accept(self))
+                }
+            }
+            condition => Some(Stmt::If {
+                keyword: keyword.clone(),
+                condition,
+                then_branch: Box::new(self.statement(then_branch)),
+                opt_else_branch: opt_else_branch
+                    .map(|else_branch| Box::new(self.statement(else_branch))),
+            }),
+        }
+    }
+
+    fn visit_import(&mut self, keyword: &Token, path: &str) -> Option<Stmt> {
+        Some(Stmt::Import {
+            keyword: keyword.clone(),
+            path: path.to_string(),
+        })
+    }
+
+    fn visit_print(&mut self, keyword: &Token, value: &Expr) -> Option<Stmt> {
+        Some(Stmt::Print {
+            keyword: keyword.clone(),
+            value: self.expression(value),
+        })
+    }
+
+    fn visit_return(&mut self, keyword: &Token, value: &Expr) -> Option<Stmt> {
+        Some(Stmt::Return {
+            keyword: keyword.clone(),
+            value: self.expression(value),
+        })
+    }
+
+    fn visit_switch(
+        &mut self,
+        keyword: &Token,
+        value: &Expr,
+        cases: &[(Expr, Vec<Stmt>)],
+        opt_default: Option<&[Stmt]>,
+    ) -> Option<Stmt> {
+        Some(Stmt::Switch {
+            keyword: keyword.clone(),
+            value: self.expression(value),
+            cases: cases
+                .iter()
+                .map(|(case_value, body)| (self.expression(case_value), self.block(body)))
+                .collect(),
+            opt_default: opt_default.map(|body| self.block(body)),
+        })
+    }
+
+    fn visit_throw(&mut self, keyword: &Token, value: &Expr) -> Option<Stmt> {
+        Some(Stmt::Throw {
+            keyword: keyword.clone(),
+            value: self.expression(value),
+        })
+    }
+
+    fn visit_try(
+        &mut self,
+        keyword: &Token,
+        body: &[Stmt],
+        opt_catch: Option<(&Token, &[Stmt])>,
+        opt_finally: Option<&[Stmt]>,
+    ) -> Option<Stmt> {
+        Some(Stmt::Try {
+            keyword: keyword.clone(),
+            body: self.block(body),
+            opt_catch: opt_catch.map(|(name, catch_body)| (name.clone(), self.block(catch_body))),
+            opt_finally: opt_finally.map(|finally_body| self.block(finally_body)),
+        })
+    }
+
+    fn visit_var(&mut self, name: &Token, initializer: &Expr, is_const: bool) -> Option<Stmt> {
+        Some(Stmt::Var {
+            name: name.clone(),
+            initializer: self.expression(initializer),
+            is_const,
+        })
+    }
+
+    fn visit_while(&mut self, keyword: &Token, condition: &Expr, body: &Stmt) -> Option<Stmt> {
+        let condition = self.expression(condition);
+
+        if let Expr::Literal(ref value) = condition {
+            if !bool::from(value.clone()) {
+                return None;
+            }
+        }
+
+        Some(Stmt::While {
+            keyword: keyword.clone(),
+            condition,
+            body: Box::new(self.statement(body)),
+        })
+    }
+}
+
+impl ExprVisitor<Expr> for Optimizer {
+    fn visit_assign(&mut self, id: ExprId, name: &Token, value: &Expr) -> Expr {
+        Expr::Assign {
+            id,
+            name: name.clone(),
+            value: Box::new(self.expression(value)),
+        }
+    }
+
+    fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Expr {
+        let left = self.expression(left);
+        let right = self.expression(right);
+
+        if let (Expr::Literal(n), Expr::Literal(m)) = (&left, &right) {
+            if let Some(value) = fold_binary(&operator.token_type, n, m) {
+                return Expr::Literal(value);
+            }
+        }
+
+        Expr::Binary {
+            left: Box::new(left),
+            operator: operator.clone(),
+            right: Box::new(right),
+        }
+    }
+
+    fn visit_call(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> Expr {
+        Expr::Call {
+            callee: Box::new(self.expression(callee)),
+            paren: paren.clone(),
+            arguments: arguments.iter().map(|arg| self.expression(arg)).collect(),
+        }
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &Token, is_optional: bool) -> Expr {
+        Expr::Get {
+            object: Box::new(self.expression(object)),
+            name: name.clone(),
+            is_optional,
+        }
+    }
+
+    fn visit_grouping(&mut self, expr: &Expr) -> Expr {
+        match self.expression(expr) {
+            Expr::Literal(value) => Expr::Literal(value),
+            expr => Expr::Grouping(Box::new(expr)),
+        }
+    }
+
+    fn visit_literal(&mut self, value: &LoxType) -> Expr {
+        Expr::Literal(value.clone())
+    }
+
+    fn visit_logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Expr {
+        let left = self.expression(left);
+        let right = self.expression(right);
+
+        if let Expr::Literal(ref value) = left {
+            let is_left_truthy = bool::from(value.clone());
+
+            // Short-circuits the same way the interpreter does.
+            let short_circuits = match operator.token_type {
+                TokenType::Or => is_left_truthy,
+                TokenType::QuestionQuestion => !matches!(value, LoxType::Nil),
+                _ => !is_left_truthy,
+            };
+
+            return if short_circuits { left } else { right };
+        }
+
+        Expr::Logical {
+            left: Box::new(left),
+            operator: operator.clone(),
+            right: Box::new(right),
+        }
+    }
+
+    fn visit_optional_chain(&mut self, chain: &Expr) -> Expr {
+        Expr::OptionalChain(Box::new(self.expression(chain)))
+    }
+
+    fn visit_set(&mut self, object: &Expr, name: &Token, value: &Expr) -> Expr {
+        Expr::Set {
+            object: Box::new(self.expression(object)),
+            name: name.clone(),
+            value: Box::new(self.expression(value)),
+        }
+    }
+
+    fn visit_spread(&mut self, ellipsis: &Token, value: &Expr) -> Expr {
+        Expr::Spread {
+            ellipsis: ellipsis.clone(),
+            value: Box::new(self.expression(value)),
+        }
+    }
+
+    fn visit_super(&mut self, id: ExprId, keyword: &Token, method: &Token) -> Expr {
+        Expr::Super {
+            id,
+            keyword: keyword.clone(),
+            method: method.clone(),
+        }
+    }
+
+    fn visit_this(&mut self, id: ExprId, keyword: &Token) -> Expr {
+        Expr::This {
+            id,
+            keyword: keyword.clone(),
+        }
+    }
+
+    fn visit_tuple(&mut self, elements: &[Expr]) -> Expr {
+        Expr::Tuple(
+            elements
+                .iter()
+                .map(|element| self.expression(element))
+                .collect(),
+        )
+    }
+
+    fn visit_unary(&mut self, operator: &Token, right: &Expr) -> Expr {
+        let right = self.expression(right);
+
+        match (&operator.token_type, &right) {
+            (TokenType::Bang, Expr::Literal(value)) => {
+                Expr::Literal(LoxType::Boolean(!bool::from(value.clone())))
+            }
+            (TokenType::Minus, Expr::Literal(LoxType::Integer(n))) if *n != i64::MIN => {
+                Expr::Literal(LoxType::Integer(-n))
+            }
+            (TokenType::Minus, Expr::Literal(LoxType::Number(n))) => {
+                Expr::Literal(LoxType::Number(-n))
+            }
+            (TokenType::Tilde, Expr::Literal(LoxType::Integer(n))) => {
+                Expr::Literal(LoxType::Integer(!n))
+            }
+            (TokenType::Tilde, Expr::Literal(LoxType::Number(n))) => {
+                Expr::Literal(LoxType::Integer(!(*n as i64)))
+            }
+            _ => Expr::Unary {
+                operator: operator.clone(),
+                right: Box::new(right),
+            },
+        }
+    }
+
+    fn visit_variable(&mut self, id: ExprId, name: &Token) -> Expr {
+        Expr::Variable {
+            id,
+            name: name.clone(),
+        }
+    }
+}
+
+/// The value of a binary operator applied to two literals, or `None` if the
+/// operation would raise an error or produce a non-finite number, which
+/// strict math reports at runtime.
+fn fold_binary(operator: &TokenType, left: &LoxType, right: &LoxType) -> Option<LoxType> {
+    use LoxType::{Boolean, Integer, Number, String};
+
+    if let (Integer(n), Integer(m)) = (left, right) {
+        if let Some(value) = integer_binary(operator, *n, *m) {
+            return Some(value);
+        }
+    }
+
+    let value = match (operator, left, right) {
+        (TokenType::Plus, String(n), String(m)) => String(format!("{}{}", n, m).into()),
+        (TokenType::Plus, String(n), m) if m.as_number().is_some() => {
+            String(format!("{}{}", n, m).into())
+        }
+        (TokenType::Plus, n, String(m)) if n.as_number().is_some() => {
+            String(format!("{}{}", n, m).into())
+        }
+        (TokenType::Interpolation, n, m) => String(format!("{}{}", n, m).into()),
+        (TokenType::EqualEqual, n, m) => Boolean(n == m),
+        (TokenType::BangEqual, n, m) => Boolean(n != m),
+        (TokenType::Comma, _, m) => m.clone(),
+        _ => {
+            let (n, m) = (left.as_number()?, right.as_number()?);
+
+            match operator {
+                TokenType::Plus => Number(n + m),
+                TokenType::Minus => Number(n - m),
+                TokenType::Star => Number(n * m),
+                TokenType::Slash if m != 0.0 => Number(n / m),
+                TokenType::Greater => Boolean(n > m),
+                TokenType::GreaterEqual => Boolean(n >= m),
+                TokenType::Less => Boolean(n < m),
+                TokenType::LessEqual => Boolean(n <= m),
+                TokenType::Ampersand
+                | TokenType::Caret
+                | TokenType::Pipe
+                | TokenType::LessLess
+                | TokenType::GreaterGreater => Integer(bitwise(operator, n, m)),
+                _ => return None,
+            }
+        }
+    };
+
+    match value {
+        Number(n) if !n.is_finite() => None,
+        value => Some(value),
+    }
+}
@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+
+use crate::{lox_type::LoxType, shared::Shared, token::Token};
+
+/// Remembers where string literals appeared in the source so that
+/// `origin(value)` can point back at them. Strings are matched by identity:
+/// a literal evaluates to the string the scanner made for it, while computed
+/// strings are new, so they report nil even when equal to some literal.
+/// Numbers carry no identity and always report nil.
+#[derive(Debug, Default)]
+pub struct Origins {
+    /// Keyed by the address of the literal's text, which is kept alive here
+    /// so the address can't be reused by a computed string.
+    locations: HashMap<usize, (Shared<str>, String)>,
+}
+
+fn address(s: &Shared<str>) -> usize {
+    Shared::as_ptr(s) as *const u8 as usize
+}
+
+impl Origins {
+    pub fn record(&mut self, file: &str, tokens: &[Token]) {
+        for token in tokens {
+            if let Some(LoxType::String(s)) = &token.literal {
+                self.locations
+                    .entry(address(s))
+                    .or_insert_with(|| (s.clone(), format!("{}:{}", file, token.line)));
+            }
+        }
+    }
+
+    pub fn lookup(&self, value: &LoxType) -> Option<&str> {
+        match value {
+            LoxType::String(s) => self
+                .locations
+                .get(&address(s))
+                .map(|(_, location)| location.as_str()),
+            _ => None,
+        }
+    }
+}
@@ -1,7 +1,13 @@
+use std::collections::VecDeque;
+
 use crate::{
-    ast::{Expr, Stmt},
-    lox,
+    ast::{Expr, ExprId, Stmt},
+    error::Diagnostics,
+    lox::Limits,
     lox_type::LoxType,
+    scanner::{ScanError, EXTENSION_KEYWORDS},
+    shared::Shared,
+    stack::StackGuard,
     token::Token,
     token_type::TokenType,
 };
@@ -9,70 +15,195 @@ use crate::{
 #[derive(Debug)]
 pub struct ParseError;
 
-pub struct Parser {
-    tokens: Vec<Token>,
-    current: usize,
+/// A top-level declaration parsed on its own by `Parser::top_level`.
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub struct TopLevel {
+    pub statements: Vec<Stmt>,
+    /// The last token the declaration consumed.
+    pub last: Token,
+    /// The line of the furthest token scanned while parsing it. Edits past
+    /// this line can't change how it parsed.
+    pub seen_line: usize,
+    /// Whether it parsed without errors or warnings.
+    pub is_clean: bool,
+}
+
+/// How many tokens the parser keeps looking ahead at, e.g. to spot
+/// `for (var x in`. `peek_nth` looks further when it has to.
+const LOOKAHEAD: usize = 3;
+
+/// Parses tokens as they're pulled from any token iterator, such as a
+/// `Scanner`, keeping only the few it looks ahead at. Scan errors are
+/// reported as they're reached and their tokens skipped.
+pub struct Parser<'a, I> {
+    tokens: I,
+    /// The upcoming tokens, up to and including `Eof`.
+    lookahead: VecDeque<Token>,
+    opt_previous: Option<Token>,
+    /// How many `(` have been consumed without their `)`.
+    open_parens: usize,
+    /// How many statements, expressions and chain links the current one is
+    /// nested in.
+    depth: usize,
+    limits: Limits,
+    stack: StackGuard,
+    diagnostics: &'a mut Diagnostics,
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+impl<'a, I: Iterator<Item = Result<Token, ScanError>>> Parser<'a, I> {
+    pub fn new<T>(tokens: T, diagnostics: &'a mut Diagnostics) -> Self
+    where
+        T: IntoIterator<IntoIter = I>,
+    {
+        let mut parser = Self {
+            tokens: tokens.into_iter(),
+            lookahead: VecDeque::with_capacity(LOOKAHEAD),
+            opt_previous: None,
+            open_parens: 0,
+            depth: 0,
+            limits: Limits::default(),
+            stack: StackGuard::new(Limits::default().stack_size),
+            diagnostics,
+        };
+
+        parser.fill_lookahead(LOOKAHEAD);
+
+        parser
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.stack.size = limits.stack_size;
+        self.limits = limits;
+
+        self
     }
 
     pub fn parse(&mut self) -> Vec<Stmt> {
         let mut statements = Vec::new();
 
-        while !self.is_at_end() {
-            match self.declaration() {
-                Ok(stmt) => statements.push(stmt),
-                Err(_) => self.synchronize(),
-            }
+        while let Some(top_level) = self.top_level() {
+            statements.extend(top_level.statements);
         }
 
         statements
     }
 
-    fn declaration(&mut self) -> Result<Stmt, ParseError> {
-        if self.matches(vec![TokenType::Class]) {
-            self.class_declaration()
-        } else if self.matches(vec![TokenType::Fun]) {
-            self.function("function")
-        } else if self.matches(vec![TokenType::Var]) {
+    /// Parses the next top-level declaration, or returns `None` at the end
+    /// of the tokens. A declaration with errors is skipped past like in
+    /// `parse` and comes back without statements.
+    pub fn top_level(&mut self) -> Option<TopLevel> {
+        if self.is_at_end() {
+            return None;
+        }
+
+        let reported = self.diagnostics.errors().len() + self.diagnostics.warnings().len();
+
+        let statements = match self.declaration() {
+            Ok(stmts) => stmts,
+            Err(_) => {
+                self.synchronize();
+
+                // No block is open to close, so a `}` here only belongs
+                // to the code the error was in.
+                while self.matches(&[TokenType::RightBrace]) {}
+
+                Vec::new()
+            }
+        };
+
+        Some(TopLevel {
+            statements,
+            last: self.previous().clone(),
+            seen_line: self.lookahead.back().map_or(0, |token| token.line),
+            is_clean: self.diagnostics.errors().len() + self.diagnostics.warnings().len()
+                == reported,
+        })
+    }
+
+    /// One declaration or statement. A declaration list like
+    /// `var a = 1, b;` becomes one `Stmt::Var` per variable.
+    fn declaration(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        if self.matches(&[TokenType::Class]) {
+            Ok(vec![self.class_declaration()?])
+        } else if self.matches(&[TokenType::Fun]) {
+            Ok(vec![self.function("function")?])
+        } else if self.matches(&[TokenType::Var]) {
             self.var_declaration()
+        } else if self.matches_keyword(TokenType::Const) {
+            self.const_declaration()
         } else {
-            self.statement()
+            Ok(vec![self.statement()?])
         }
     }
 
     fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
         let name = self.consume(TokenType::Identifier, "Expect class name.")?;
 
-        let opt_superclass = if self.matches(vec![TokenType::Less]) {
+        let opt_superclass = if self.matches(&[TokenType::Less]) {
             self.consume(TokenType::Identifier, "Expect superclass name.")?;
 
-            Some(Expr::Variable(self.previous()))
+            Some(Expr::Variable {
+                id: ExprId::next(),
+                name: self.previous().clone(),
+            })
         } else {
             None
         };
 
         self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
 
+        let mut fields = Vec::new();
         let mut methods = Vec::new();
 
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            methods.push(self.function("method")?);
+            if self.matches(&[TokenType::Var]) {
+                fields.extend(self.field_declaration()?);
+            } else {
+                methods.push(self.function("method")?);
+            }
         }
 
         self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
 
         Ok(Stmt::Class {
             name,
+            fields,
             methods,
             opt_superclass,
         })
     }
 
+    /// The fields of a `var` declaration in a class body. A field without
+    /// an initializer starts out nil.
+    fn field_declaration(&mut self) -> Result<Vec<(Token, Expr)>, ParseError> {
+        let mut fields = Vec::new();
+
+        loop {
+            let name = self.consume(TokenType::Identifier, "Expect field name.")?;
+
+            let value = if self.matches(&[TokenType::Equal]) {
+                self.assignment()?
+            } else {
+                Expr::Literal(LoxType::Nil)
+            };
+
+            fields.push((name, value));
+
+            if !self.matches(&[TokenType::Comma]) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::SemiColon, "Expect ';' after field declaration.")?;
+
+        Ok(fields)
+    }
+
     fn function(&mut self, kind: &str) -> Result<Stmt, ParseError> {
+        self.nested(|parser| parser.function_declaration(kind))
+    }
+
+    fn function_declaration(&mut self, kind: &str) -> Result<Stmt, ParseError> {
         let name = self.consume(TokenType::Identifier, &format!("Expect {} name.", kind))?;
 
         self.consume(
@@ -81,22 +212,42 @@ impl Parser {
         )?;
 
         let mut params = Vec::new();
+        let mut opt_rest = None;
 
         if !self.check(TokenType::RightParen) {
             loop {
-                if params.len() >= 255 {
-                    self.error(self.peek(), "Can't have more than 255 parameters.");
+                if params.len() >= self.limits.max_arguments {
+                    self.error(
+                        self.peek().clone(),
+                        &format!(
+                            "Can't have more than {} parameters.",
+                            self.limits.max_arguments
+                        ),
+                    );
+                }
+
+                if self.matches(&[TokenType::Ellipsis]) {
+                    opt_rest = Some(self.consume(TokenType::Identifier, "Expect parameter name.")?);
+
+                    break;
                 }
 
                 params.push(self.consume(TokenType::Identifier, "Expect parameter name.")?);
 
-                if !self.matches(vec![TokenType::Comma]) {
+                if !self.matches(&[TokenType::Comma]) {
                     break;
                 }
             }
         }
 
-        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        self.consume(
+            TokenType::RightParen,
+            if opt_rest.is_some() {
+                "Expect ')' after rest parameter."
+            } else {
+                "Expect ')' after parameters."
+            },
+        )?;
 
         self.consume(
             TokenType::LeftBrace,
@@ -105,53 +256,246 @@ impl Parser {
 
         let body = self.block()?;
 
-        Ok(Stmt::Function { name, body, params })
+        Ok(Stmt::Function {
+            name,
+            params,
+            opt_rest,
+            body,
+        })
     }
 
-    fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
-        let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+    fn var_declaration(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut declarations = Vec::new();
 
-        let initializer = if self.matches(vec![TokenType::Equal]) {
-            self.expression()?
+        loop {
+            if self.matches(&[TokenType::LeftParen, TokenType::LeftBrace]) {
+                declarations.push(self.destructure()?);
+
+                if !self.matches(&[TokenType::Comma]) {
+                    break;
+                }
+
+                continue;
+            }
+
+            let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+
+            let initializer = if self.matches(&[TokenType::Equal]) {
+                self.assignment()?
+            } else {
+                Expr::Literal(LoxType::Uninitialized)
+            };
+
+            declarations.push(Stmt::Var {
+                name,
+                initializer,
+                is_const: false,
+            });
+
+            if !self.matches(&[TokenType::Comma]) {
+                break;
+            }
+        }
+
+        self.consume(
+            TokenType::SemiColon,
+            "Expect ';' after variable declaration.",
+        )?;
+
+        Ok(declarations)
+    }
+
+    /// The rest of `var (a, b) = value` or `var {a, b} = value` after the
+    /// opening parenthesis or brace.
+    fn destructure(&mut self) -> Result<Stmt, ParseError> {
+        let (opening, names) = self.destructure_names()?;
+
+        Ok(Stmt::Destructure {
+            opening,
+            names,
+            initializer: self.assignment()?,
+        })
+    }
+
+    /// The names of a destructuring and the `=` after them, returned with
+    /// the opening parenthesis or brace that was just consumed.
+    fn destructure_names(&mut self) -> Result<(Token, Vec<Token>), ParseError> {
+        let opening = self.previous().clone();
+
+        let (closing, message) = if opening.token_type == TokenType::LeftBrace {
+            (TokenType::RightBrace, "Expect '}' after variable names.")
         } else {
-            Expr::Literal(LoxType::Nil)
+            (TokenType::RightParen, "Expect ')' after variable names.")
         };
 
+        let mut names = vec![self.consume(TokenType::Identifier, "Expect variable name.")?];
+
+        while self.matches(&[TokenType::Comma]) {
+            names.push(self.consume(TokenType::Identifier, "Expect variable name.")?);
+        }
+
+        self.consume(closing, message)?;
+
+        self.consume(TokenType::Equal, "Expect '=' after variable names.")?;
+
+        Ok((opening, names))
+    }
+
+    fn const_declaration(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut declarations = Vec::new();
+
+        loop {
+            let name = self.consume(TokenType::Identifier, "Expect constant name.")?;
+
+            self.consume(TokenType::Equal, "Expect '=' after constant name.")?;
+
+            declarations.push(Stmt::Var {
+                name,
+                initializer: self.assignment()?,
+                is_const: true,
+            });
+
+            if !self.matches(&[TokenType::Comma]) {
+                break;
+            }
+        }
+
         self.consume(
             TokenType::SemiColon,
-            "Expect ';' after variable declaration.",
+            "Expect ';' after constant declaration.",
         )?;
 
-        Ok(Stmt::Var { name, initializer })
+        Ok(declarations)
     }
 
     fn statement(&mut self) -> Result<Stmt, ParseError> {
-        if self.matches(vec![TokenType::For]) {
+        self.nested(Self::statement_kind)
+    }
+
+    fn statement_kind(&mut self) -> Result<Stmt, ParseError> {
+        if self.matches_keyword(TokenType::Do) {
+            self.do_while_statement()
+        } else if self.matches(&[TokenType::For]) {
             self.for_statement()
-        } else if self.matches(vec![TokenType::If]) {
+        } else if self.matches(&[TokenType::If]) {
             self.if_statement()
-        } else if self.matches(vec![TokenType::Print]) {
+        } else if self.matches_contextual("import", TokenType::String) {
+            self.import_statement()
+        } else if self.matches(&[TokenType::Print]) {
             self.print_statement()
-        } else if self.matches(vec![TokenType::Return]) {
+        } else if self.matches(&[TokenType::Return]) {
             self.return_statement()
-        } else if self.matches(vec![TokenType::While]) {
+        } else if self.matches_keyword(TokenType::Switch) {
+            self.switch_statement()
+        } else if self.matches_keyword(TokenType::Throw) {
+            self.throw_statement()
+        } else if self.matches_keyword(TokenType::Try) {
+            self.try_statement()
+        } else if self.matches(&[TokenType::While]) {
             self.while_statement()
-        } else if self.matches(vec![TokenType::LeftBrace]) {
+        } else if self.is_destructuring_assignment() {
+            self.advance();
+
+            self.destructuring_assignment()
+        } else if self.matches(&[TokenType::LeftBrace]) {
             Ok(Stmt::Block(self.block()?))
         } else {
             self.expression_statement()
         }
     }
 
+    /// Whether the statement ahead is `(a, b) = value;` or `{a, b} = value;`,
+    /// which can only be told from a grouping or a block by the `=` after
+    /// the names.
+    fn is_destructuring_assignment(&mut self) -> bool {
+        let closing = match self.peek().token_type {
+            TokenType::LeftParen => TokenType::RightParen,
+            TokenType::LeftBrace => TokenType::RightBrace,
+            _ => return false,
+        };
+
+        let mut index = 1;
+
+        loop {
+            let name = self.peek_nth(index);
+
+            if name.token_type != TokenType::Identifier && !is_extension_keyword(name) {
+                return false;
+            }
+
+            match self.peek_nth(index + 1).token_type {
+                TokenType::Comma => index += 2,
+                token_type if token_type == closing => {
+                    return self.peek_nth(index + 2).token_type == TokenType::Equal
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    /// The rest of `(a, b) = value;` or `{a, b} = value;` after the opening
+    /// parenthesis or brace.
+    fn destructuring_assignment(&mut self) -> Result<Stmt, ParseError> {
+        let (opening, names) = self.destructure_names()?;
+
+        let value = self.assignment()?;
+
+        self.consume(TokenType::SemiColon, "Expect ';' after expression.")?;
+
+        Ok(Stmt::DestructureAssign {
+            opening,
+            targets: names
+                .into_iter()
+                .map(|name| (ExprId::next(), name))
+                .collect(),
+            value,
+        })
+    }
+
+    fn do_while_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
+        let body = self.statement()?;
+
+        self.consume(TokenType::While, "Expect 'while' after do body.")?;
+
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+
+        let condition = self.expression()?;
+
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+
+        self.consume(TokenType::SemiColon, "Expect ';' after do-while condition.")?;
+
+        Ok(Stmt::DoWhile {
+            keyword,
+            body: Box::new(body),
+            condition,
+        })
+    }
+
     fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
 
-        let opt_initializer = if self.matches(vec![TokenType::SemiColon]) {
-            None
-        } else if self.matches(vec![TokenType::Var]) {
-            Some(self.var_declaration()?)
+        let is_for_in = self.check(TokenType::Var)
+            && self.lookahead.get(2).is_some_and(|token| {
+                token.token_type == TokenType::Identifier && &*token.lexeme == "in"
+            });
+
+        if is_for_in {
+            self.advance();
+
+            return self.for_in_statement(keyword);
+        }
+
+        let initializers = if self.matches(&[TokenType::SemiColon]) {
+            Vec::new()
+        } else if self.matches(&[TokenType::Var]) {
+            self.var_declaration()?
         } else {
-            Some(self.expression_statement()?)
+            vec![self.expression_statement()?]
         };
 
         let condition = if !self.check(TokenType::SemiColon) {
@@ -163,32 +507,55 @@ impl Parser {
         self.consume(TokenType::SemiColon, "Expect ';' after loop condition.")?;
 
         let opt_increment = if !self.check(TokenType::RightParen) {
-            Some(self.expression()?)
+            Some(Box::new(self.expression()?))
         } else {
             None
         };
 
         self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
 
-        let mut body = self.statement()?;
-
-        if let Some(increment) = opt_increment {
-            body = Stmt::Block(vec![body, Stmt::Expression(increment)]);
-        }
+        let body = self.statement()?;
 
-        body = Stmt::While {
+        Ok(Stmt::For {
+            keyword,
+            initializers,
             condition,
+            opt_increment,
             body: Box::new(body),
+        })
+    }
+
+    /// The rest of a `for (var name in ...)` loop, after the `var`.
+    fn for_in_statement(&mut self, keyword: Token) -> Result<Stmt, ParseError> {
+        let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+
+        // The contextual `in` keyword.
+        self.advance();
+
+        let value = self.expression()?;
+
+        let opt_end = if self.matches(&[TokenType::DotDot]) {
+            Some(Box::new(self.expression()?))
+        } else {
+            None
         };
 
-        if let Some(initializer) = opt_initializer {
-            body = Stmt::Block(vec![initializer, body]);
-        }
+        self.consume(TokenType::RightParen, "Expect ')' after for-in clause.")?;
+
+        let body = self.statement()?;
 
-        Ok(body)
+        Ok(Stmt::ForIn {
+            keyword,
+            name,
+            value,
+            opt_end,
+            body: Box::new(body),
+        })
     }
 
     fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
 
         let condition = self.expression()?;
@@ -197,34 +564,62 @@ impl Parser {
 
         let then_branch = Box::new(self.statement()?);
 
-        let opt_else_branch = if self.matches(vec![TokenType::Else]) {
+        let opt_else_branch = if self.matches(&[TokenType::Else]) {
             Some(Box::new(self.statement()?))
         } else {
             None
         };
 
         Ok(Stmt::If {
+            keyword,
             condition,
             then_branch,
             opt_else_branch,
         })
     }
 
+    fn import_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
+        let path = self.consume(TokenType::String, "Expect module path after 'import'.")?;
+
+        self.consume(TokenType::SemiColon, "Expect ';' after module path.")?;
+
+        Ok(Stmt::Import {
+            keyword,
+            path: path.lexeme.trim_matches('"').to_string(),
+        })
+    }
+
     fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
         let value = self.expression()?;
 
         self.consume(TokenType::SemiColon, "Expect ';' after value.")?;
 
-        Ok(Stmt::Print(value))
+        Ok(Stmt::Print { keyword, value })
     }
 
     fn return_statement(&mut self) -> Result<Stmt, ParseError> {
-        let keyword = self.previous();
+        let keyword = self.previous().clone();
 
-        let value = if !self.check(TokenType::SemiColon) {
-            self.expression()?
-        } else {
+        // Commas after `return` build a tuple rather than acting as the
+        // comma operator.
+        let value = if self.check(TokenType::SemiColon) {
             Expr::Literal(LoxType::Nil)
+        } else {
+            let mut elements = vec![self.assignment()?];
+
+            while self.matches(&[TokenType::Comma]) {
+                elements.push(self.assignment()?);
+            }
+
+            if elements.len() == 1 {
+                elements.remove(0)
+            } else {
+                Expr::Tuple(elements)
+            }
         };
 
         self.consume(TokenType::SemiColon, "Expect ';' after return value.")?;
@@ -232,7 +627,119 @@ impl Parser {
         Ok(Stmt::Return { keyword, value })
     }
 
+    fn switch_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
+        self.consume(TokenType::LeftParen, "Expect '(' after 'switch'.")?;
+
+        let value = self.expression()?;
+
+        self.consume(TokenType::RightParen, "Expect ')' after switch value.")?;
+
+        self.consume(TokenType::LeftBrace, "Expect '{' before switch body.")?;
+
+        let mut cases = Vec::new();
+
+        while self.matches(&[TokenType::Case]) {
+            let case_value = self.expression()?;
+
+            self.consume(TokenType::Colon, "Expect ':' after case value.")?;
+
+            cases.push((case_value, self.case_body()?));
+        }
+
+        let opt_default = if self.matches(&[TokenType::Default]) {
+            self.consume(TokenType::Colon, "Expect ':' after 'default'.")?;
+
+            Some(self.case_body()?)
+        } else {
+            None
+        };
+
+        self.consume(TokenType::RightBrace, "Expect '}' after switch body.")?;
+
+        Ok(Stmt::Switch {
+            keyword,
+            value,
+            cases,
+            opt_default,
+        })
+    }
+
+    fn case_body(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut statements = Vec::new();
+
+        while !self.check(TokenType::Case)
+            && !self.check(TokenType::Default)
+            && !self.check(TokenType::RightBrace)
+            && !self.is_at_end()
+        {
+            statements.extend(self.declaration()?);
+        }
+
+        Ok(statements)
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
+        let value = self.expression()?;
+
+        self.consume(TokenType::SemiColon, "Expect ';' after thrown value.")?;
+
+        Ok(Stmt::Throw { keyword, value })
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+
+        let body = self.block()?;
+
+        let opt_catch = if self.matches(&[TokenType::Catch]) {
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+
+            let name = self.consume(TokenType::Identifier, "Expect exception variable name.")?;
+
+            self.consume(
+                TokenType::RightParen,
+                "Expect ')' after exception variable.",
+            )?;
+
+            self.consume(TokenType::LeftBrace, "Expect '{' before catch body.")?;
+
+            Some((name, self.block()?))
+        } else {
+            None
+        };
+
+        let opt_finally = if self.matches(&[TokenType::Finally]) {
+            self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.")?;
+
+            Some(self.block()?)
+        } else {
+            None
+        };
+
+        if opt_catch.is_none() && opt_finally.is_none() {
+            return Err(self.error(
+                self.peek().clone(),
+                "Expect 'catch' or 'finally' after try block.",
+            ));
+        }
+
+        Ok(Stmt::Try {
+            keyword,
+            body,
+            opt_catch,
+            opt_finally,
+        })
+    }
+
     fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
 
         let condition = self.expression()?;
@@ -242,16 +749,22 @@ impl Parser {
         let body = self.statement()?;
 
         Ok(Stmt::While {
+            keyword,
             condition,
             body: Box::new(body),
         })
     }
 
+    /// The statements up to a closing `}`. A statement that doesn't parse
+    /// is skipped so the rest of the block is still checked.
     fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
         let mut statements = Vec::new();
 
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            match self.declaration() {
+                Ok(stmts) => statements.extend(stmts),
+                Err(_) => self.synchronize(),
+            }
         }
 
         self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
@@ -268,23 +781,54 @@ impl Parser {
     }
 
     fn expression(&mut self) -> Result<Expr, ParseError> {
-        self.assignment()
+        self.comma()
+    }
+
+    /// `a, b` evaluates both operands left to right and yields `b`. Places
+    /// where a comma separates items, like arguments and `return` values,
+    /// parse `assignment`.
+    fn comma(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.assignment()?;
+        let depth = self.depth;
+
+        while self.matches(&[TokenType::Comma]) {
+            let operator = self.previous().clone();
+
+            self.link()?;
+
+            let right = self.assignment()?;
+
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        self.depth = depth;
+
+        Ok(expr)
     }
 
     fn assignment(&mut self) -> Result<Expr, ParseError> {
-        let expr = self.or()?;
+        self.nested(Self::assignment_target)
+    }
 
-        if self.matches(vec![TokenType::Equal]) {
-            let equals = self.previous();
+    fn assignment_target(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.coalesce()?;
+
+        if self.matches(&[TokenType::Equal]) {
+            let equals = self.previous().clone();
 
             let value = self.assignment()?;
 
             match expr {
-                Expr::Variable(name) => Ok(Expr::Assign {
+                Expr::Variable { id, name } => Ok(Expr::Assign {
+                    id,
                     name,
                     value: Box::new(value),
                 }),
-                Expr::Get { name, object } => Ok(Expr::Set {
+                Expr::Get { name, object, .. } => Ok(Expr::Set {
                     object,
                     name,
                     value: Box::new(value),
@@ -296,11 +840,39 @@ impl Parser {
         }
     }
 
+    /// `a ?? b`, binding looser than `or` so `a or b ?? c` defaults the
+    /// result of the `or`.
+    fn coalesce(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.or()?;
+        let depth = self.depth;
+
+        while self.matches(&[TokenType::QuestionQuestion]) {
+            let operator = self.previous().clone();
+
+            self.link()?;
+
+            let right = self.or()?;
+
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        self.depth = depth;
+
+        Ok(expr)
+    }
+
     fn or(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.and()?;
+        let depth = self.depth;
+
+        while self.matches(&[TokenType::Or]) {
+            let operator = self.previous().clone();
 
-        while self.matches(vec![TokenType::Or]) {
-            let operator = self.previous();
+            self.link()?;
 
             let right = self.and()?;
 
@@ -311,16 +883,21 @@ impl Parser {
             };
         }
 
+        self.depth = depth;
+
         Ok(expr)
     }
 
     fn and(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.equality()?;
+        let mut expr = self.bit_or()?;
+        let depth = self.depth;
 
-        while self.matches(vec![TokenType::And]) {
-            let operator = self.previous();
+        while self.matches(&[TokenType::And]) {
+            let operator = self.previous().clone();
 
-            let right = self.equality()?;
+            self.link()?;
+
+            let right = self.bit_or()?;
 
             expr = Expr::Logical {
                 left: Box::new(expr),
@@ -329,14 +906,88 @@ impl Parser {
             }
         }
 
+        self.depth = depth;
+
+        Ok(expr)
+    }
+
+    fn bit_or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.bit_xor()?;
+        let depth = self.depth;
+
+        while self.matches(&[TokenType::Pipe]) {
+            let operator = self.previous().clone();
+
+            self.link()?;
+
+            let right = self.bit_xor()?;
+
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        self.depth = depth;
+
+        Ok(expr)
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.bit_and()?;
+        let depth = self.depth;
+
+        while self.matches(&[TokenType::Caret]) {
+            let operator = self.previous().clone();
+
+            self.link()?;
+
+            let right = self.bit_and()?;
+
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        self.depth = depth;
+
+        Ok(expr)
+    }
+
+    fn bit_and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.equality()?;
+        let depth = self.depth;
+
+        while self.matches(&[TokenType::Ampersand]) {
+            let operator = self.previous().clone();
+
+            self.link()?;
+
+            let right = self.equality()?;
+
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        self.depth = depth;
+
         Ok(expr)
     }
 
     fn equality(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.comparison()?;
+        let depth = self.depth;
+
+        while self.matches(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+            let operator = self.previous().clone();
 
-        while self.matches(vec![TokenType::BangEqual, TokenType::EqualEqual]) {
-            let operator = self.previous();
+            self.link()?;
 
             let right = self.comparison()?;
 
@@ -347,19 +998,48 @@ impl Parser {
             };
         }
 
+        self.depth = depth;
+
         Ok(expr)
     }
 
     fn comparison(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.term()?;
+        let mut expr = self.shift()?;
+        let depth = self.depth;
 
-        while self.matches(vec![
+        while self.matches(&[
             TokenType::Greater,
             TokenType::GreaterEqual,
             TokenType::Less,
             TokenType::LessEqual,
+            TokenType::Is,
         ]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
+
+            self.link()?;
+
+            let right = self.shift()?;
+
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        self.depth = depth;
+
+        Ok(expr)
+    }
+
+    fn shift(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.term()?;
+        let depth = self.depth;
+
+        while self.matches(&[TokenType::GreaterGreater, TokenType::LessLess]) {
+            let operator = self.previous().clone();
+
+            self.link()?;
 
             let right = self.term()?;
 
@@ -370,14 +1050,19 @@ impl Parser {
             };
         }
 
+        self.depth = depth;
+
         Ok(expr)
     }
 
     fn term(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.factor()?;
+        let depth = self.depth;
+
+        while self.matches(&[TokenType::Minus, TokenType::Plus]) {
+            let operator = self.previous().clone();
 
-        while self.matches(vec![TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous();
+            self.link()?;
 
             let right = self.factor()?;
 
@@ -388,14 +1073,19 @@ impl Parser {
             };
         }
 
+        self.depth = depth;
+
         Ok(expr)
     }
 
     fn factor(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.unary()?;
+        let depth = self.depth;
+
+        while self.matches(&[TokenType::Slash, TokenType::Star]) {
+            let operator = self.previous().clone();
 
-        while self.matches(vec![TokenType::Slash, TokenType::Star]) {
-            let operator = self.previous();
+            self.link()?;
 
             let right = self.unary()?;
 
@@ -406,14 +1096,16 @@ impl Parser {
             }
         }
 
+        self.depth = depth;
+
         Ok(expr)
     }
 
     fn unary(&mut self) -> Result<Expr, ParseError> {
-        if self.matches(vec![TokenType::Bang, TokenType::Minus]) {
-            let operator = self.previous();
+        if self.matches(&[TokenType::Bang, TokenType::Minus, TokenType::Tilde]) {
+            let operator = self.previous().clone();
 
-            let right = self.unary()?;
+            let right = self.nested(Self::unary)?;
 
             Ok(Expr::Unary {
                 operator,
@@ -426,72 +1118,165 @@ impl Parser {
 
     fn call(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.primary()?;
+        let mut is_optional_chain = false;
+        let depth = self.depth;
 
         loop {
-            if self.matches(vec![TokenType::LeftParen]) {
+            if self.matches(&[TokenType::LeftParen]) {
+                self.link()?;
+
                 expr = self.finish_call(expr)?;
-            } else if self.matches(vec![TokenType::Dot]) {
+            } else if self.matches(&[TokenType::Dot, TokenType::QuestionDot]) {
+                self.link()?;
+
+                let is_optional = self.previous().token_type == TokenType::QuestionDot;
+
                 let name =
                     self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
 
                 expr = Expr::Get {
                     object: Box::new(expr),
                     name,
+                    is_optional,
                 };
+
+                is_optional_chain |= is_optional;
             } else {
                 break;
             }
         }
 
-        Ok(expr)
+        self.depth = depth;
+
+        if is_optional_chain {
+            Ok(Expr::OptionalChain(Box::new(expr)))
+        } else {
+            Ok(expr)
+        }
     }
 
     fn primary(&mut self) -> Result<Expr, ParseError> {
-        if self.matches(vec![TokenType::False]) {
+        if self.matches(&[TokenType::False]) {
             Ok(Expr::Literal(LoxType::Boolean(false)))
-        } else if self.matches(vec![TokenType::True]) {
+        } else if self.matches(&[TokenType::True]) {
             Ok(Expr::Literal(LoxType::Boolean(true)))
-        } else if self.matches(vec![TokenType::Nil]) {
+        } else if self.matches(&[TokenType::Nil]) {
             Ok(Expr::Literal(LoxType::Nil))
-        } else if self.matches(vec![TokenType::Number, TokenType::String])
+        } else if self.matches(&[TokenType::Interpolation]) {
+            self.interpolation()
+        } else if self.matches(&[TokenType::Number, TokenType::String])
             && self.previous().literal.is_some()
         {
-            Ok(Expr::Literal(self.previous().literal.unwrap()))
-        } else if self.matches(vec![TokenType::Super]) {
-            let keyword = self.previous();
+            Ok(Expr::Literal(self.previous().literal.clone().unwrap()))
+        } else if self.matches(&[TokenType::Super]) {
+            let keyword = self.previous().clone();
 
             self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
 
             let method = self.consume(TokenType::Identifier, "Expect superclass method name.")?;
 
-            Ok(Expr::Super { keyword, method })
-        } else if self.matches(vec![TokenType::This]) {
-            Ok(Expr::This(self.previous()))
-        } else if self.matches(vec![TokenType::Identifier]) {
-            Ok(Expr::Variable(self.previous()))
-        } else if self.matches(vec![TokenType::LeftParen]) {
+            Ok(Expr::Super {
+                id: ExprId::next(),
+                keyword,
+                method,
+            })
+        } else if self.matches(&[TokenType::This]) {
+            Ok(Expr::This {
+                id: ExprId::next(),
+                keyword: self.previous().clone(),
+            })
+        } else if self.matches(&[TokenType::Identifier]) {
+            Ok(Expr::Variable {
+                id: ExprId::next(),
+                name: self.previous().clone(),
+            })
+        } else if self.matches(&[TokenType::LeftParen]) {
             let expr = self.expression()?;
 
             self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
 
             Ok(Expr::Grouping(Box::new(expr)))
         } else {
-            Err(self.error(self.peek(), "Expect expression."))
+            Err(self.error(self.peek().clone(), "Expect expression."))
+        }
+    }
+
+    /// Desugars `"a ${b} c"` into `"a " + b + " c"`, where each `+` is an
+    /// `Interpolation` operator that turns any value into a string the way
+    /// `print` does.
+    fn interpolation(&mut self) -> Result<Expr, ParseError> {
+        let start = self.previous().clone();
+
+        let plus = Token::new(
+            TokenType::Interpolation,
+            Shared::from("+"),
+            None,
+            start.line,
+            start.column,
+        );
+
+        let concat = |left: Expr, right: Expr| Expr::Binary {
+            left: Box::new(left),
+            operator: plus.clone(),
+            right: Box::new(right),
+        };
+
+        let mut expr = Expr::Literal(start.literal.unwrap_or(LoxType::Nil));
+        let depth = self.depth;
+
+        loop {
+            self.link()?;
+
+            expr = concat(expr, self.expression()?);
+
+            let segment = if self.matches(&[TokenType::Interpolation]) {
+                self.previous().clone()
+            } else {
+                self.consume(TokenType::String, "Expect end of string interpolation.")?
+            };
+
+            let is_end = segment.token_type == TokenType::String;
+
+            if let Some(LoxType::String(s)) = segment.literal {
+                if !s.is_empty() {
+                    expr = concat(expr, Expr::Literal(LoxType::String(s)));
+                }
+            }
+
+            if is_end {
+                self.depth = depth;
+
+                return Ok(expr);
+            }
         }
     }
 
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
         let mut arguments = Vec::new();
+        let depth = self.open_parens;
 
         if !self.check(TokenType::RightParen) {
             loop {
-                if arguments.len() >= 255 {
-                    self.error(self.peek(), "Can't have more than 255 arguments.");
+                if arguments.len() >= self.limits.max_arguments {
+                    self.error(
+                        self.peek().clone(),
+                        &format!(
+                            "Can't have more than {} arguments.",
+                            self.limits.max_arguments
+                        ),
+                    );
                 }
 
-                arguments.push(self.expression()?);
+                match self.argument() {
+                    Ok(argument) => arguments.push(argument),
+                    Err(err) => {
+                        if !self.skip_argument(depth) {
+                            return Err(err);
+                        }
+                    }
+                }
 
-                if !self.matches(vec![TokenType::Comma]) {
+                if !self.matches(&[TokenType::Comma]) {
                     break;
                 }
             }
@@ -506,10 +1291,81 @@ impl Parser {
         })
     }
 
-    fn matches(&mut self, types: Vec<TokenType>) -> bool {
-        for token_type in &types {
-            if self.check(token_type.to_owned()) {
-                self.advance();
+    /// A call argument, which may spread a tuple with `...`.
+    fn argument(&mut self) -> Result<Expr, ParseError> {
+        if self.matches(&[TokenType::Ellipsis]) {
+            let ellipsis = self.previous().clone();
+
+            Ok(Expr::Spread {
+                ellipsis,
+                value: Box::new(self.assignment()?),
+            })
+        } else {
+            self.assignment()
+        }
+    }
+
+    /// Runs `parse` one level deeper, failing instead if that would nest
+    /// past `Limits::max_nesting_depth` or come close to the end of the
+    /// stack. Links of a chain that `parse` left counted on an error are
+    /// given back too.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth >= self.limits.max_nesting_depth || self.stack.is_exhausted() {
+            return Err(self.error(self.peek().clone(), "Too much nesting."));
+        }
+
+        let depth = self.depth;
+
+        self.depth += 1;
+
+        let result = parse(self);
+
+        self.depth = depth;
+
+        result
+    }
+
+    /// Counts one more link of a chain like `a + b + c`, which nests its
+    /// tree a level deeper each time. The chain gives its links back once
+    /// it ends.
+    fn link(&mut self) -> Result<(), ParseError> {
+        if self.depth >= self.limits.max_nesting_depth {
+            return Err(self.error(self.previous().clone(), "Too much nesting."));
+        }
+
+        self.depth += 1;
+
+        Ok(())
+    }
+
+    /// Skips the rest of an argument that doesn't parse, stopping at the
+    /// `,` or `)` that ends it, `depth` parentheses in. Returns false if the
+    /// argument list doesn't end before the statement does.
+    fn skip_argument(&mut self, depth: usize) -> bool {
+        while !self.is_at_end() {
+            match self.peek().token_type {
+                TokenType::Comma | TokenType::RightParen if self.open_parens == depth => {
+                    return true
+                }
+                TokenType::SemiColon | TokenType::LeftBrace | TokenType::RightBrace => {
+                    return false
+                }
+                _ => {}
+            }
+
+            self.advance();
+        }
+
+        false
+    }
+
+    fn matches(&mut self, types: &[TokenType]) -> bool {
+        for token_type in types {
+            if self.check(*token_type) {
+                self.advance_as(*token_type);
 
                 return true;
             }
@@ -518,48 +1374,157 @@ impl Parser {
         false
     }
 
+    /// Matches an extension keyword that starts a statement. Followed by a
+    /// token that can only come after an operand, as in `do = 1;`, it's left
+    /// to be read as an identifier instead.
+    fn matches_keyword(&mut self, keyword: TokenType) -> bool {
+        let is_keyword = self.check(keyword)
+            && !self
+                .lookahead
+                .get(1)
+                .is_some_and(|token| token.token_type.follows_operand());
+
+        if is_keyword {
+            self.advance();
+        }
+
+        is_keyword
+    }
+
+    /// Matches a contextual keyword: an identifier that only acts as a
+    /// keyword when followed by a token of type `next`, so it stays usable as
+    /// a plain identifier everywhere else.
+    fn matches_contextual(&mut self, keyword: &str, next: TokenType) -> bool {
+        let is_keyword = self.check(TokenType::Identifier)
+            && &*self.peek().lexeme == keyword
+            && self.lookahead.get(1).map(|token| &token.token_type) == Some(&next);
+
+        if is_keyword {
+            self.advance();
+        }
+
+        is_keyword
+    }
+
     fn consume(&mut self, token_type: TokenType, message: &str) -> Result<Token, ParseError> {
         if self.check(token_type) {
-            Ok(self.advance())
+            self.advance_as(token_type);
+
+            Ok(self.previous().clone())
         } else {
-            Err(self.error(self.peek(), message))
+            Err(self.error(self.peek().clone(), message))
         }
     }
 
+    /// Whether the next token is of type `token_type`. Extension keywords
+    /// also pass for identifiers, since outside the places they start their
+    /// own constructs they're ordinary names.
     fn check(&self, token_type: TokenType) -> bool {
         if self.is_at_end() {
             return false;
         }
 
         self.peek().token_type == token_type
+            || token_type == TokenType::Identifier && is_extension_keyword(self.peek())
+    }
+
+    /// Advances past a token `check` accepted as `token_type`, so an
+    /// extension keyword taken as an identifier becomes one.
+    fn advance_as(&mut self, token_type: TokenType) {
+        self.advance();
+
+        if let Some(ref mut previous) = self.opt_previous {
+            previous.token_type = token_type;
+        }
     }
 
-    fn advance(&mut self) -> Token {
+    fn advance(&mut self) {
         if !self.is_at_end() {
-            self.current += 1;
+            self.opt_previous = self.lookahead.pop_front();
+
+            match self.previous().token_type {
+                TokenType::LeftParen => self.open_parens += 1,
+                TokenType::RightParen => self.open_parens = self.open_parens.saturating_sub(1),
+                _ => {}
+            }
+
+            self.fill_lookahead(LOOKAHEAD);
         }
+    }
 
-        self.previous()
+    /// Pulls tokens until `count` are buffered or `Eof` is reached. A token
+    /// stream that stops without an `Eof` gets one.
+    fn fill_lookahead(&mut self, count: usize) {
+        while self.lookahead.len() < count
+            && self
+                .lookahead
+                .back()
+                .is_none_or(|token| token.token_type != TokenType::Eof)
+        {
+            match self.tokens.next() {
+                Some(Ok(token)) => self.lookahead.push_back(token),
+                Some(Err(err)) => self.diagnostics.error(err.line, &err.message),
+                None => {
+                    let line = self
+                        .lookahead
+                        .back()
+                        .or(self.opt_previous.as_ref())
+                        .map_or(1, |token| token.line);
+
+                    self.lookahead.push_back(Token::new(
+                        TokenType::Eof,
+                        Shared::from(""),
+                        None,
+                        line,
+                        0,
+                    ));
+                }
+            }
+        }
     }
 
     fn is_at_end(&self) -> bool {
         self.peek().token_type == TokenType::Eof
     }
 
-    fn peek(&self) -> Token {
-        self.tokens[self.current].clone()
+    fn peek(&self) -> &Token {
+        &self.lookahead[0]
+    }
+
+    /// The token `n` places ahead, pulling more than `LOOKAHEAD` if needed,
+    /// or `Eof` past the end.
+    fn peek_nth(&mut self, n: usize) -> &Token {
+        self.fill_lookahead(n + 1);
+
+        let last = self.lookahead.len() - 1;
+
+        &self.lookahead[n.min(last)]
     }
 
-    fn previous(&self) -> Token {
-        self.tokens[self.current - 1].clone()
+    fn previous(&self) -> &Token {
+        self.opt_previous
+            .as_ref()
+            .unwrap_or_else(|| panic!("No token before {}", self.peek().lexeme))
     }
 
-    fn error(&self, token: Token, message: &str) -> ParseError {
-        lox::parse_error(&token, message);
+    fn error(&mut self, token: Token, message: &str) -> ParseError {
+        if is_extension_keyword(&token) {
+            self.diagnostics.token_error(
+                &token,
+                &format!(
+                    "{} '{}' is a reserved word; rename the identifier or run with --compat.",
+                    message, token.lexeme
+                ),
+            );
+        } else {
+            self.diagnostics.token_error(&token, message);
+        }
 
         ParseError {}
     }
 
+    /// Skips to the start of the next statement, or to the `}` closing the
+    /// block the error was in.
     fn synchronize(&mut self) {
         self.advance();
 
@@ -569,14 +1534,20 @@ impl Parser {
             }
 
             match self.peek().token_type {
-                TokenType::Class
+                TokenType::RightBrace
+                | TokenType::Class
+                | TokenType::Const
+                | TokenType::Do
                 | TokenType::Fun
                 | TokenType::Var
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return,
+                | TokenType::Return
+                | TokenType::Switch
+                | TokenType::Throw
+                | TokenType::Try => return,
                 _ => {}
             }
 
@@ -584,3 +1555,9 @@ impl Parser {
         }
     }
 }
+
+/// Whether `token` is one of the keywords added on top of the book, which
+/// compat mode scans as identifiers instead.
+fn is_extension_keyword(token: &Token) -> bool {
+    token.token_type != TokenType::Identifier && EXTENSION_KEYWORDS.contains(&&*token.lexeme)
+}
@@ -0,0 +1,95 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{shared::Shared, stats::format_duration, token::Token};
+
+/// Call counts and time spent in each user function, collected by
+/// `--profile`. Functions are told apart by name and declaration line.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    entries: HashMap<(Shared<str>, usize), Entry>,
+    stack: Vec<Frame>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Entry {
+    calls: usize,
+    /// Time from entering to leaving the function, counted once for
+    /// recursive calls.
+    total: Duration,
+    /// `total` minus the time spent in the functions it called.
+    self_time: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct Frame {
+    key: (Shared<str>, usize),
+    start: Instant,
+    children: Duration,
+}
+
+impl Profile {
+    pub(crate) fn enter(&mut self, name: &Token) {
+        self.stack.push(Frame {
+            key: (Shared::clone(&name.lexeme), name.line),
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    pub(crate) fn exit(&mut self) {
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        let elapsed = frame.start.elapsed();
+
+        let is_recursive = self.stack.iter().any(|outer| outer.key == frame.key);
+
+        if let Some(caller) = self.stack.last_mut() {
+            caller.children += elapsed;
+        }
+
+        let entry = self.entries.entry(frame.key).or_default();
+
+        entry.calls += 1;
+        entry.self_time += elapsed.saturating_sub(frame.children);
+
+        if !is_recursive {
+            entry.total += elapsed;
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+
+        entries.sort_by(|(a_key, a), (b_key, b)| {
+            b.self_time.cmp(&a.self_time).then_with(|| a_key.cmp(b_key))
+        });
+
+        write!(
+            f,
+            "{:<24} {:>8} {:>12} {:>12}",
+            "function", "calls", "total", "self"
+        )?;
+
+        for ((name, line), entry) in entries {
+            write!(
+                f,
+                "\n{:<24} {:>8} {:>12} {:>12}",
+                format!("{} (line {})", name, line),
+                entry.calls,
+                format_duration(entry.total),
+                format_duration(entry.self_time)
+            )?;
+        }
+
+        Ok(())
+    }
+}
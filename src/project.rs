@@ -0,0 +1,76 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::manifest::MANIFEST_FILE;
+
+/// Name of the per-project file holding default command-line flags.
+pub const RC_FILE: &str = ".rloxrc";
+
+const MAIN: &str = r#"import "lib/greeting.lox";
+
+greet("world");
+"#;
+
+const GREETING: &str = r#"fun greeting(name) {
+  return "Hello, " + name + "!";
+}
+
+fun greet(name) {
+  print greeting(name);
+}
+"#;
+
+const GREETING_TEST: &str = r#"import "../lib/greeting.lox";
+
+print greeting("test"); // expect: Hello, test!
+greet("again"); // expect: Hello, again!
+"#;
+
+const MANIFEST: &str = r#"[project]
+entry = "main.lox"
+src = ["lib"]
+tests = "tests/*_test.lox"
+"#;
+
+const RC: &str = "# Default flags passed to rlox, one per line.
+";
+
+/// Creates a starter project in a new directory called `name`.
+pub fn init(name: &str) -> io::Result<PathBuf> {
+    let root = Path::new(name);
+
+    if root.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("'{}' already exists", name),
+        ));
+    }
+
+    fs::create_dir_all(root.join("lib"))?;
+    fs::create_dir_all(root.join("tests"))?;
+
+    fs::write(root.join("main.lox"), MAIN)?;
+    fs::write(root.join("lib").join("greeting.lox"), GREETING)?;
+    fs::write(root.join("tests").join("greeting_test.lox"), GREETING_TEST)?;
+    fs::write(root.join(MANIFEST_FILE), MANIFEST)?;
+    fs::write(root.join(RC_FILE), RC)?;
+
+    Ok(root.to_path_buf())
+}
+
+/// Reads the flags listed in `.rloxrc` in the current directory, skipping
+/// blank lines and `#` comments.
+pub fn rc_flags() -> Vec<String> {
+    fs::read_to_string(RC_FILE)
+        .map(|contents| {
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,63 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A small SplitMix64 generator behind `random()`, `random_int()` and
+/// `seed()`. Every interpreter has its own, and a seed always replays the
+/// same sequence. Not suitable for anything that needs to be unguessable.
+#[derive(Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Default for Random {
+    /// Seeded from the clock, or with a fixed seed in the browser.
+    fn default() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+
+        #[cfg(target_arch = "wasm32")]
+        let seed = 0;
+
+        Self::new(seed)
+    }
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        z ^ (z >> 31)
+    }
+
+    /// A float in `[0, 1)`.
+    pub fn next_float(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// An integer from `low` to `high`, both included. `low` must not be
+    /// greater than `high`.
+    pub fn next_int(&mut self, low: i64, high: i64) -> i64 {
+        let span = (high as i128 - low as i128) as u128 + 1;
+
+        if span > u64::MAX as u128 {
+            return self.next_u64() as i64;
+        }
+
+        low.wrapping_add((self.next_u64() as u128 % span) as i64)
+    }
+}
@@ -1,10 +1,18 @@
-use std::{collections::HashMap, mem};
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+};
 
 use crate::{
-    ast::{Expr, Stmt},
+    ast::{Expr, ExprId, ExprVisitor, Stmt, StmtVisitor},
+    class::is_private,
+    error::Diagnostics,
     interpreter::Interpreter,
-    lox,
+    lox_type::LoxType,
+    shared::Shared,
+    stack::StackGuard,
     token::Token,
+    token_type::TokenType,
 };
 
 #[derive(Clone)]
@@ -21,274 +29,928 @@ enum ClassType {
     SubClass,
 }
 
+struct Variable {
+    declaration: Option<Token>,
+    kind: DefinitionKind,
+    /// Index into `ResolvedProgram::definitions`, when one is being built.
+    opt_definition: Option<usize>,
+    /// Index of the variable's value in its environment at runtime.
+    slot: usize,
+    defined: bool,
+    used: bool,
+    is_const: bool,
+}
+
+impl Variable {
+    /// `this` or `super`, each alone in its own scope.
+    fn implicit() -> Self {
+        Self {
+            declaration: None,
+            kind: DefinitionKind::Variable,
+            opt_definition: None,
+            slot: 0,
+            defined: true,
+            used: true,
+            is_const: false,
+        }
+    }
+}
+
+/// What a name was declared as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionKind {
+    Class,
+    Constant,
+    Function,
+    Parameter,
+    Variable,
+}
+
+/// A declared name and the scope it lives in.
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub name: Token,
+    pub kind: DefinitionKind,
+    /// Index into `ResolvedProgram::scopes`.
+    pub scope: usize,
+}
+
+/// A block, function body or other scope the resolver entered.
+#[derive(Debug, Clone, Default)]
+pub struct Scope {
+    /// The enclosing scope. Only the global scope has none.
+    pub opt_parent: Option<usize>,
+    /// Indexes into `ResolvedProgram::definitions`, in declaration order.
+    pub definitions: Vec<usize>,
+}
+
+/// A use of a variable and the definition it resolved to. Uses of globals
+/// resolve to the first global declared with that name, if any, since
+/// globals are looked up by name when the code runs.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub name: Token,
+    /// Index into `ResolvedProgram::definitions`.
+    pub opt_definition: Option<usize>,
+}
+
+/// Everything the resolver learned about a program's names, for tools like
+/// go-to-definition, rename and lints that shouldn't redo scope analysis.
+#[derive(Debug, Clone)]
+pub struct ResolvedProgram {
+    /// Every scope entered, the global scope first.
+    pub scopes: Vec<Scope>,
+    /// Every use of a variable, in the order they were resolved.
+    pub references: Vec<Reference>,
+    /// Every declared name, in the order they were declared.
+    pub definitions: Vec<Definition>,
+}
+
+impl Default for ResolvedProgram {
+    fn default() -> Self {
+        Self {
+            scopes: vec![Scope::default()],
+            references: Vec::new(),
+            definitions: Vec::new(),
+        }
+    }
+}
+
+impl ResolvedProgram {
+    /// The definition `reference` resolved to.
+    pub fn definition(&self, reference: &Reference) -> Option<&Definition> {
+        reference
+            .opt_definition
+            .map(|definition| &self.definitions[definition])
+    }
+
+    /// The uses of the definition at `definition`.
+    pub fn references_to(&self, definition: usize) -> impl Iterator<Item = &Reference> {
+        self.references
+            .iter()
+            .filter(move |reference| reference.opt_definition == Some(definition))
+    }
+
+    /// Points uses of globals at the global declared with their name.
+    fn link_globals(&mut self) {
+        let Self {
+            scopes,
+            references,
+            definitions,
+        } = self;
+
+        let globals: HashMap<&Shared<str>, usize> = scopes[0]
+            .definitions
+            .iter()
+            .rev()
+            .map(|&definition| (&definitions[definition].name.lexeme, definition))
+            .collect();
+
+        for reference in references {
+            if reference.opt_definition.is_none() {
+                reference.opt_definition = globals.get(&reference.name.lexeme).copied();
+            }
+        }
+    }
+}
+
 pub struct Resolver<'a> {
     interpreter: &'a mut Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+    diagnostics: &'a mut Diagnostics,
+    scopes: Vec<HashMap<Shared<str>, Variable>>,
     current_function: FunctionType,
     current_class: ClassType,
+    /// Globals declared with `const`, which live outside `scopes`.
+    global_constants: HashSet<Shared<str>>,
+    /// Functions and classes declared at the top level.
+    global_declarations: Vec<(Token, DefinitionKind)>,
+    /// Every name declared at the top level.
+    global_names: HashSet<Shared<str>>,
+    /// Names used that weren't found in any local scope.
+    used_globals: HashSet<Shared<str>>,
+    opt_program: Option<ResolvedProgram>,
+    /// Index into `ResolvedProgram::scopes` of each scope in `scopes`.
+    scope_ids: Vec<usize>,
+    stack: StackGuard,
+    /// Whether nesting too deep for the stack has been reported, which
+    /// only needs saying once.
+    is_too_deep: bool,
 }
 
 impl<'a> Resolver<'a> {
-    pub fn new(interpreter: &'a mut Interpreter) -> Self {
+    pub fn new(interpreter: &'a mut Interpreter, diagnostics: &'a mut Diagnostics) -> Self {
+        let stack = StackGuard::new(interpreter.options().limits.stack_size);
+
         Self {
             interpreter,
+            diagnostics,
             scopes: Vec::new(),
             current_function: FunctionType::None,
             current_class: ClassType::None,
+            global_constants: HashSet::new(),
+            global_declarations: Vec::new(),
+            global_names: HashSet::new(),
+            used_globals: HashSet::new(),
+            opt_program: None,
+            scope_ids: Vec::new(),
+            stack,
+            is_too_deep: false,
         }
     }
 
+    /// Remember every scope, declaration and variable use, for tooling.
+    pub fn record_program(mut self) -> Self {
+        self.opt_program = Some(ResolvedProgram::default());
+
+        self
+    }
+
+    /// What was recorded since `record_program`.
+    pub fn into_program(self) -> ResolvedProgram {
+        let mut program = self.opt_program.unwrap_or_default();
+
+        program.link_globals();
+
+        program
+    }
+
     pub fn resolve(&mut self, stmts: &[Stmt]) {
-        for stmt in stmts {
+        for (index, stmt) in stmts.iter().enumerate() {
             self.resolve_statement(stmt);
+
+            if let Stmt::Return { keyword, .. } = stmt {
+                if index + 1 < stmts.len() {
+                    self.strict_warning(keyword, "Code after 'return' is never run.");
+                }
+            }
+        }
+    }
+
+    /// Warns about top-level functions and classes nothing refers to. Only
+    /// meaningful once the whole program has been resolved. Earlier errors
+    /// don't stop it, so fixing them doesn't turn up a new batch of
+    /// warnings.
+    pub fn warn_unused_globals(&mut self) {
+        for (name, kind) in &self.global_declarations {
+            if !self.used_globals.contains(&name.lexeme) {
+                let what = match kind {
+                    DefinitionKind::Class => "Class",
+                    _ => "Function",
+                };
+
+                self.diagnostics
+                    .warning(name, &format!("{} '{}' is never used.", what, name.lexeme));
+            }
         }
     }
 
     fn resolve_statement(&mut self, stmt: &Stmt) {
-        match stmt {
-            Stmt::Block(stmts) => {
-                self.begin_scope();
+        if !self.is_nested_too_deep(stmt.line()) {
+            stmt.accept(self);
+        }
+    }
 
-                self.resolve(stmts);
+    fn resolve_expression(&mut self, expr: &Expr) {
+        if !self.is_nested_too_deep(expr.line()) {
+            expr.accept(self);
+        }
+    }
 
-                self.end_scope();
-            }
-            Stmt::Class {
-                name,
-                methods,
-                opt_superclass,
-            } => {
-                let enclosing_class = mem::replace(&mut self.current_class, ClassType::Class);
+    /// Whether going deeper could overflow the stack, in which case the
+    /// code at `opt_line` is skipped with an error.
+    fn is_nested_too_deep(&mut self, opt_line: Option<usize>) -> bool {
+        if !self.stack.is_exhausted() {
+            return false;
+        }
 
-                self.declare(name);
-                self.define(name);
+        if !self.is_too_deep {
+            self.is_too_deep = true;
 
-                if let Some(Expr::Variable(superclass_name)) = opt_superclass {
-                    if name.lexeme == superclass_name.lexeme {
-                        lox::parse_error(superclass_name, "A class can't inherit from itself.");
-                    }
+            self.diagnostics
+                .error(opt_line.unwrap_or(0), "Too much nesting.");
+        }
 
-                    self.current_class = ClassType::SubClass;
+        true
+    }
 
-                    self.resolve_local(superclass_name);
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
 
-                    self.begin_scope();
+        if let Some(program) = &mut self.opt_program {
+            program.scopes.push(Scope {
+                opt_parent: Some(self.scope_ids.last().copied().unwrap_or(0)),
+                definitions: Vec::new(),
+            });
 
-                    if let Some(scope) = self.scopes.last_mut() {
-                        scope.insert("super".to_string(), true);
-                    }
-                }
+            self.scope_ids.push(program.scopes.len() - 1);
+        }
+    }
 
-                self.begin_scope();
+    fn end_scope(&mut self) {
+        self.scope_ids.pop();
+
+        if let Some(scope) = self.scopes.pop() {
+            let mut unused: Vec<(Token, DefinitionKind)> = scope
+                .into_iter()
+                .filter(|(_, variable)| !variable.used)
+                .filter_map(|(_, variable)| Some((variable.declaration?, variable.kind)))
+                .collect();
+
+            unused.sort_by_key(|(token, _)| token.line);
+
+            for (token, kind) in unused {
+                let what = match kind {
+                    DefinitionKind::Class => "class",
+                    DefinitionKind::Function => "function",
+                    _ => "variable",
+                };
 
-                if let Some(scope) = self.scopes.last_mut() {
-                    scope.insert("this".to_string(), true);
-                }
+                self.strict_warning(
+                    &token,
+                    &format!("Local {} '{}' is never used.", what, token.lexeme),
+                );
+            }
+        }
+    }
 
-                for method in methods {
-                    if let Stmt::Function {
-                        body, params, name, ..
-                    } = method
-                    {
-                        let mut declaration = FunctionType::Method;
+    fn declare(&mut self, name: &Token, kind: DefinitionKind) {
+        let scope = self.scope_ids.last().copied().unwrap_or(0);
 
-                        if name.lexeme == "init" {
-                            declaration = FunctionType::Initializer;
-                        }
+        let opt_definition = self.opt_program.as_mut().map(|program| {
+            program.definitions.push(Definition {
+                name: name.clone(),
+                kind,
+                scope,
+            });
 
-                        self.resolve_function(params, body, declaration);
-                    }
-                }
+            program.scopes[scope]
+                .definitions
+                .push(program.definitions.len() - 1);
 
-                self.end_scope();
+            program.definitions.len() - 1
+        });
 
-                if opt_superclass.is_some() {
-                    self.end_scope();
-                }
+        if self.interpreter.options().strict {
+            self.warn_shadowing(name);
+        }
 
-                self.current_class = enclosing_class;
-            }
-            Stmt::Expression(expr) => {
-                self.resolve_expression(expr);
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.contains_key(&name.lexeme) {
+                self.diagnostics
+                    .token_error(name, "Already a variable with this name in this scope.")
             }
-            Stmt::Function { body, name, params } => {
-                self.declare(name);
-                self.define(name);
 
-                self.resolve_function(params, body, FunctionType::Function);
+            let slot = scope.len();
+
+            scope.insert(
+                Shared::clone(&name.lexeme),
+                Variable {
+                    declaration: Some(name.clone()),
+                    kind,
+                    opt_definition,
+                    slot,
+                    defined: false,
+                    used: false,
+                    is_const: false,
+                },
+            );
+        } else {
+            self.global_constants.remove(&name.lexeme);
+            self.global_names.insert(Shared::clone(&name.lexeme));
+
+            if let DefinitionKind::Class | DefinitionKind::Function = kind {
+                self.global_declarations.push((name.clone(), kind));
             }
-            Stmt::If {
-                condition,
-                then_branch,
-                opt_else_branch,
-            } => {
-                self.resolve_expression(condition);
+        }
+    }
 
-                self.resolve_statement(then_branch);
+    /// Warns when a local declaration hides a variable from an enclosing
+    /// scope or a global, which is easy to do by accident.
+    fn warn_shadowing(&mut self, name: &Token) {
+        let (current, enclosing) = match self.scopes.split_last() {
+            Some(scopes) => scopes,
+            None => return,
+        };
 
-                if let Some(else_branch) = opt_else_branch {
-                    self.resolve_statement(else_branch);
-                }
-            }
-            Stmt::Print(expr) => {
-                self.resolve_expression(expr);
-            }
-            Stmt::Return { value, keyword } => {
-                if let FunctionType::None = self.current_function {
-                    lox::parse_error(keyword, "Can't return from top-level code.")
-                }
+        if current.contains_key(&name.lexeme) {
+            return;
+        }
 
-                if !value.is_nil() {
-                    if let FunctionType::Initializer = self.current_function {
-                        lox::parse_error(keyword, "Can't return a value from an initializer.");
-                    }
+        let message = if enclosing.iter().any(|scope| {
+            scope
+                .get(&name.lexeme)
+                .is_some_and(|variable| variable.declaration.is_some())
+        }) {
+            format!(
+                "'{}' shadows a variable in an enclosing scope.",
+                name.lexeme
+            )
+        } else if self.global_names.contains(&name.lexeme)
+            || self.interpreter.is_global(&name.lexeme)
+        {
+            format!("'{}' shadows a global.", name.lexeme)
+        } else {
+            return;
+        };
 
-                    self.resolve_expression(value);
-                }
-            }
-            Stmt::Var { name, initializer } => {
-                self.declare(name);
+        self.diagnostics.warning(name, &message);
+    }
 
-                if !initializer.is_nil() {
-                    self.resolve_expression(initializer);
-                }
+    /// A warning that `--strict` reports as an error instead.
+    fn strict_warning(&mut self, token: &Token, message: &str) {
+        if self.interpreter.options().strict {
+            self.diagnostics.token_error(token, message);
+        } else {
+            self.diagnostics.warning(token, message);
+        }
+    }
 
-                self.define(name);
-            }
-            Stmt::While { body, condition } => {
-                self.resolve_expression(condition);
+    fn define(&mut self, name: &Token) {
+        if let Some(variable) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.lexeme))
+        {
+            variable.defined = true;
+        }
+    }
 
-                self.resolve_statement(body);
+    fn mark_used(&mut self, name: &Token) {
+        match self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(&name.lexeme))
+        {
+            Some(variable) => variable.used = true,
+            None => {
+                self.used_globals.insert(Shared::clone(&name.lexeme));
             }
         }
     }
 
-    fn resolve_expression(&mut self, expr: &Expr) {
-        match expr {
-            Expr::Assign { name, value } => {
-                self.resolve_expression(value);
+    fn record_reference(&mut self, name: &Token) {
+        if let Some(program) = &mut self.opt_program {
+            let opt_definition = self
+                .scopes
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(&name.lexeme))
+                .and_then(|variable| variable.opt_definition);
+
+            program.references.push(Reference {
+                name: name.clone(),
+                opt_definition,
+            });
+        }
+    }
 
-                self.resolve_local(name);
-            }
-            Expr::Binary { left, right, .. } => {
-                self.resolve_expression(left);
-                self.resolve_expression(right);
-            }
-            Expr::Call {
-                callee, arguments, ..
-            } => {
-                self.resolve_expression(callee);
+    fn resolve_local(&mut self, id: ExprId, name: &Token) {
+        for (index, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(variable) = scope.get(&name.lexeme) {
+                self.interpreter.resolve(id, index, variable.slot);
 
-                for arg in arguments {
-                    self.resolve_expression(arg);
-                }
-            }
-            Expr::Get { object, .. } => {
-                self.resolve_expression(object);
-            }
-            Expr::Grouping(group) => {
-                self.resolve_expression(group);
+                return;
             }
-            Expr::Literal(_) => (),
-            Expr::Logical { left, right, .. } => {
-                self.resolve_expression(left);
-                self.resolve_expression(right);
+        }
+    }
+
+    /// Points an assignment to `name` at its variable, which mustn't be a
+    /// constant.
+    fn resolve_assignment(&mut self, id: ExprId, name: &Token) {
+        let is_const = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.lexeme))
+            .map_or_else(
+                || self.global_constants.contains(&name.lexeme),
+                |variable| variable.is_const,
+            );
+
+        if is_const {
+            self.diagnostics.token_error(
+                name,
+                &format!("Can't assign to constant '{}'.", name.lexeme),
+            );
+        }
+
+        self.record_reference(name);
+
+        self.resolve_local(id, name);
+    }
+
+    /// Private properties can only be used from inside a class. Which
+    /// instances a class may touch is checked at runtime.
+    fn check_private_access(&mut self, name: &Token) {
+        if let ClassType::None = self.current_class {
+            if is_private(&name.lexeme) && !self.interpreter.options().compat {
+                self.diagnostics.token_error(
+                    name,
+                    &format!(
+                        "Can't access private property '{}' outside of a class.",
+                        name.lexeme
+                    ),
+                );
             }
-            Expr::Set { object, value, .. } => {
-                self.resolve_expression(value);
-                self.resolve_expression(object);
+        }
+    }
+
+    fn resolve_function(
+        &mut self,
+        params: &[Token],
+        opt_rest: Option<&Token>,
+        body: &[Stmt],
+        function_type: FunctionType,
+    ) {
+        let enclosing_function = mem::replace(&mut self.current_function, function_type);
+
+        self.begin_scope();
+
+        for param in params.iter().chain(opt_rest) {
+            self.declare(param, DefinitionKind::Parameter);
+            self.define(param);
+            self.mark_used(param);
+        }
+
+        self.resolve(body);
+
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+    }
+}
+
+impl StmtVisitor<()> for Resolver<'_> {
+    fn visit_block(&mut self, stmts: &[Stmt]) {
+        self.begin_scope();
+
+        self.resolve(stmts);
+
+        self.end_scope();
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        fields: &[(Token, Expr)],
+        methods: &[Stmt],
+        opt_superclass: Option<&Expr>,
+    ) {
+        let enclosing_class = mem::replace(&mut self.current_class, ClassType::Class);
+
+        self.declare(name, DefinitionKind::Class);
+        self.define(name);
+
+        if let Some(Expr::Variable {
+            id,
+            name: superclass_name,
+        }) = opt_superclass
+        {
+            if name.lexeme == superclass_name.lexeme {
+                self.diagnostics
+                    .token_error(superclass_name, "A class can't inherit from itself.");
             }
-            Expr::Super { keyword, .. } => {
-                match self.current_class {
-                    ClassType::None => {
-                        lox::parse_error(keyword, "Can't use 'super' outside of a class.");
-                    }
-                    ClassType::Class => {
-                        lox::parse_error(
-                            keyword,
-                            "Can't use 'super' in a class with no superclass.",
-                        );
-                    }
-                    ClassType::SubClass => (),
-                };
 
-                self.resolve_local(keyword);
+            self.current_class = ClassType::SubClass;
+
+            self.mark_used(superclass_name);
+
+            self.record_reference(superclass_name);
+
+            self.resolve_local(*id, superclass_name);
+
+            self.begin_scope();
+
+            if let Some(scope) = self.scopes.last_mut() {
+                scope.insert(Shared::from("super"), Variable::implicit());
             }
-            Expr::This(keyword) => {
-                if let ClassType::None = self.current_class {
-                    lox::parse_error(keyword, "Can't use 'this' outside of a class.");
-                } else {
-                    self.resolve_local(keyword);
+        }
+
+        self.begin_scope();
+
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(Shared::from("this"), Variable::implicit());
+        }
+
+        // Field initializers run with `this` bound, like a method body.
+        for (_, value) in fields {
+            self.resolve_expression(value);
+        }
+
+        for method in methods {
+            if let Stmt::Function {
+                body,
+                params,
+                opt_rest,
+                name,
+            } = method
+            {
+                let mut declaration = FunctionType::Method;
+
+                if &*name.lexeme == "init" {
+                    declaration = FunctionType::Initializer;
                 }
+
+                self.resolve_function(params, opt_rest.as_ref(), body, declaration);
             }
-            Expr::Unary { right, .. } => {
-                self.resolve_expression(right);
+        }
+
+        self.end_scope();
+
+        if opt_superclass.is_some() {
+            self.end_scope();
+        }
+
+        self.current_class = enclosing_class;
+    }
+
+    fn visit_destructure(&mut self, opening: &Token, names: &[Token], initializer: &Expr) {
+        for name in names {
+            if opening.token_type == TokenType::LeftBrace {
+                self.check_private_access(name);
             }
-            Expr::Variable(name) => {
-                if let Some(scope) = self.scopes.last() {
-                    if let Some(val) = scope.get(&name.lexeme) {
-                        if !val {
-                            lox::parse_error(
-                                name,
-                                "Can't read local variable in its own initializer.",
-                            );
-                        }
-                    }
-                }
 
-                self.resolve_local(name);
+            self.declare(name, DefinitionKind::Variable);
+        }
+
+        self.resolve_expression(initializer);
+
+        for name in names {
+            self.define(name);
+        }
+    }
+
+    fn visit_destructure_assign(
+        &mut self,
+        opening: &Token,
+        targets: &[(ExprId, Token)],
+        value: &Expr,
+    ) {
+        self.resolve_expression(value);
+
+        for (id, name) in targets {
+            if opening.token_type == TokenType::LeftBrace {
+                self.check_private_access(name);
             }
+
+            self.resolve_assignment(*id, name);
         }
     }
 
-    fn begin_scope(&mut self) {
-        self.scopes.push(HashMap::new());
+    fn visit_do_while(&mut self, _keyword: &Token, body: &Stmt, condition: &Expr) {
+        self.resolve_statement(body);
+
+        self.resolve_expression(condition);
     }
 
-    fn end_scope(&mut self) {
-        self.scopes.pop();
+    fn visit_expression(&mut self, expr: &Expr) {
+        self.resolve_expression(expr);
     }
 
-    fn declare(&mut self, name: &Token) {
-        if let Some(scope) = self.scopes.last_mut() {
-            if scope.contains_key(&name.lexeme) {
-                lox::parse_error(name, "Already a variable with this name in this scope.")
-            }
+    fn visit_for(
+        &mut self,
+        _keyword: &Token,
+        initializers: &[Stmt],
+        condition: &Expr,
+        opt_increment: Option<&Expr>,
+        body: &Stmt,
+    ) {
+        self.begin_scope();
 
-            scope.insert(name.lexeme.to_string(), false);
-        };
+        for initializer in initializers {
+            self.resolve_statement(initializer);
+        }
+
+        self.resolve_expression(condition);
+
+        if let Some(increment) = opt_increment {
+            self.resolve_expression(increment);
+        }
+
+        self.resolve_statement(body);
+
+        self.end_scope();
     }
 
-    fn define(&mut self, name: &Token) {
-        if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.lexeme.to_string(), true);
+    fn visit_for_in(
+        &mut self,
+        _keyword: &Token,
+        name: &Token,
+        value: &Expr,
+        opt_end: Option<&Expr>,
+        body: &Stmt,
+    ) {
+        self.resolve_expression(value);
+
+        if let Some(end) = opt_end {
+            self.resolve_expression(end);
         }
+
+        self.begin_scope();
+
+        self.declare(name, DefinitionKind::Variable);
+        self.define(name);
+
+        self.resolve_statement(body);
+
+        self.end_scope();
     }
 
-    fn resolve_local(&mut self, name: &Token) {
-        for (index, scope) in self.scopes.iter().rev().enumerate() {
-            if scope.contains_key(&name.lexeme) {
-                self.interpreter.resolve(name, index);
+    fn visit_function(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        opt_rest: Option<&Token>,
+        body: &[Stmt],
+    ) {
+        self.declare(name, DefinitionKind::Function);
+        self.define(name);
+
+        self.resolve_function(params, opt_rest, body, FunctionType::Function);
+    }
 
-                return;
+    fn visit_if(
+        &mut self,
+        _keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        opt_else_branch: Option<&Stmt>,
+    ) {
+        self.resolve_expression(condition);
+
+        self.resolve_statement(then_branch);
+
+        if let Some(else_branch) = opt_else_branch {
+            self.resolve_statement(else_branch);
+        }
+    }
+
+    fn visit_import(&mut self, _keyword: &Token, _path: &str) {}
+
+    fn visit_print(&mut self, _keyword: &Token, expr: &Expr) {
+        self.resolve_expression(expr);
+    }
+
+    fn visit_return(&mut self, keyword: &Token, value: &Expr) {
+        if let FunctionType::None = self.current_function {
+            self.diagnostics
+                .token_error(keyword, "Can't return from top-level code.")
+        }
+
+        if !value.is_nil() {
+            if let FunctionType::Initializer = self.current_function {
+                self.diagnostics
+                    .token_error(keyword, "Can't return a value from an initializer.");
             }
+
+            self.resolve_expression(value);
         }
     }
 
-    fn resolve_function(&mut self, params: &[Token], body: &[Stmt], function_type: FunctionType) {
-        let enclosing_function = mem::replace(&mut self.current_function, function_type);
+    fn visit_switch(
+        &mut self,
+        _keyword: &Token,
+        value: &Expr,
+        cases: &[(Expr, Vec<Stmt>)],
+        opt_default: Option<&[Stmt]>,
+    ) {
+        self.resolve_expression(value);
 
-        self.begin_scope();
+        for (case_value, body) in cases {
+            self.resolve_expression(case_value);
 
-        for param in params {
-            self.declare(param);
-            self.define(param);
+            self.begin_scope();
+
+            self.resolve(body);
+
+            self.end_scope();
         }
 
+        if let Some(body) = opt_default {
+            self.begin_scope();
+
+            self.resolve(body);
+
+            self.end_scope();
+        }
+    }
+
+    fn visit_throw(&mut self, _keyword: &Token, value: &Expr) {
+        self.resolve_expression(value);
+    }
+
+    fn visit_try(
+        &mut self,
+        _keyword: &Token,
+        body: &[Stmt],
+        opt_catch: Option<(&Token, &[Stmt])>,
+        opt_finally: Option<&[Stmt]>,
+    ) {
+        self.begin_scope();
+
         self.resolve(body);
 
         self.end_scope();
 
-        self.current_function = enclosing_function;
+        if let Some((name, catch_body)) = opt_catch {
+            self.begin_scope();
+
+            self.declare(name, DefinitionKind::Variable);
+            self.define(name);
+            self.mark_used(name);
+
+            self.resolve(catch_body);
+
+            self.end_scope();
+        }
+
+        if let Some(finally_body) = opt_finally {
+            self.begin_scope();
+
+            self.resolve(finally_body);
+
+            self.end_scope();
+        }
+    }
+
+    fn visit_var(&mut self, name: &Token, initializer: &Expr, is_const: bool) {
+        let kind = if is_const {
+            DefinitionKind::Constant
+        } else {
+            DefinitionKind::Variable
+        };
+
+        self.declare(name, kind);
+
+        if is_const {
+            match self.scopes.last_mut() {
+                Some(scope) => {
+                    if let Some(variable) = scope.get_mut(&name.lexeme) {
+                        variable.is_const = true;
+                    }
+                }
+                None => {
+                    self.global_constants.insert(Shared::clone(&name.lexeme));
+                }
+            }
+        }
+
+        if !initializer.is_nil() {
+            self.resolve_expression(initializer);
+        }
+
+        self.define(name);
+    }
+
+    fn visit_while(&mut self, _keyword: &Token, condition: &Expr, body: &Stmt) {
+        self.resolve_expression(condition);
+
+        self.resolve_statement(body);
+    }
+}
+
+impl ExprVisitor<()> for Resolver<'_> {
+    fn visit_assign(&mut self, id: ExprId, name: &Token, value: &Expr) {
+        self.resolve_expression(value);
+
+        self.resolve_assignment(id, name);
+    }
+
+    fn visit_binary(&mut self, left: &Expr, _operator: &Token, right: &Expr) {
+        self.resolve_expression(left);
+        self.resolve_expression(right);
+    }
+
+    fn visit_call(&mut self, callee: &Expr, _paren: &Token, arguments: &[Expr]) {
+        self.resolve_expression(callee);
+
+        for arg in arguments {
+            self.resolve_expression(arg);
+        }
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &Token, _is_optional: bool) {
+        self.check_private_access(name);
+
+        self.resolve_expression(object);
+    }
+
+    fn visit_grouping(&mut self, group: &Expr) {
+        self.resolve_expression(group);
+    }
+
+    fn visit_literal(&mut self, _value: &LoxType) {}
+
+    fn visit_logical(&mut self, left: &Expr, _operator: &Token, right: &Expr) {
+        self.resolve_expression(left);
+        self.resolve_expression(right);
+    }
+
+    fn visit_optional_chain(&mut self, chain: &Expr) {
+        self.resolve_expression(chain);
+    }
+
+    fn visit_set(&mut self, object: &Expr, name: &Token, value: &Expr) {
+        self.check_private_access(name);
+
+        self.resolve_expression(value);
+        self.resolve_expression(object);
+    }
+
+    fn visit_spread(&mut self, _ellipsis: &Token, value: &Expr) {
+        self.resolve_expression(value);
+    }
+
+    fn visit_super(&mut self, id: ExprId, keyword: &Token, _method: &Token) {
+        match self.current_class {
+            ClassType::None => {
+                self.diagnostics
+                    .token_error(keyword, "Can't use 'super' outside of a class.");
+            }
+            ClassType::Class => {
+                self.diagnostics
+                    .token_error(keyword, "Can't use 'super' in a class with no superclass.");
+            }
+            ClassType::SubClass => (),
+        };
+
+        self.resolve_local(id, keyword);
+    }
+
+    fn visit_this(&mut self, id: ExprId, keyword: &Token) {
+        if let ClassType::None = self.current_class {
+            self.diagnostics
+                .token_error(keyword, "Can't use 'this' outside of a class.");
+        } else {
+            self.resolve_local(id, keyword);
+        }
+    }
+
+    fn visit_tuple(&mut self, elements: &[Expr]) {
+        for element in elements {
+            self.resolve_expression(element);
+        }
+    }
+
+    fn visit_unary(&mut self, _operator: &Token, right: &Expr) {
+        self.resolve_expression(right);
+    }
+
+    fn visit_variable(&mut self, id: ExprId, name: &Token) {
+        if let Some(scope) = self.scopes.last() {
+            if let Some(variable) = scope.get(&name.lexeme) {
+                if !variable.defined {
+                    self.diagnostics
+                        .token_error(name, "Can't read local variable in its own initializer.");
+                }
+            }
+        }
+
+        self.mark_used(name);
+
+        self.record_reference(name);
+
+        self.resolve_local(id, name);
     }
 }
@@ -1,61 +1,158 @@
-use std::{clone::Clone, collections::HashMap, iter::Peekable, str::Chars};
+use std::{clone::Clone, collections::HashMap, fmt, mem};
+
+use crate::{
+    error::Diagnostics,
+    interner::Interner,
+    lox_type::LoxType,
+    shared::Shared,
+    token::{Comment, Token},
+    token_type::TokenType,
+};
+
+/// Keywords added on top of the book's grammar. The parser only treats them
+/// as keywords where they start their construct: `case` and `default` inside
+/// a switch body, `catch` and `finally` after a try block, `is` after an
+/// operand, and the rest at the start of a statement unless the next token
+/// can only follow an operand, as in `try = 1;`. Everywhere else they're
+/// identifiers. A statement can still not start with a call like `switch(x);`
+/// or `throw (x);`; compat mode scans them all as plain identifiers.
+pub const EXTENSION_KEYWORDS: &[&str] = &[
+    "case", "catch", "const", "default", "do", "finally", "is", "switch", "throw", "try",
+];
+
+const KEYWORDS: &[(&str, TokenType)] = &[
+    ("and", TokenType::And),
+    ("case", TokenType::Case),
+    ("catch", TokenType::Catch),
+    ("class", TokenType::Class),
+    ("const", TokenType::Const),
+    ("default", TokenType::Default),
+    ("do", TokenType::Do),
+    ("else", TokenType::Else),
+    ("false", TokenType::False),
+    ("finally", TokenType::Finally),
+    ("for", TokenType::For),
+    ("fun", TokenType::Fun),
+    ("if", TokenType::If),
+    ("is", TokenType::Is),
+    ("nil", TokenType::Nil),
+    ("or", TokenType::Or),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("super", TokenType::Super),
+    ("switch", TokenType::Switch),
+    ("this", TokenType::This),
+    ("throw", TokenType::Throw),
+    ("true", TokenType::True),
+    ("try", TokenType::Try),
+    ("var", TokenType::Var),
+    ("while", TokenType::While),
+];
+
+/// Every reserved word, including the extension keywords.
+pub fn keywords() -> impl Iterator<Item = &'static str> {
+    KEYWORDS.iter().map(|&(keyword, _)| keyword)
+}
+
+/// A character the scanner couldn't make a token out of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanError {
+    pub line: usize,
+    pub message: String,
+}
 
-use crate::{lox, lox_type::LoxType, token::Token, token_type::TokenType};
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error: {}", self.line, self.message)
+    }
+}
 
+/// Turns source into tokens on demand, ending with a single `Eof` token.
+/// Scanning carries on past errors, so every error in the source is seen.
 pub struct Scanner<'a> {
-    source: String,
-    chars: Peekable<Chars<'a>>,
-    tokens: Vec<Token>,
+    source: &'a str,
+    /// The token or error produced by the last `scan_token`, if any.
+    opt_scanned: Option<Result<Token, ScanError>>,
+    is_finished: bool,
     keywords: HashMap<&'a str, TokenType>,
     start: usize,
+    /// Byte offset of the next character, like `start`.
     current: usize,
     line: usize,
+    /// Column of `current`, in characters.
+    column: usize,
+    /// Column of the token being scanned, in characters.
+    start_column: usize,
+    /// Open `{` counts for each `${` being scanned, innermost last.
+    interpolations: Vec<usize>,
+    interpolate: bool,
+    /// Whether numbers without a decimal point scan as integers.
+    integers: bool,
+    interner: Interner,
+    /// Comments waiting to be attached to the next token.
+    comments: Vec<Comment>,
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Self {
-        let mut keywords = HashMap::new();
-
-        keywords.insert("and", TokenType::And);
-        keywords.insert("class", TokenType::Class);
-        keywords.insert("else", TokenType::Else);
-        keywords.insert("false", TokenType::False);
-        keywords.insert("for", TokenType::For);
-        keywords.insert("fun", TokenType::Fun);
-        keywords.insert("if", TokenType::If);
-        keywords.insert("nil", TokenType::Nil);
-        keywords.insert("or", TokenType::Or);
-        keywords.insert("print", TokenType::Print);
-        keywords.insert("return", TokenType::Return);
-        keywords.insert("super", TokenType::Super);
-        keywords.insert("this", TokenType::This);
-        keywords.insert("true", TokenType::True);
-        keywords.insert("var", TokenType::Var);
-        keywords.insert("while", TokenType::While);
+        let keywords = KEYWORDS.iter().cloned().collect();
 
         Self {
-            source: source.to_string(),
-            chars: source.chars().peekable(),
-            tokens: Vec::new(),
+            source,
+            opt_scanned: None,
+            is_finished: false,
             keywords,
             start: 0,
             current: 0,
             line: 1,
+            column: 0,
+            start_column: 0,
+            interpolations: Vec::new(),
+            interpolate: true,
+            integers: true,
+            interner: Interner::default(),
+            comments: Vec::new(),
         }
     }
 
-    pub fn scan_tokens(&mut self) -> Vec<Token> {
-        while !self.is_at_end() {
-            self.start = self.current;
+    pub fn compat(mut self, compat: bool) -> Self {
+        if compat {
+            for keyword in EXTENSION_KEYWORDS {
+                self.keywords.remove(keyword);
+            }
 
-            self.scan_token();
+            self.interpolate = false;
+            self.integers = false;
         }
 
-        let end_token = Token::new(TokenType::Eof, String::new(), None, self.line);
+        self
+    }
 
-        self.tokens.push(end_token);
+    /// Starts scanning at byte `offset`, which is on `line`, instead of at the
+    /// start of the source. `offset` must fall between two tokens.
+    pub fn resume_at(mut self, offset: usize, line: usize) -> Self {
+        self.start = offset;
+        self.current = offset;
+        self.line = line;
 
-        self.tokens.clone()
+        let line_start = self.source[..offset].rfind('\n').map_or(0, |i| i + 1);
+
+        self.column = self.source[line_start..offset].chars().count();
+
+        self
+    }
+
+    /// Scans every token up front, reporting errors to `diagnostics`.
+    pub fn scan_tokens(self, diagnostics: &mut Diagnostics) -> Vec<Token> {
+        self.filter_map(|scanned| match scanned {
+            Ok(token) => Some(token),
+            Err(err) => {
+                diagnostics.error(err.line, &err.message);
+
+                None
+            }
+        })
+        .collect()
     }
 
     fn scan_token(&mut self) {
@@ -64,14 +161,49 @@ impl<'a> Scanner<'a> {
         match c {
             '(' => self.add_token(TokenType::LeftParen),
             ')' => self.add_token(TokenType::RightParen),
-            '{' => self.add_token(TokenType::LeftBrace),
-            '}' => self.add_token(TokenType::RightBrace),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+
+                self.add_token(TokenType::LeftBrace);
+            }
+            '}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    self.interpolations.pop();
+
+                    self.string();
+                }
+                Some(depth) => {
+                    *depth -= 1;
+
+                    self.add_token(TokenType::RightBrace);
+                }
+                None => self.add_token(TokenType::RightBrace),
+            },
             ',' => self.add_token(TokenType::Comma),
-            '.' => self.add_token(TokenType::Dot),
+            ':' => self.add_token(TokenType::Colon),
+            '.' => {
+                let token_type = if self.matches('.') {
+                    if self.matches('.') {
+                        TokenType::Ellipsis
+                    } else {
+                        TokenType::DotDot
+                    }
+                } else {
+                    TokenType::Dot
+                };
+
+                self.add_token(token_type);
+            }
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::SemiColon),
             '*' => self.add_token(TokenType::Star),
+            '&' => self.add_token(TokenType::Ampersand),
+            '|' => self.add_token(TokenType::Pipe),
+            '^' => self.add_token(TokenType::Caret),
+            '~' => self.add_token(TokenType::Tilde),
             '!' => {
                 let token_type = if self.matches('=') {
                     TokenType::BangEqual
@@ -93,6 +225,8 @@ impl<'a> Scanner<'a> {
             '<' => {
                 let token_type = if self.matches('=') {
                     TokenType::LessEqual
+                } else if self.matches('<') {
+                    TokenType::LessLess
                 } else {
                     TokenType::Less
                 };
@@ -102,17 +236,33 @@ impl<'a> Scanner<'a> {
             '>' => {
                 let token_type = if self.matches('=') {
                     TokenType::GreaterEqual
+                } else if self.matches('>') {
+                    TokenType::GreaterGreater
                 } else {
                     TokenType::Greater
                 };
 
                 self.add_token(token_type);
             }
+            '?' => {
+                if self.matches('.') {
+                    self.add_token(TokenType::QuestionDot);
+                } else if self.matches('?') {
+                    self.add_token(TokenType::QuestionQuestion);
+                } else {
+                    self.error("Unexpected character -> ? <-");
+                }
+            }
             '/' => {
                 if self.matches('/') {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+
+                    self.comments.push(Comment {
+                        text: Shared::from(self.source[self.start..self.current].trim_end()),
+                        line: self.line,
+                    });
                 } else {
                     self.add_token(TokenType::Slash);
                 }
@@ -121,12 +271,12 @@ impl<'a> Scanner<'a> {
             '\n' => self.increment_line(),
             '"' => self.string(),
             _ => {
-                if c.is_digit(10) {
+                if c.is_ascii_digit() {
                     self.number();
                 } else if is_alpha(c) {
                     self.indentifier();
                 } else {
-                    lox::error(self.line, &format!("Unexpected character -> {} <-", c));
+                    self.error(&format!("Unexpected character -> {} <-", c));
                 }
             }
         }
@@ -148,34 +298,65 @@ impl<'a> Scanner<'a> {
     }
 
     fn number(&mut self) {
-        while self.peek().is_digit(10) {
+        while self.peek().is_ascii_digit() {
             self.advance();
         }
 
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        let mut is_float = !self.integers;
+
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             self.advance();
 
-            while self.peek().is_digit(10) {
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
+
+            is_float = true;
         }
 
-        let value: f64 = self.source[self.start..self.current].parse().unwrap();
+        let text = &self.source[self.start..self.current];
+
+        // Integers too large for 64 bits become floats.
+        let value = match text.parse() {
+            Ok(n) if !is_float => LoxType::Integer(n),
+            _ => LoxType::Number(text.parse().unwrap()),
+        };
 
-        self.add_token_with_literal(TokenType::Number, Some(LoxType::Number(value)));
+        self.add_token_with_literal(TokenType::Number, Some(value));
     }
 
+    /// Scans a string segment, starting after its opening `"` or after the
+    /// `}` closing an interpolated expression.
     fn string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.increment_line();
+            if self.interpolate
+                && self
+                    .source
+                    .get(self.current..)
+                    .is_some_and(|rest| rest.starts_with("${"))
+            {
+                let value = self.source[(self.start + 1)..self.current].to_string();
+
+                self.advance();
+                self.advance();
+
+                self.add_token_with_literal(
+                    TokenType::Interpolation,
+                    Some(LoxType::String(value.into())),
+                );
+
+                self.interpolations.push(0);
+
+                return;
             }
 
-            self.advance();
+            if self.advance() == '\n' {
+                self.increment_line();
+            }
         }
 
         if self.is_at_end() {
-            lox::error(self.line, "Unterminated string.");
+            self.error("Unterminated string.");
 
             return;
         }
@@ -184,7 +365,7 @@ impl<'a> Scanner<'a> {
 
         let value = self.source[(self.start + 1)..(self.current - 1)].to_string();
 
-        self.add_token_with_literal(TokenType::String, Some(LoxType::String(value)));
+        self.add_token_with_literal(TokenType::String, Some(LoxType::String(value.into())));
     }
 
     fn matches(&mut self, expected: char) -> bool {
@@ -197,20 +378,21 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    fn peek(&mut self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            *self.chars.peek().unwrap()
-        }
+    /// The character at `current`, or `'\0'` at the end of the source.
+    fn peek(&self) -> char {
+        self.lookahead(0)
     }
 
-    fn peek_next(&mut self) -> char {
-        if self.current + 1 >= self.source.len() {
-            '\0'
-        } else {
-            self.chars.nth(self.current + 1).unwrap()
-        }
+    /// The character after `peek`'s, or `'\0'` past the end of the source.
+    fn peek_next(&self) -> char {
+        self.lookahead(1)
+    }
+
+    fn lookahead(&self, n: usize) -> char {
+        self.source
+            .get(self.current..)
+            .and_then(|rest| rest.chars().nth(n))
+            .unwrap_or('\0')
     }
 
     fn is_at_end(&self) -> bool {
@@ -218,9 +400,12 @@ impl<'a> Scanner<'a> {
     }
 
     fn advance(&mut self) -> char {
-        self.current += 1;
+        let c = self.peek();
+
+        self.current += c.len_utf8();
+        self.column += 1;
 
-        self.chars.next().unwrap()
+        c
     }
 
     fn add_token(&mut self, token_type: TokenType) {
@@ -228,24 +413,69 @@ impl<'a> Scanner<'a> {
     }
 
     fn add_token_with_literal(&mut self, token_type: TokenType, literal: Option<LoxType>) {
-        let lexeme = self.source[self.start..self.current].to_string();
-        let token = Token::new(token_type, lexeme, literal, self.line);
+        let lexeme = self.interner.intern(&self.source[self.start..self.current]);
+        let mut token = Token::new(token_type, lexeme, literal, self.line, self.start_column);
+
+        token.comments = mem::take(&mut self.comments);
+
+        self.opt_scanned = Some(Ok(token));
+    }
 
-        self.tokens.push(token);
+    fn error(&mut self, message: &str) {
+        self.opt_scanned = Some(Err(ScanError {
+            line: self.line,
+            message: message.to_string(),
+        }));
     }
 
+    /// Moves on to the next line, after its `\n` has been consumed.
     fn increment_line(&mut self) {
         self.line += 1;
+        self.column = 0;
     }
 }
 
-fn is_alpha(c: char) -> bool {
-    match c {
-        'a'..='z' | 'A'..='Z' | '_' => true,
-        _ => false,
+impl<'a> Iterator for Scanner<'a> {
+    type Item = Result<Token, ScanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_finished {
+            return None;
+        }
+
+        while !self.is_at_end() {
+            self.start = self.current;
+            self.start_column = self.column;
+
+            self.scan_token();
+
+            if let Some(scanned) = self.opt_scanned.take() {
+                return Some(scanned);
+            }
+        }
+
+        self.is_finished = true;
+
+        let mut end_token = Token::new(
+            TokenType::Eof,
+            Shared::from(""),
+            None,
+            self.line,
+            self.column,
+        );
+
+        end_token.comments = mem::take(&mut self.comments);
+
+        Some(Ok(end_token))
     }
 }
 
-fn is_alpha_numberic(c: char) -> bool {
-    is_alpha(c) || c.is_digit(10)
+/// Identifiers start with a letter from any script or an underscore.
+fn is_alpha(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+/// Whether `c` can continue an identifier.
+pub(crate) fn is_alpha_numberic(c: char) -> bool {
+    is_alpha(c) || c.is_ascii_digit()
 }
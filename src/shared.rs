@@ -0,0 +1,71 @@
+//! The pointers values are built from. By default these are `Rc` and
+//! `RefCell`. With the `sync` feature they're `Arc` and a `RwLock`, so an
+//! interpreter and its values can move between threads.
+
+#[cfg(not(feature = "sync"))]
+pub use std::{cell::RefCell as Locked, rc::Weak};
+
+#[cfg(feature = "sync")]
+pub use std::sync::Weak;
+
+#[cfg(feature = "sync")]
+use std::{
+    fmt,
+    sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+/// A reference counted pointer.
+#[cfg(not(feature = "sync"))]
+pub type Shared<T> = std::rc::Rc<T>;
+
+/// A reference counted pointer.
+#[cfg(feature = "sync")]
+pub type Shared<T> = std::sync::Arc<T>;
+
+/// Bounds that values shared with the interpreter need: nothing by default,
+/// `Send` and `Sync` with the `sync` feature.
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSync {}
+
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> MaybeSync for T {}
+
+/// Bounds that values shared with the interpreter need: nothing by default,
+/// `Send` and `Sync` with the `sync` feature.
+#[cfg(feature = "sync")]
+pub trait MaybeSync: Send + Sync {}
+
+#[cfg(feature = "sync")]
+impl<T: ?Sized + Send + Sync> MaybeSync for T {}
+
+/// A `RefCell` that can be shared between threads. Borrowing waits for a
+/// conflicting borrow on another thread to end, so a conflicting borrow on
+/// the same thread deadlocks where a `RefCell` would panic.
+#[cfg(feature = "sync")]
+#[derive(Default)]
+pub struct Locked<T: ?Sized>(RwLock<T>);
+
+#[cfg(feature = "sync")]
+impl<T> Locked<T> {
+    pub fn new(value: T) -> Self {
+        Self(RwLock::new(value))
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<T: ?Sized> Locked<T> {
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Locked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
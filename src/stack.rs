@@ -0,0 +1,44 @@
+//! How much of the thread's stack is in use, so the recursive passes over a
+//! program can fail with an error before they overflow it.
+
+/// Stack kept free below the deepest frame that checks, which the frames
+/// between two checks and whatever ran before the pass can use up.
+const STACK_MARGIN: usize = 256 * 1024;
+
+/// Measures stack use from where a pass started.
+#[derive(Debug, Clone, Copy)]
+pub struct StackGuard {
+    base: usize,
+    /// Bytes of stack the thread has, from `Limits::stack_size`.
+    pub size: usize,
+}
+
+impl StackGuard {
+    /// Starts measuring from the caller's frame.
+    pub fn new(size: usize) -> Self {
+        Self {
+            base: stack_address(),
+            size,
+        }
+    }
+
+    /// Measures from the caller's frame from now on.
+    pub fn reset(&mut self) {
+        self.base = stack_address();
+    }
+
+    /// Whether the stack has grown so close to `size` that going deeper
+    /// could overflow it.
+    pub fn is_exhausted(&self) -> bool {
+        self.base.abs_diff(stack_address()) + STACK_MARGIN > self.size
+    }
+}
+
+/// The address of a local in a frame of its own, which tells how deep the
+/// caller is in the stack.
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+
+    &marker as *const u8 as usize
+}
@@ -0,0 +1,33 @@
+use std::{fmt, time::Duration};
+
+use crate::{coverage::Coverage, profile::Profile};
+
+/// Counters describing the work done by a run, reported by `--stats` and
+/// `rlox bench`.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub statements: usize,
+    pub calls: usize,
+    pub environments: usize,
+    /// Environments and instances freed by breaking reference cycles.
+    pub collected: usize,
+    pub elapsed: Duration,
+    /// Time per function, if the run was profiled.
+    pub opt_profile: Option<Profile>,
+    /// Lines run per file, if coverage was tracked.
+    pub opt_coverage: Option<Coverage>,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "statements executed:  {}", self.statements)?;
+        writeln!(f, "function calls:       {}", self.calls)?;
+        writeln!(f, "environments created: {}", self.environments)?;
+        writeln!(f, "objects collected:    {}", self.collected)?;
+        write!(f, "wall time:            {}", format_duration(self.elapsed))
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
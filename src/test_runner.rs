@@ -0,0 +1,199 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::LoxError,
+    lox::{self, Capture, Options},
+};
+
+#[derive(Debug, Default)]
+pub struct TestSummary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// What a test script expects, read from comments in the format used by the
+/// Crafting Interpreters test suite:
+///
+/// ```lox
+/// print 1 + 2; // expect: 3
+/// var a = ; // Error at ';': Expect expression.
+/// nil(); // expect runtime error: Can only call functions and classes.
+/// ```
+#[derive(Debug, Default, PartialEq)]
+struct Expectations {
+    output: Vec<String>,
+    compile_errors: Vec<String>,
+    runtime_error: Option<String>,
+}
+
+impl Expectations {
+    fn parse(src: &str) -> Self {
+        let mut expectations = Self::default();
+
+        for (index, line) in src.lines().enumerate() {
+            let comment = match line.find("// ") {
+                Some(start) => &line[start + 3..],
+                None => continue,
+            };
+
+            if let Some(output) = comment.strip_prefix("expect: ") {
+                expectations.output.push(output.to_string());
+            } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+                expectations.runtime_error = Some(message.to_string());
+            } else if comment.starts_with("[line ") {
+                expectations.compile_errors.push(comment.to_string());
+            } else if comment.starts_with("Error") {
+                expectations
+                    .compile_errors
+                    .push(format!("[line {}] {}", index + 1, comment));
+            }
+        }
+
+        expectations
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Lists every `.lox` file under `dir`, sorted by path.
+pub fn discover(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    collect_lox_files(dir, &mut files);
+
+    files.sort();
+
+    files
+}
+
+fn collect_lox_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if path.is_dir() {
+                collect_lox_files(&path, files);
+            } else if path.extension().is_some_and(|ext| ext == "lox") {
+                files.push(path);
+            }
+        }
+    }
+}
+
+/// Runs each script in `files` and compares its output and errors with the
+/// expectations written in its comments. A script without expectations
+/// passes when it finishes without errors.
+pub fn run_tests(files: &[PathBuf], options: &Options) -> TestSummary {
+    let mut summary = TestSummary::default();
+
+    for file in files {
+        match run_test(file, options) {
+            Ok(()) => {
+                println!("PASS {}", file.display());
+
+                summary.passed += 1;
+            }
+            Err(failures) => {
+                println!("FAIL {}", file.display());
+
+                for failure in failures {
+                    println!("    {}", failure.replace('\n', "\n    "));
+                }
+
+                summary.failed += 1;
+            }
+        }
+    }
+
+    println!("\n{} passed, {} failed", summary.passed, summary.failed);
+
+    summary
+}
+
+fn run_test(file: &Path, options: &Options) -> Result<(), Vec<String>> {
+    let src = fs::read_to_string(file).map_err(|err| vec![err.to_string()])?;
+    let expectations = Expectations::parse(&src);
+
+    let capture = Capture::default();
+
+    let diagnostics = lox::run_script_with_output(file, &[], options, Box::new(capture.clone()))
+        .map_err(|err| vec![err.to_string()])?;
+
+    let output = capture.contents();
+
+    let mut failures = Vec::new();
+
+    if expectations.is_empty() {
+        for err in diagnostics.errors() {
+            failures.push(err.to_string());
+        }
+
+        return if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        };
+    }
+
+    let actual_output: Vec<&str> = output.lines().collect();
+
+    for (index, expected) in expectations.output.iter().enumerate() {
+        match actual_output.get(index) {
+            Some(actual) if actual == expected => (),
+            Some(actual) => failures.push(format!(
+                "Expected output '{}' but got '{}'.",
+                expected, actual
+            )),
+            None => failures.push(format!("Missing expected output '{}'.", expected)),
+        }
+    }
+
+    for extra in actual_output.iter().skip(expectations.output.len()) {
+        failures.push(format!("Got unexpected output '{}'.", extra));
+    }
+
+    let mut compile_errors = Vec::new();
+    let mut runtime_error = None;
+
+    for err in diagnostics.errors() {
+        match err {
+            LoxError::Compile { .. } => compile_errors.push(err.to_string()),
+            LoxError::Runtime { message, .. } => runtime_error = Some(message.clone()),
+        }
+    }
+
+    if compile_errors != expectations.compile_errors {
+        for expected in &expectations.compile_errors {
+            if !compile_errors.contains(expected) {
+                failures.push(format!("Missing expected error: {}", expected));
+            }
+        }
+
+        for actual in &compile_errors {
+            if !expectations.compile_errors.contains(actual) {
+                failures.push(format!("Unexpected error: {}", actual));
+            }
+        }
+    }
+
+    match (&expectations.runtime_error, &runtime_error) {
+        (Some(expected), Some(actual)) if expected != actual => failures.push(format!(
+            "Expected runtime error '{}' but got '{}'.",
+            expected, actual
+        )),
+        (Some(expected), None) => failures.push(format!("Expected runtime error '{}'.", expected)),
+        (None, Some(actual)) => failures.push(format!("Unexpected runtime error '{}'.", actual)),
+        _ => (),
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
@@ -3,28 +3,43 @@ use std::{
     hash::{Hash, Hasher},
 };
 
-use crate::{lox_type::LoxType, token_type::TokenType};
+use crate::{lox_type::LoxType, shared::Shared, token_type::TokenType};
+
+/// A `//` comment, kept as trivia on the token that follows it so tools like
+/// the formatter can reproduce it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: Shared<str>,
+    pub line: usize,
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Shared<str>,
     pub literal: Option<LoxType>,
     pub line: usize,
+    /// Characters between the start of the line and the start of the token.
+    pub column: usize,
+    /// Comments between the previous token and this one, in source order.
+    pub comments: Vec<Comment>,
 }
 
 impl Token {
     pub fn new(
         token_type: TokenType,
-        lexeme: String,
+        lexeme: Shared<str>,
         literal: Option<LoxType>,
         line: usize,
+        column: usize,
     ) -> Self {
         Self {
             token_type,
             lexeme,
             literal,
             line,
+            column,
+            comments: Vec::new(),
         }
     }
 }
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+
+use crate::{token::Token, token_type::TokenType};
+
+/// A transformation applied to the scanned token stream before it reaches
+/// the parser. Filters run in order, each one receiving the output of the
+/// previous filter.
+pub trait TokenFilter {
+    fn filter(&mut self, tokens: Vec<Token>) -> Vec<Token>;
+}
+
+/// Rewrites identifiers into keywords, e.g. letting scripts write `let`
+/// instead of `var` or `fn` instead of `fun`.
+#[derive(Debug, Default)]
+pub struct KeywordAliases {
+    aliases: HashMap<String, TokenType>,
+}
+
+impl KeywordAliases {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn alias(mut self, name: &str, token_type: TokenType) -> Self {
+        self.aliases.insert(name.to_string(), token_type);
+
+        self
+    }
+}
+
+impl TokenFilter for KeywordAliases {
+    fn filter(&mut self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .map(|mut token| {
+                if token.token_type == TokenType::Identifier {
+                    if let Some(token_type) = self.aliases.get(&*token.lexeme) {
+                        token.token_type = *token_type;
+                    }
+                }
+
+                token
+            })
+            .collect()
+    }
+}
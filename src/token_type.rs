@@ -1,10 +1,11 @@
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum TokenType {
     // Single-character tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -12,39 +13,167 @@ pub enum TokenType {
     SemiColon,
     Slash,
     Star,
+    Ampersand,
+    Caret,
+    Pipe,
+    Tilde,
 
     // One or two character tokens.
     Bang,
     BangEqual,
+    DotDot,
+    Ellipsis,
     Equal,
     EqualEqual,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
+    /// `?.`, which reads a property unless the object is nil.
+    QuestionDot,
+    /// `??`, which picks its right operand when the left one is nil.
+    QuestionQuestion,
 
     // Literals.
     Identifier,
+    /// A string segment ending in `${`, followed by the tokens of the
+    /// interpolated expression.
+    Interpolation,
     String,
     Number,
 
     // Keywords.
     And,
+    Case,
+    Catch,
     Class,
+    Const,
+    Default,
+    Do,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
+    Is,
     Nil,
     Or,
     Print,
     Return,
     Super,
+    Switch,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
     Eof,
 }
+
+impl TokenType {
+    /// Every token type, in declaration order. Keep it in step with the enum.
+    pub const ALL: &'static [TokenType] = &[
+        TokenType::LeftParen,
+        TokenType::RightParen,
+        TokenType::LeftBrace,
+        TokenType::RightBrace,
+        TokenType::Colon,
+        TokenType::Comma,
+        TokenType::Dot,
+        TokenType::Minus,
+        TokenType::Plus,
+        TokenType::SemiColon,
+        TokenType::Slash,
+        TokenType::Star,
+        TokenType::Ampersand,
+        TokenType::Caret,
+        TokenType::Pipe,
+        TokenType::Tilde,
+        TokenType::Bang,
+        TokenType::BangEqual,
+        TokenType::DotDot,
+        TokenType::Ellipsis,
+        TokenType::Equal,
+        TokenType::EqualEqual,
+        TokenType::Greater,
+        TokenType::GreaterEqual,
+        TokenType::GreaterGreater,
+        TokenType::Less,
+        TokenType::LessEqual,
+        TokenType::LessLess,
+        TokenType::QuestionDot,
+        TokenType::QuestionQuestion,
+        TokenType::Identifier,
+        TokenType::Interpolation,
+        TokenType::String,
+        TokenType::Number,
+        TokenType::And,
+        TokenType::Case,
+        TokenType::Catch,
+        TokenType::Class,
+        TokenType::Const,
+        TokenType::Default,
+        TokenType::Do,
+        TokenType::Else,
+        TokenType::False,
+        TokenType::Finally,
+        TokenType::Fun,
+        TokenType::For,
+        TokenType::If,
+        TokenType::Is,
+        TokenType::Nil,
+        TokenType::Or,
+        TokenType::Print,
+        TokenType::Return,
+        TokenType::Super,
+        TokenType::Switch,
+        TokenType::This,
+        TokenType::Throw,
+        TokenType::True,
+        TokenType::Try,
+        TokenType::Var,
+        TokenType::While,
+        TokenType::Eof,
+    ];
+
+    /// Whether a token of this type can follow an operand but can't start
+    /// an expression, so a word right before it has to be an identifier.
+    pub fn follows_operand(self) -> bool {
+        matches!(
+            self,
+            TokenType::RightParen
+                | TokenType::RightBrace
+                | TokenType::Colon
+                | TokenType::Comma
+                | TokenType::Dot
+                | TokenType::Plus
+                | TokenType::SemiColon
+                | TokenType::Slash
+                | TokenType::Star
+                | TokenType::Ampersand
+                | TokenType::Caret
+                | TokenType::Pipe
+                | TokenType::BangEqual
+                | TokenType::DotDot
+                | TokenType::Equal
+                | TokenType::EqualEqual
+                | TokenType::Greater
+                | TokenType::GreaterEqual
+                | TokenType::GreaterGreater
+                | TokenType::Less
+                | TokenType::LessEqual
+                | TokenType::LessLess
+                | TokenType::QuestionDot
+                | TokenType::QuestionQuestion
+                | TokenType::And
+                | TokenType::Is
+                | TokenType::Or
+                | TokenType::Eof
+        )
+    }
+}
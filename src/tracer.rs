@@ -0,0 +1,84 @@
+use crate::{
+    ast::Stmt,
+    ast_printer::AstPrinter,
+    interpreter::{Hook, Interpreter, InterpreterError},
+    lox_type::LoxType,
+    token::Token,
+};
+
+/// Logs each statement to stderr as it runs, indented by call depth, and
+/// optionally each user function call and return. Installed as an
+/// interpreter hook by `rlox --trace`.
+pub struct Tracer {
+    trace_calls: bool,
+}
+
+impl Tracer {
+    pub fn new(trace_calls: bool) -> Self {
+        Self { trace_calls }
+    }
+
+    fn log_call(&self, interpreter: &Interpreter, kind: &str, name: &Token, arguments: &[LoxType]) {
+        if !self.trace_calls {
+            return;
+        }
+
+        let arguments = arguments
+            .iter()
+            .map(LoxType::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        // The call is already counted in the depth, so step back out.
+        eprintln!(
+            "{}{} {}({})",
+            "  ".repeat(interpreter.call_depth().saturating_sub(1)),
+            kind,
+            name.lexeme,
+            arguments
+        );
+    }
+}
+
+impl Hook for Tracer {
+    fn before_statement(
+        &mut self,
+        interpreter: &Interpreter,
+        stmt: &Stmt,
+    ) -> Result<(), InterpreterError> {
+        if let Stmt::Block(_) = stmt {
+            return Ok(());
+        }
+
+        if let Some(line) = stmt.line() {
+            eprintln!(
+                "{}[line {}] {}",
+                indent(interpreter),
+                line,
+                AstPrinter.summarize_statement(stmt, 72)
+            );
+        }
+
+        Ok(())
+    }
+
+    fn on_call(&mut self, interpreter: &Interpreter, name: &Token, arguments: &[LoxType]) {
+        self.log_call(interpreter, "call", name, arguments);
+    }
+
+    /// A tail call takes over its caller's frame, so no `return` is logged
+    /// for the caller.
+    fn on_tail_call(&mut self, interpreter: &Interpreter, name: &Token, arguments: &[LoxType]) {
+        self.log_call(interpreter, "tail call", name, arguments);
+    }
+
+    fn on_return(&mut self, interpreter: &Interpreter, name: &Token, value: &LoxType) {
+        if self.trace_calls {
+            eprintln!("{}return {} -> {}", indent(interpreter), name.lexeme, value);
+        }
+    }
+}
+
+fn indent(interpreter: &Interpreter) -> String {
+    "  ".repeat(interpreter.call_depth())
+}
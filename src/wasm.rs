@@ -0,0 +1,48 @@
+use wasm_bindgen::prelude::*;
+
+use crate::lox::{self, Options};
+
+/// The result of running a program, for display in a browser playground.
+#[wasm_bindgen]
+pub struct RunResult {
+    output: String,
+    diagnostics: String,
+    success: bool,
+}
+
+#[wasm_bindgen]
+impl RunResult {
+    /// Everything the program printed.
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> String {
+        self.output.clone()
+    }
+
+    /// Warnings and errors, formatted as the command line reports them.
+    #[wasm_bindgen(getter)]
+    pub fn diagnostics(&self) -> String {
+        self.diagnostics.clone()
+    }
+
+    /// Whether the program ran without errors.
+    #[wasm_bindgen(getter)]
+    pub fn success(&self) -> bool {
+        self.success
+    }
+}
+
+/// Runs `source` in a fresh interpreter with its output captured.
+#[wasm_bindgen(js_name = runSource)]
+pub fn run_source(source: &str) -> RunResult {
+    let (output, diagnostics) = lox::run_source_captured(source, &Options::default());
+
+    let mut report = Vec::new();
+
+    lox::report(&diagnostics, &mut report);
+
+    RunResult {
+        output,
+        diagnostics: String::from_utf8_lossy(&report).into_owned(),
+        success: diagnostics.errors().is_empty(),
+    }
+}
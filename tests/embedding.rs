@@ -0,0 +1,309 @@
+use std::convert::TryFrom;
+
+use rlox::{lox, Interpreter, LoxType};
+
+/// Enough cyclic garbage to set off several automatic collections.
+const CHURN: &str = "
+class Node { init() { this.me = this; } }
+for (var i = 0; i < 3000; i = i + 1) { Node(); }
+";
+
+#[test]
+fn host_values_survive_automatic_collection() {
+    let mut interpreter = Interpreter::new();
+
+    let counter = lox::eval_with(
+        "fun make() { var x = 41; fun next() { x = x + 1; return x; } return next; } make()",
+        &mut interpreter,
+    )
+    .ok()
+    .unwrap();
+
+    let point = lox::eval_with(
+        "class P { init() { this.v = 7; this.me = this; } } P()",
+        &mut interpreter,
+    )
+    .ok()
+    .unwrap();
+
+    for _ in 0..3 {
+        assert!(lox::run_with(CHURN, &mut interpreter).errors().is_empty());
+    }
+
+    assert!(interpreter.stats().collected > 0);
+
+    let next = interpreter.call_function(&counter, &[]).ok().unwrap();
+    assert_eq!(i64::try_from(next).ok(), Some(42));
+
+    interpreter.define_global("point", point);
+
+    let v = lox::eval_with("point.v", &mut interpreter).ok().unwrap();
+    assert_eq!(v, LoxType::Integer(7));
+}
+
+#[test]
+fn eval_result_survives_forced_collection() {
+    let mut interpreter = Interpreter::new();
+
+    let pair = lox::eval_with(
+        "class Box { init(v) { this.v = v; this.me = this; } }
+         fun make() { var hidden = Box(5); fun get() { return hidden.v; } return get, Box(9); }
+         make()",
+        &mut interpreter,
+    )
+    .ok()
+    .unwrap();
+
+    interpreter.collect_garbage();
+
+    interpreter.define_global("pair", pair);
+
+    let (get, boxed) = match lox::eval_with(
+        "fun read() { var (get, boxed) = pair; return get(), boxed.v; } read()",
+        &mut interpreter,
+    ) {
+        Ok(LoxType::Tuple(values)) => (values[0].clone(), values[1].clone()),
+        _ => panic!("expected a tuple"),
+    };
+
+    assert_eq!(get, LoxType::Integer(5));
+    assert_eq!(boxed, LoxType::Integer(9));
+}
+
+#[test]
+fn call_function_after_collection() {
+    let mut interpreter = Interpreter::new();
+
+    let adder = lox::eval_with(
+        "fun make(n) { fun add(x) { return x + n; } return add; } make(10)",
+        &mut interpreter,
+    )
+    .ok()
+    .unwrap();
+
+    let class = lox::eval_with(
+        "fun define() { var start = 3; class Counter { init() { this.n = start; } } return Counter; } define()",
+        &mut interpreter,
+    )
+    .ok()
+    .unwrap();
+
+    assert!(lox::run_with(CHURN, &mut interpreter).errors().is_empty());
+
+    interpreter.collect_garbage();
+
+    let sum = interpreter
+        .call_function(&adder, &[LoxType::Integer(5)])
+        .ok()
+        .unwrap();
+    assert_eq!(sum, LoxType::Integer(15));
+
+    let counter = interpreter.call_function(&class, &[]).ok().unwrap();
+
+    interpreter.define_global("counter", counter);
+
+    let n = lox::eval_with("counter.n", &mut interpreter).ok().unwrap();
+    assert_eq!(n, LoxType::Integer(3));
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn interpreters_and_values_move_between_threads() {
+    fn assert_send<T: Send>() {}
+
+    assert_send::<Interpreter>();
+    assert_send::<LoxType>();
+
+    let mut interpreter = Interpreter::new();
+
+    let greet = lox::eval_with(
+        "class Greeter { init(name) { this.name = name; } greet() { return \"hi \" + this.name; } }
+         Greeter(\"lox\").greet",
+        &mut interpreter,
+    )
+    .ok()
+    .unwrap();
+
+    let greeting = std::thread::spawn(move || interpreter.call_function(&greet, &[]).ok())
+        .join()
+        .unwrap();
+
+    assert_eq!(greeting, Some(LoxType::from("hi lox")));
+}
+
+#[test]
+fn deep_recursion_is_catchable_on_a_default_thread() {
+    let (output, diagnostics) = lox::run_source_captured(
+        "fun g(n) { return 1 + g(n + 1); } try { g(0); } catch (e) { print e; }",
+        &lox::Options::default(),
+    );
+
+    assert_eq!(output, "Stack overflow.\n");
+    assert!(diagnostics.errors().is_empty());
+}
+
+fn error_messages(diagnostics: &rlox::error::Diagnostics) -> Vec<String> {
+    diagnostics.errors().iter().map(|e| e.to_string()).collect()
+}
+
+#[test]
+fn deep_parentheses_are_an_error_on_a_default_thread() {
+    let src = format!("print {}1{};", "(".repeat(150), ")".repeat(150));
+
+    let expected = vec!["[line 1] Error at '(': Too much nesting.".to_string()];
+
+    let (_, diagnostics) = lox::parse(&src, &lox::Options::default());
+
+    assert_eq!(error_messages(&diagnostics), expected);
+
+    let diagnostics = lox::check(&src, &lox::Options::default());
+
+    assert_eq!(error_messages(&diagnostics), expected);
+
+    let (output, diagnostics) = lox::run_source_captured(&src, &lox::Options::default());
+
+    assert_eq!(output, "");
+    assert_eq!(error_messages(&diagnostics), expected);
+}
+
+#[test]
+fn long_chains_are_an_error_on_a_default_thread() {
+    let src = format!("print 1{};", " + 1".repeat(5000));
+
+    let (output, diagnostics) = lox::run_source_captured(&src, &lox::Options::default());
+
+    assert_eq!(output, "");
+    assert_eq!(
+        error_messages(&diagnostics),
+        vec!["[line 1] Error at '+': Too much nesting.".to_string()]
+    );
+}
+
+#[test]
+fn chains_within_the_limit_still_run() {
+    let src = format!("print 1{};", " + 1".repeat(99));
+
+    let (output, diagnostics) = lox::run_source_captured(&src, &lox::Options::default());
+
+    assert_eq!(output, "100\n");
+    assert!(diagnostics.errors().is_empty());
+}
+
+#[test]
+fn interrupt_while_idle_does_not_stop_the_next_run() {
+    let mut interpreter = Interpreter::new();
+
+    interpreter.interrupt_handle().interrupt();
+
+    let sum = lox::eval_with("1 + 2", &mut interpreter).ok().unwrap();
+    assert_eq!(i64::try_from(sum).ok(), Some(3));
+
+    let handle = interpreter.interrupt_handle();
+
+    let stopper = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+
+        handle.interrupt();
+    });
+
+    let diagnostics = lox::run_with("while (true) {}", &mut interpreter);
+
+    stopper.join().unwrap();
+
+    assert_eq!(diagnostics.errors().len(), 1);
+    assert!(diagnostics.errors()[0].to_string().contains("Interrupted."));
+}
+
+/// The first error `src` reports when run with the capabilities `deny`
+/// leaves.
+fn denied_error(deny: fn(&mut lox::Capabilities), src: &str) -> String {
+    let mut options = lox::Options::default();
+
+    deny(&mut options.capabilities);
+
+    let (_, diagnostics) = lox::run_source_captured(src, &options);
+
+    diagnostics.errors()[0].to_string()
+}
+
+#[test]
+fn denying_process_leaves_out_exit_and_args() {
+    let deny = |capabilities: &mut lox::Capabilities| capabilities.allow_process = false;
+
+    assert!(denied_error(deny, "exit(0);").contains("Undefined variable 'exit'."));
+    assert!(denied_error(deny, "arg(0);").contains("Undefined variable 'arg'."));
+}
+
+#[test]
+fn denying_fs_leaves_out_file_natives_and_imports() {
+    let deny = |capabilities: &mut lox::Capabilities| capabilities.allow_fs = false;
+
+    assert!(denied_error(deny, "read_file(\"x\");").contains("Undefined variable 'read_file'."));
+    assert!(denied_error(deny, "import \"/etc/hostname\";")
+        .contains("Imports need file system access."));
+}
+
+#[test]
+fn denying_time_leaves_out_the_clock() {
+    let deny = |capabilities: &mut lox::Capabilities| capabilities.allow_time = false;
+
+    assert!(denied_error(deny, "clock();").contains("Undefined variable 'clock'."));
+    assert!(denied_error(deny, "sleep(1);").contains("Undefined variable 'sleep'."));
+}
+
+#[test]
+fn denying_stdin_leaves_out_readline() {
+    let deny = |capabilities: &mut lox::Capabilities| capabilities.allow_stdin = false;
+
+    assert!(denied_error(deny, "readline();").contains("Undefined variable 'readline'."));
+}
+
+/// Keeps catching the budget error and looping again.
+const STUBBORN_LOOP: &str = "
+var n = 0;
+while (true) { try { while (true) { n = n + 1; } } catch (e) { } }
+";
+
+#[test]
+fn max_steps_stops_a_script_that_catches_the_error() {
+    let mut options = lox::Options::default();
+
+    options.limits.opt_max_steps = Some(10_000);
+
+    let (_, diagnostics) = lox::run_source_captured(STUBBORN_LOOP, &options);
+
+    assert!(diagnostics.errors()[0]
+        .to_string()
+        .contains("Execution budget exceeded."));
+}
+
+#[test]
+fn max_time_stops_a_script_that_catches_the_error() {
+    let mut options = lox::Options::default();
+
+    options.limits.opt_max_time = Some(std::time::Duration::from_millis(50));
+
+    let (_, diagnostics) = lox::run_source_captured(STUBBORN_LOOP, &options);
+
+    assert!(diagnostics.errors()[0]
+        .to_string()
+        .contains("Execution budget exceeded."));
+}
+
+#[test]
+fn unused_globals_are_reported_alongside_errors() {
+    let diagnostics = lox::check(
+        "fun helper() {}\nprint undefined +;",
+        &lox::Options::default(),
+    );
+
+    assert_eq!(diagnostics.errors().len(), 1);
+
+    let warnings: Vec<_> = diagnostics
+        .warnings()
+        .iter()
+        .map(|warning| warning.message.as_str())
+        .collect();
+
+    assert_eq!(warnings, vec!["Function 'helper' is never used."]);
+}
@@ -0,0 +1,118 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use rlox::lsp::Server;
+use serde_json::{json, Value};
+
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn frame(messages: &[Value]) -> Vec<u8> {
+    let mut input = Vec::new();
+
+    for message in messages {
+        let body = message.to_string();
+
+        write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    }
+
+    input
+}
+
+/// Runs a server over `messages` and returns the result of the request with
+/// id 1.
+fn result(messages: &[Value]) -> Value {
+    let capture = Capture::default();
+
+    let mut server = Server::new(false, Box::new(capture.clone()));
+
+    server.run(&mut &frame(messages)[..]).unwrap();
+
+    let output = String::from_utf8(capture.0.borrow().clone()).unwrap();
+
+    output
+        .split("Content-Length: ")
+        .filter_map(|message| message.split_once("\r\n\r\n"))
+        .map(|(_, body)| serde_json::from_str::<Value>(body).unwrap())
+        .find(|message| message["id"] == 1)
+        .unwrap()["result"]
+        .clone()
+}
+
+fn open(text: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": { "textDocument": { "uri": "file:///a.lox", "text": text } },
+    })
+}
+
+fn definition(line: usize, character: usize) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "textDocument/definition",
+        "params": {
+            "textDocument": { "uri": "file:///a.lox" },
+            "position": { "line": line, "character": character },
+        },
+    })
+}
+
+#[test]
+fn positions_count_utf16_code_units() {
+    // The emoji is one character but two UTF-16 code units.
+    let text = "var s = \"😀\"; var x = 1;\nprint \"😀\" + x;\n";
+
+    let range = &result(&[open(text), definition(1, 13)])["range"];
+
+    assert_eq!(range["start"], json!({ "line": 0, "character": 18 }));
+    assert_eq!(range["end"], json!({ "line": 0, "character": 19 }));
+}
+
+#[test]
+fn edits_count_utf16_code_units() {
+    let change = json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": "file:///a.lox" },
+            "contentChanges": [{
+                "range": {
+                    "start": { "line": 0, "character": 18 },
+                    "end": { "line": 0, "character": 19 },
+                },
+                "text": "y",
+            }],
+        },
+    });
+
+    let text = "var s = \"😀\"; var x = 1;\nprint y;\n";
+
+    let range = &result(&[open(text), change, definition(1, 6)])["range"];
+
+    assert_eq!(range["start"], json!({ "line": 0, "character": 18 }));
+}
+
+#[test]
+fn columns_restart_after_a_string_spanning_lines() {
+    let text = "var s = \"a\nb\"; var x = 1;\nprint x;\n";
+
+    let range = &result(&[open(text), definition(2, 6)])["range"];
+
+    assert_eq!(range["start"], json!({ "line": 1, "character": 8 }));
+    assert_eq!(range["end"], json!({ "line": 1, "character": 9 }));
+}
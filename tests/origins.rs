@@ -0,0 +1,43 @@
+use rlox::lox::{self, Options};
+
+fn run(src: &str) -> String {
+    let options = Options {
+        track_origins: true,
+        ..Options::default()
+    };
+
+    let (output, diagnostics) = lox::run_source_captured(src, &options);
+
+    assert!(diagnostics.errors().is_empty());
+
+    output
+}
+
+#[test]
+fn literals_report_where_they_were_written() {
+    let output = run("var name = \"config\";\nvar copy = name;\nprint origin(copy);");
+
+    assert!(output.ends_with(":1\n"), "{}", output);
+}
+
+#[test]
+fn computed_strings_equal_to_a_literal_report_nil() {
+    let output = run("
+var ab = \"ab\";
+print origin(\"a\" + \"b\");
+var a = \"a\";
+print origin(a + \"b\");
+print origin(\"${a}b\");
+print origin(str(ab));
+");
+
+    assert_eq!(output, "nil\nnil\nnil\nnil\n");
+}
+
+#[test]
+fn numbers_report_nil() {
+    assert_eq!(
+        run("var two = 2;\nprint origin(two);\nprint origin(1 + 1);"),
+        "nil\nnil\n"
+    );
+}